@@ -1,41 +1,258 @@
-use crate::hid_manager::{HidManager, DeviceDescriptor};
-use crate::protocol::{DeviceInfo, KeymapEntry, EncoderEntry, I2CDeviceInfo, SlaveKeymapEntry, SlaveEncoderEntry, BoardLayoutInfo, LayerState, LayoutCellType, LayoutCell, SliderConfig, MagneticSwitchConfig};
+use crate::config_file::ConfigFile;
+use crate::device_store::{DeviceSettings, KnownDevice};
+use crate::error::ConfigError;
+use crate::hid_manager::{DeviceDescriptor, HidManager, HotplugAction, SliderStreamMode, SLIDER_STREAM_DEFAULT_INTERVAL_MS};
+use crate::hotplug::HotplugEvent;
+use crate::history::{HistoryStep, HistorySummary};
+use crate::jobs::{JobContext, JobEvent, JobId, Jobs};
+use crate::model::{ModelEntry, ModelKey, ModelSnapshot};
+use crate::protocol::{ConfigCommand, DeviceInfo, KeymapEntry, EncoderEntry, I2CDeviceInfo, SlaveKeymapEntry, SlaveEncoderEntry, BoardLayoutInfo, LayerState, LayoutCellType, SliderConfig, MagneticSwitchConfig};
+use crate::registry::{ConnectedDeviceInfo, DeviceHandle, DeviceRegistry};
+use crate::retry::RetryPolicy;
+use crate::stats::LinkStatsSnapshot;
+use std::path::Path;
 use std::sync::Arc;
 use tauri::{AppHandle, State, Emitter};
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, mpsc};
 use serde::Serialize;
+use tracing::{debug, info, warn};
 
-pub type AppState = Arc<RwLock<HidManager>>;
+pub type AppState = Arc<DeviceRegistry>;
+
+/// How often `og:link-health` is emitted per connected device
+const LINK_HEALTH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+// Device events
+
+/// Something a device sent, tagged with the board it came from
+#[derive(Clone, Serialize)]
+struct Tagged<T> {
+    device_id: Option<String>,
+    #[serde(flatten)]
+    inner: T,
+}
+
+/// Re-emit everything a device pushes on its own as Tauri events, until its
+/// connection is dropped from the registry
+pub fn forward_device_events(app: AppHandle, device: DeviceHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut events = device.read().await.subscribe_events();
+        let device = Arc::downgrade(&device);
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    let Some(device) = device.upgrade() else { break };
+                    let device_id = device.read().await.device_id();
+                    let _ = app.emit(event.tauri_event(), Tagged { device_id, inner: event });
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("Dropped {} device events, frontend is falling behind", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// Emit `og:protocol` for every packet while the protocol console is enabled on
+/// the device, until its connection is dropped from the registry
+pub fn forward_protocol_console(app: AppHandle, device: DeviceHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut packets = device.read().await.subscribe_console();
+        let device = Arc::downgrade(&device);
+        loop {
+            match packets.recv().await {
+                Ok(entry) => {
+                    let Some(device) = device.upgrade() else { break };
+                    let device_id = device.read().await.device_id();
+                    let _ = app.emit("og:protocol", Tagged { device_id, inner: entry });
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("Protocol console skipped {} packets", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// Emit `og:link-health` every `LINK_HEALTH_INTERVAL` while the device is
+/// connected, until its connection is dropped from the registry
+pub fn forward_link_health(app: AppHandle, device: DeviceHandle) {
+    let device = Arc::downgrade(&device);
+    tauri::async_runtime::spawn(async move {
+        let mut ticker = tokio::time::interval(LINK_HEALTH_INTERVAL);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let Some(device) = device.upgrade() else { break };
+            let manager = device.read().await;
+            if manager.is_connected() {
+                let _ = app.emit("og:link-health", manager.link_health(LINK_HEALTH_INTERVAL));
+            }
+        }
+    });
+}
+
+/// Payload of `og:connected`/`og:disconnected`
+#[derive(Clone, Serialize)]
+pub struct ConnectionChange {
+    pub device_id: Option<String>,
+}
+
+fn emit_connection_change(app: &AppHandle, event: &str, device_id: Option<String>) {
+    let _ = app.emit(event, ConnectionChange { device_id });
+}
+
+/// Emit `og:device-added`/`og:device-removed`, and `og:disconnected`/`og:connected`
+/// when that drops a connection or auto-reconnects the last used device
+pub fn forward_hotplug_events(app: AppHandle, state: AppState, mut events: mpsc::UnboundedReceiver<HotplugEvent>) {
+    tauri::async_runtime::spawn(async move {
+        while let Some(event) = events.recv().await {
+            let _ = app.emit(event.tauri_event(), event.device().clone());
+
+            for (device_id, action) in state.handle_hotplug(&event).await {
+                match action {
+                    HotplugAction::Disconnected => emit_connection_change(&app, "og:disconnected", Some(device_id)),
+                    HotplugAction::Reconnected => emit_connection_change(&app, "og:connected", Some(device_id)),
+                    HotplugAction::None => {}
+                }
+            }
+        }
+    });
+}
+
+/// Emit `og:progress` and `og:job-finished` for every background job
+pub fn forward_job_events(app: AppHandle, mut events: broadcast::Receiver<JobEvent>) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    let _ = app.emit(event.tauri_event(), event);
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("Dropped {} job events, frontend is falling behind", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+}
 
 // Device management commands
 
 #[tauri::command]
-pub async fn scan_devices(state: State<'_, AppState>) -> Result<Vec<DeviceDescriptor>, String> {
-    let manager = state.read().await;
+pub async fn scan_devices(state: State<'_, AppState>) -> Result<Vec<DeviceDescriptor>, ConfigError> {
+    let manager = state.read(None).await?;
     manager.scan_devices().await
 }
 
+/// Open a device and return its id. The first board becomes the primary
+/// device; connecting another one keeps both open.
 #[tauri::command]
-pub async fn connect_device(path: String, state: State<'_, AppState>, app: AppHandle) -> Result<(), String> {
-    let manager = state.read().await;
-    let res = manager.connect(&path);
-    if res.is_ok() {
-        let _ = app.emit("og:connected", ());
+pub async fn connect_device(path: String, state: State<'_, AppState>, app: AppHandle) -> Result<String, ConfigError> {
+    let connected = state.connect(&path).await?;
+    if let Err(e) = connected.handle.read().await.handshake().await {
+        state.disconnect(connected.additional.then_some(connected.device_id.as_str())).await?;
+        return Err(e);
+    }
+    state.remember_connection(&connected.device_id, !connected.additional);
+    if connected.additional {
+        forward_device_events(app.clone(), connected.handle.clone());
+        forward_protocol_console(app.clone(), connected.handle.clone());
+        forward_link_health(app.clone(), connected.handle.clone());
     }
-    res
+    emit_connection_change(&app, "og:connected", Some(connected.device_id.clone()));
+    Ok(connected.device_id)
 }
 
 #[tauri::command]
-pub async fn disconnect_device(state: State<'_, AppState>, app: AppHandle) -> Result<(), String> {
-    let manager = state.read().await;
-    manager.disconnect();
-    let _ = app.emit("og:disconnected", ());
+pub async fn disconnect_device(device_id: Option<String>, state: State<'_, AppState>, app: AppHandle) -> Result<(), ConfigError> {
+    let id = match &device_id {
+        Some(id) => Some(id.clone()),
+        None => state.read(None).await?.device_id(),
+    };
+    state.disconnect(device_id.as_deref()).await?;
+    emit_connection_change(&app, "og:disconnected", id);
     Ok(())
 }
 
+/// Every open device, primary first
+#[tauri::command]
+pub async fn list_connected_devices(state: State<'_, AppState>) -> Result<Vec<ConnectedDeviceInfo>, ConfigError> {
+    Ok(state.devices().await)
+}
+
+// Per-device settings. These take the id of any remembered device, connected
+// or not; without one they apply to the primary device.
+
+#[tauri::command]
+pub async fn list_known_devices(state: State<'_, AppState>) -> Result<Vec<KnownDevice>, ConfigError> {
+    Ok(state.store().known_devices())
+}
+
 #[tauri::command]
-pub async fn is_device_connected(state: State<'_, AppState>) -> Result<bool, String> {
-    let manager = state.read().await;
+pub async fn get_device_settings(device_id: Option<String>, state: State<'_, AppState>) -> Result<DeviceSettings, ConfigError> {
+    let device_id = state.resolve_id(device_id.as_deref()).await?;
+    Ok(state.store().settings(&device_id))
+}
+
+#[tauri::command]
+pub async fn set_device_nickname(
+    nickname: Option<String>,
+    device_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<DeviceSettings, ConfigError> {
+    let device_id = state.resolve_id(device_id.as_deref()).await?;
+    let nickname = nickname.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+    state.store().update_settings(&device_id, |s| s.nickname = nickname)
+}
+
+#[tauri::command]
+pub async fn set_preferred_profile(
+    profile: Option<String>,
+    device_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<DeviceSettings, ConfigError> {
+    let device_id = state.resolve_id(device_id.as_deref()).await?;
+    state.store().update_settings(&device_id, |s| s.preferred_profile = profile)
+}
+
+/// Drop everything remembered about a device; returns whether there was anything
+#[tauri::command]
+pub async fn forget_device(device_id: String, state: State<'_, AppState>) -> Result<bool, ConfigError> {
+    state.store().forget(&device_id)
+}
+
+#[tauri::command]
+pub async fn start_trace_recording(path: String, device_id: Option<String>, state: State<'_, AppState>) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.start_recording(std::path::Path::new(&path))
+}
+
+#[tauri::command]
+pub async fn stop_trace_recording(device_id: Option<String>, state: State<'_, AppState>) -> Result<bool, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    Ok(manager.stop_recording())
+}
+
+/// Replay a recorded trace as a device, the primary one if none is connected;
+/// returns its device id (`replay:<path>`).
+#[tauri::command]
+pub async fn connect_replay(path: String, state: State<'_, AppState>, app: AppHandle) -> Result<String, ConfigError> {
+    let connected = state.connect_replay(Path::new(&path)).await?;
+    if connected.additional {
+        forward_device_events(app.clone(), connected.handle.clone());
+        forward_protocol_console(app.clone(), connected.handle.clone());
+        forward_link_health(app.clone(), connected.handle.clone());
+    }
+    emit_connection_change(&app, "og:connected", Some(connected.device_id.clone()));
+    Ok(connected.device_id)
+}
+
+#[tauri::command]
+pub async fn is_device_connected(device_id: Option<String>, state: State<'_, AppState>) -> Result<bool, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     Ok(manager.is_connected())
 }
 
@@ -43,12 +260,12 @@ pub async fn is_device_connected(state: State<'_, AppState>) -> Result<bool, Str
 pub struct ConnectionStatus {
     pub connected: bool,
     pub device_info: Option<DeviceInfo>,
-    pub error: Option<String>,
+    pub error: Option<ConfigError>,
 }
 
 #[tauri::command]
-pub async fn get_connection_status(state: State<'_, AppState>) -> Result<ConnectionStatus, String> {
-    let manager = state.read().await;
+pub async fn get_connection_status(device_id: Option<String>, state: State<'_, AppState>) -> Result<ConnectionStatus, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     let connected = manager.is_connected();
     
     let mut status = ConnectionStatus {
@@ -73,162 +290,248 @@ pub async fn get_connection_status(state: State<'_, AppState>) -> Result<Connect
 }
 
 #[tauri::command]
-pub async fn ping_device(state: State<'_, AppState>) -> Result<bool, String> {
-    let manager = state.read().await;
+pub async fn get_auto_reconnect(state: State<'_, AppState>) -> Result<bool, ConfigError> {
+    let manager = state.read(None).await?;
+    Ok(manager.auto_reconnect())
+}
+
+/// Whether plugging the last used device back in reconnects it without the UI asking
+#[tauri::command]
+pub async fn set_auto_reconnect(enabled: bool, state: State<'_, AppState>) -> Result<(), ConfigError> {
+    let manager = state.read(None).await?;
+    manager.set_auto_reconnect(enabled);
+    Ok(())
+}
+
+/// Capabilities negotiated on connect, in a shape the UI can check directly
+#[derive(Serialize)]
+pub struct CapabilitiesView {
+    pub protocol_version: u8,
+    pub commands: Vec<ConfigCommand>,
+    pub cell_types: Vec<LayoutCellType>,
+}
+
+#[tauri::command]
+pub async fn get_capabilities(device_id: Option<String>, state: State<'_, AppState>) -> Result<Option<CapabilitiesView>, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    Ok(manager.capabilities().map(|caps| CapabilitiesView {
+        protocol_version: caps.protocol_version,
+        commands: caps.commands(),
+        cell_types: caps.cell_types(),
+    }))
+}
+
+#[tauri::command]
+pub async fn ping_device(device_id: Option<String>, state: State<'_, AppState>) -> Result<bool, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     Ok(manager.is_connected())
 }
 
 #[tauri::command]
-pub async fn get_board_layout(state: State<'_, AppState>) -> Result<BoardLayoutInfo, String> {
-    let manager = state.read().await;
-    manager.get_board_layout().await
+pub async fn get_board_layout(refresh: Option<bool>, device_id: Option<String>, state: State<'_, AppState>) -> Result<BoardLayoutInfo, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    let cached = if refresh.unwrap_or(false) { None } else { manager.model().layout() };
+    match cached {
+        Some(layout) => Ok(layout),
+        None => manager.get_board_layout().await,
+    }
 }
 
 #[tauri::command]
-pub async fn get_layout_cell_type(row: u8, col: u8, state: State<'_, AppState>) -> Result<u8, String> {
-    let manager = state.read().await;
+pub async fn get_layout_cell_type(row: u8, col: u8, device_id: Option<String>, state: State<'_, AppState>) -> Result<u8, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     manager.get_layout_cell_type(row, col).await
 }
 
 #[tauri::command]
-pub async fn get_layout_cell_component_id(row: u8, col: u8, state: State<'_, AppState>) -> Result<u8, String> {
-    let manager = state.read().await;
+pub async fn get_layout_cell_component_id(row: u8, col: u8, device_id: Option<String>, state: State<'_, AppState>) -> Result<u8, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     manager.get_layout_cell_component_id(row, col).await
 }
 
 #[tauri::command]
-pub async fn get_slider_value(slider_id: u8, state: State<'_, AppState>) -> Result<u8, String> {
-    let manager = state.read().await;
+pub async fn get_slider_value(slider_id: u8, device_id: Option<String>, state: State<'_, AppState>) -> Result<u8, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     manager.get_slider_value(slider_id).await
 }
 
+/// Start `og:slider-values` events; `slider_ids` are only used when the firmware has to be polled
 #[tauri::command]
-pub async fn get_slider_config(layer: u8, slider_id: u8, state: State<'_, AppState>) -> Result<SliderConfig, String> {
-    let manager = state.read().await;
-    manager.get_slider_config(layer, slider_id).await
+pub async fn subscribe_sliders(
+    slider_ids: Vec<u8>,
+    interval_ms: Option<u16>,
+    device_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<SliderStreamMode, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager
+        .subscribe_sliders(&slider_ids, interval_ms.unwrap_or(SLIDER_STREAM_DEFAULT_INTERVAL_MS))
+        .await
 }
 
 #[tauri::command]
-pub async fn set_slider_config(config: SliderConfig, state: State<'_, AppState>) -> Result<(), String> {
-    let manager = state.read().await;
-    manager.set_slider_config(&config).await
+pub async fn unsubscribe_sliders(device_id: Option<String>, state: State<'_, AppState>) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.unsubscribe_sliders().await
 }
 
 #[tauri::command]
-pub async fn get_magnetic_switch_value(switch_id: u8, state: State<'_, AppState>) -> Result<u8, String> {
-    let manager = state.read().await;
+pub async fn get_slider_config(
+    layer: u8,
+    slider_id: u8,
+    refresh: Option<bool>,
+    device_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<SliderConfig, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    match manager.cached(ModelKey::Slider { layer, slider_id }, refresh.unwrap_or(false)) {
+        Some(ModelEntry::Slider(config)) => Ok(config),
+        _ => manager.get_slider_config(layer, slider_id).await,
+    }
+}
+
+#[tauri::command]
+pub async fn set_slider_config(config: SliderConfig, device_id: Option<String>, state: State<'_, AppState>) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.apply_edit("set_slider_config", &[ModelEntry::Slider(config)]).await
+}
+
+#[tauri::command]
+pub async fn get_magnetic_switch_value(switch_id: u8, device_id: Option<String>, state: State<'_, AppState>) -> Result<u8, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     manager.get_magnetic_switch_value(switch_id).await
 }
 
 #[tauri::command]
-pub async fn get_magnetic_switch_config(layer: u8, switch_id: u8, state: State<'_, AppState>) -> Result<MagneticSwitchConfig, String> {
-    let manager = state.read().await;
-    manager.get_magnetic_switch_config(layer, switch_id).await
+pub async fn get_magnetic_switch_config(
+    layer: u8,
+    switch_id: u8,
+    refresh: Option<bool>,
+    device_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<MagneticSwitchConfig, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    match manager.cached(ModelKey::MagneticSwitch { layer, switch_id }, refresh.unwrap_or(false)) {
+        Some(ModelEntry::MagneticSwitch(config)) => Ok(config),
+        _ => manager.get_magnetic_switch_config(layer, switch_id).await,
+    }
 }
 
 #[tauri::command]
-pub async fn set_magnetic_switch_config(config: MagneticSwitchConfig, state: State<'_, AppState>) -> Result<(), String> {
-    let manager = state.read().await;
-    manager.set_magnetic_switch_config(&config).await
+pub async fn set_magnetic_switch_config(config: MagneticSwitchConfig, device_id: Option<String>, state: State<'_, AppState>) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.apply_edit("set_magnetic_switch_config", &[ModelEntry::MagneticSwitch(config)]).await
 }
 
 #[tauri::command]
-pub async fn calibrate_magnetic_switch(switch_id: u8, step: u8, state: State<'_, AppState>) -> Result<(), String> {
-    let manager = state.read().await;
+pub async fn calibrate_magnetic_switch(switch_id: u8, step: u8, device_id: Option<String>, state: State<'_, AppState>) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     manager.calibrate_magnetic_switch(switch_id, step).await
 }
 
 #[tauri::command]
-pub async fn set_magnetic_switch_sensitivity(switch_id: u8, sensitivity: u8, state: State<'_, AppState>) -> Result<(), String> {
-    let manager = state.read().await;
+pub async fn set_magnetic_switch_sensitivity(switch_id: u8, sensitivity: u8, device_id: Option<String>, state: State<'_, AppState>) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     manager.set_magnetic_switch_sensitivity(switch_id, sensitivity).await
 }
 
 #[tauri::command]
-pub async fn check_device_status_and_reconnect(state: State<'_, AppState>) -> Result<bool, String> {
-    let manager = state.read().await;
+pub async fn check_device_status_and_reconnect(device_id: Option<String>, state: State<'_, AppState>) -> Result<bool, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     let connected = manager.is_connected();
-    println!("Device status check: connected={}", connected);
+    debug!("Device status check: connected={}", connected);
     Ok(connected)
 }
 
 // Auto-connect command exposed to frontend
 #[tauri::command]
-pub async fn auto_connect(state: State<'_, AppState>, app: AppHandle) -> Result<bool, String> {
-    let manager = state.read().await;
+pub async fn auto_connect(state: State<'_, AppState>, app: AppHandle) -> Result<bool, ConfigError> {
+    let manager = state.read(None).await?;
     if manager.is_connected() {
-        println!("auto_connect: already connected, skipping");
+        debug!("auto_connect: already connected, skipping");
         return Ok(true);
     }
     drop(manager);
     
-    println!("auto_connect: attempting to connect...");
-    let manager_w = state.write().await;
-    let ok = manager_w.auto_connect();
+    info!("auto_connect: attempting to connect...");
+    let manager_w = state.write(None).await?;
+    let ok = manager_w.auto_connect(state.store().last_used().as_deref());
     drop(manager_w);
 
     if let Ok(true) = ok {
-        println!("auto_connect: connection successful, verifying device...");
+        info!("auto_connect: connection successful, verifying device...");
         // Verify device responds to GetInfo before emitting event
         for attempt in 0..3 {
-            println!("auto_connect: verification attempt {} of 3", attempt + 1);
-            let mgr = state.read().await;
-            let res = mgr.get_device_info().await;
+            debug!("auto_connect: verification attempt {} of 3", attempt + 1);
+            let mgr = state.read(None).await?;
+            let res = mgr.handshake().await;
+            let device_id = mgr.device_id();
             drop(mgr);
             match res {
                 Ok(info) => {
-                    println!("auto_connect: device verification successful, device_name={}", info.device_name);
-                    println!("auto_connect: emitting og:connected event");
-                    let emit_result = app.emit("og:connected", ());
-                    println!("auto_connect: og:connected emit result: {:?}", emit_result);
+                    info!("auto_connect: device verification successful, device_name={}", info.device_name);
+                    if let Some(device_id) = &device_id {
+                        state.remember_connection(device_id, true);
+                    }
+                    debug!("auto_connect: emitting og:connected event");
+                    let emit_result = app.emit("og:connected", ConnectionChange { device_id });
+                    debug!("auto_connect: og:connected emit result: {:?}", emit_result);
                     return Ok(true);
                 }
+                Err(e @ ConfigError::IncompatibleProtocol { .. }) => {
+                    warn!("auto_connect: {}", e);
+                    let mgrw = state.write(None).await?;
+                    mgrw.disconnect();
+                    return Err(e);
+                }
                 Err(e) if attempt < 2 => {
-                    println!("auto_connect: device verification failed (attempt {}): {}", attempt + 1, e);
+                    warn!("auto_connect: device verification failed (attempt {}): {}", attempt + 1, e);
                     tokio::time::sleep(std::time::Duration::from_millis(200)).await;
                     continue;
                 }
                 Err(e) => {
-                    println!("auto_connect: all device verification attempts failed: {}", e);
+                    warn!("auto_connect: all device verification attempts failed: {}", e);
                     // Disconnect to allow next poll to retry cleanly
-                    let mgrw = state.write().await;
+                    let mgrw = state.write(None).await?;
                     mgrw.disconnect();
                     return Ok(false);
                 }
             }
         }
     } else {
-        println!("auto_connect: initial connection failed: {:?}", ok);
+        warn!("auto_connect: initial connection failed: {:?}", ok);
     }
     Ok(false)
 }
 
-// Simple connect command that connects and loads all data
+/// Connect and negotiate with the device, then load all data in the background;
+/// the job's result is a `FullState`, with progress per keymap and encoder layer.
 #[tauri::command]
-pub async fn simple_connect(state: State<'_, AppState>) -> Result<FullState, String> {
+pub async fn simple_connect(state: State<'_, AppState>, jobs: State<'_, Jobs>) -> Result<JobId, ConfigError> {
     // Step 1: Connect to device
     {
-        let manager_w = state.write().await;
+        let manager_w = state.write(None).await?;
         
         // First disconnect if already connected
         if manager_w.is_connected() {
             manager_w.disconnect();
-            println!("simple_connect: disconnected existing connection");
+            info!("simple_connect: disconnected existing connection");
             // Small delay after disconnect
             tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
         }
         
         // Try to connect
-        println!("simple_connect: attempting to connect...");
-        match manager_w.auto_connect() {
+        info!("simple_connect: attempting to connect...");
+        match manager_w.auto_connect(state.store().last_used().as_deref()) {
             Ok(true) => {
-                println!("simple_connect: connection successful");
+                info!("simple_connect: connection successful");
             }
             Ok(false) => {
-                return Err("No device found".to_string());
+                return Err(ConfigError::NotConnected);
             }
             Err(e) => {
-                return Err(format!("Connection failed: {}", e));
+                warn!("simple_connect: connection failed: {}", e);
+                return Err(e);
             }
         }
     } // Release write lock
@@ -236,48 +539,69 @@ pub async fn simple_connect(state: State<'_, AppState>) -> Result<FullState, Str
     // Small delay to ensure connection is stable
     tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
     
-    // Step 2: Get device info
-    println!("simple_connect: getting device info...");
+    // Step 2: Handshake (protocol version + capabilities), which also yields device info
+    info!("simple_connect: negotiating with device...");
     let device_info = {
-        let manager = state.read().await;
-        manager.get_device_info().await?
+        let manager = state.read(None).await?;
+        match manager.handshake().await {
+            Ok(info) => {
+                if let Some(device_id) = manager.device_id() {
+                    state.remember_connection(&device_id, true);
+                }
+                info
+            }
+            Err(e) => {
+                drop(manager);
+                state.write(None).await?.disconnect();
+                return Err(e);
+            }
+        }
     };
-    println!("simple_connect: device connected: {} ({}x{} matrix, {} encoders)", 
+    info!("simple_connect: device connected: {} ({}x{} matrix, {} encoders)", 
              device_info.device_name, device_info.matrix_rows, device_info.matrix_cols, device_info.encoder_count);
     
     // Step 3: Fetch layout metadata (optional but preferred)
-    println!("simple_connect: fetching board layout metadata...");
+    info!("simple_connect: fetching board layout metadata...");
     let layout = {
-        let manager = state.read().await;
+        let manager = state.read(None).await?;
         match manager.get_board_layout().await {
             Ok(info) => Some(info),
             Err(e) => {
-                println!("simple_connect: warning - failed to fetch board layout: {}", e);
+                warn!("simple_connect: failed to fetch board layout: {}", e);
                 None
             }
         }
     };
 
-    let full_state = build_full_state(&state, device_info, layout).await?;
-    println!("simple_connect: all data loaded successfully");
-    Ok(full_state)
+    let state = state.inner().clone();
+    Ok(jobs.spawn("simple_connect", move |job| async move {
+        let full_state = build_full_state(&state, None, device_info, layout, true, &job).await?;
+        info!("simple_connect: all data loaded successfully");
+        Ok(full_state)
+    }))
 }
 
 // Simple disconnect command
 #[tauri::command]
-pub async fn simple_disconnect(state: State<'_, AppState>) -> Result<(), String> {
-    let manager_w = state.write().await;
+pub async fn simple_disconnect(device_id: Option<String>, state: State<'_, AppState>) -> Result<(), ConfigError> {
+    let manager_w = state.write(device_id.as_deref()).await?;
     manager_w.disconnect();
-    println!("simple_disconnect: device disconnected");
+    info!("simple_disconnect: device disconnected");
     Ok(())
 }
 
+/// Read everything the editor needs, taking keymap and encoder layers from the
+/// cached model unless `refresh` is set. Progress counts keymap and encoder
+/// layers; cancelling stops before the next layer.
 async fn build_full_state(
-    state: &State<'_, AppState>,
+    state: &DeviceRegistry,
+    device_id: Option<&str>,
     device_info: DeviceInfo,
     layout: Option<BoardLayoutInfo>,
-) -> Result<FullState, String> {
-    println!(
+    refresh: bool,
+    job: &JobContext,
+) -> Result<FullState, ConfigError> {
+    info!(
         "build_full_state: snapshotting '{}' (layers={}, rows={} cols={} encoders={})",
         device_info.device_name,
         device_info.layer_count,
@@ -286,118 +610,159 @@ async fn build_full_state(
         device_info.encoder_count
     );
 
+    if let Some(layout) = &layout {
+        state.remember_layout(device_id, layout).await;
+    }
+
     let layer_state = {
-        let manager = state.read().await;
+        let manager = state.read(device_id).await?;
         match manager.get_layer_state().await {
             Ok(state) => Some(state),
             Err(e) => {
-                println!("build_full_state: layer state unavailable ({})", e);
+                warn!("build_full_state: layer state unavailable ({})", e);
                 None
             }
         }
     };
 
+    let total_steps = device_info.layer_count.max(1) as u32 * 2;
+    let keymap = {
+        let manager = state.read(device_id).await?;
+        read_keymap_layers(&manager, &device_info, refresh, job, 0, total_steps).await?
+    };
+    let encoders = {
+        let manager = state.read(device_id).await?;
+        read_encoder_layers(&manager, &device_info, refresh, job, total_steps / 2, total_steps).await?
+    };
+
+    Ok(FullState {
+        device_info,
+        keymap,
+        encoders,
+        layout,
+        layer_state,
+    })
+}
+
+/// Device info from the cache, unless `refresh` asks the device again
+async fn device_info(manager: &HidManager, refresh: bool) -> Result<DeviceInfo, ConfigError> {
+    if refresh {
+        manager.get_device_info().await
+    } else {
+        manager.cached_device_info().await
+    }
+}
+
+/// Every keymap layer, from the cached model unless `refresh` is set. A layer
+/// that can't be read shows up as zeros. Progress counts up from `first_step`.
+async fn read_keymap_layers(
+    manager: &HidManager,
+    device_info: &DeviceInfo,
+    refresh: bool,
+    job: &JobContext,
+    first_step: u32,
+    total_steps: u32,
+) -> Result<Vec<Vec<Vec<KeymapEntry>>>, ConfigError> {
     let layer_count = device_info.layer_count.max(1);
     let matrix_rows = device_info.matrix_rows;
     let matrix_cols = device_info.matrix_cols;
-    let encoder_count = device_info.encoder_count;
-
-    println!(
-        "build_full_state: loading keymap (layers={}, rows={}, cols={})",
+    info!(
+        "read_keymap_layers: loading keymap (layers={}, rows={}, cols={})",
         layer_count,
         matrix_rows,
         matrix_cols
     );
-    let keymap = {
-        let manager = state.read().await;
-        let mut all_layers = Vec::with_capacity(layer_count as usize);
-        let mut total_entries = 0usize;
-        let mut failed_entries = 0usize;
-
-        for layer_idx in 0..layer_count {
-            let mut layer_rows = Vec::with_capacity(matrix_rows as usize);
-            for row_idx in 0..matrix_rows {
-                let mut row_entries = Vec::with_capacity(matrix_cols as usize);
-                for col_idx in 0..matrix_cols {
-                    total_entries += 1;
-                    match manager.get_keymap_entry(layer_idx, row_idx, col_idx).await {
-                        Ok(entry) => row_entries.push(entry),
-                        Err(e) => {
-                            failed_entries += 1;
-                            println!(
-                                "build_full_state: keymap read failed at L{} R{} C{} -> {}",
-                                layer_idx, row_idx, col_idx, e
-                            );
-                            row_entries.push(KeymapEntry {
-                                layer: layer_idx,
-                                row: row_idx,
-                                col: col_idx,
-                                keycode: 0,
-                            });
-                        }
-                    }
-                }
-                layer_rows.push(row_entries);
-            }
+    let mut all_layers = Vec::with_capacity(layer_count as usize);
+    let mut failed_layers = 0usize;
+
+    for layer_idx in 0..layer_count {
+        job.check()?;
+        let cached = if refresh { None } else { manager.model().keymap_layer(layer_idx, matrix_rows, matrix_cols) };
+        if let Some(layer_rows) = cached {
             all_layers.push(layer_rows);
+            job.progress(first_step + layer_idx as u32 + 1, total_steps, Some(layer_idx), None);
+            continue;
+        }
+        match manager.get_keymap_layer(layer_idx, matrix_rows, matrix_cols).await {
+            Ok(layer_rows) => all_layers.push(layer_rows),
+            Err(e) => {
+                failed_layers += 1;
+                warn!("read_keymap_layers: keymap read failed for L{} -> {}", layer_idx, e);
+                all_layers.push(
+                    (0..matrix_rows)
+                        .map(|row| {
+                            (0..matrix_cols)
+                                .map(|col| KeymapEntry { layer: layer_idx, row, col, keycode: 0 })
+                                .collect()
+                        })
+                        .collect(),
+                );
+            }
         }
+        job.progress(first_step + layer_idx as u32 + 1, total_steps, Some(layer_idx), None);
+    }
 
-        println!(
-            "build_full_state: keymap complete ({} entries, {} failures)",
-            total_entries,
-            failed_entries
-        );
-        all_layers
-    };
+    info!(
+        "read_keymap_layers: keymap complete ({} layers, {} failures)",
+        layer_count,
+        failed_layers
+    );
+    Ok(all_layers)
+}
 
-    println!(
-        "build_full_state: loading encoders (layers={}, count={})",
+/// Every encoder layer, like `read_keymap_layers`
+async fn read_encoder_layers(
+    manager: &HidManager,
+    device_info: &DeviceInfo,
+    refresh: bool,
+    job: &JobContext,
+    first_step: u32,
+    total_steps: u32,
+) -> Result<Vec<Vec<EncoderEntry>>, ConfigError> {
+    let layer_count = device_info.layer_count.max(1);
+    let encoder_count = device_info.encoder_count;
+    info!(
+        "read_encoder_layers: loading encoders (layers={}, count={})",
         layer_count,
         encoder_count
     );
-    let encoders = {
-        let manager = state.read().await;
-        let mut all_layers = Vec::with_capacity(layer_count as usize);
-        let mut failed_encoders = 0usize;
-
-        for layer_idx in 0..layer_count {
-            let mut layer_encoders = Vec::with_capacity(encoder_count as usize);
-            for encoder_idx in 0..encoder_count {
-                match manager.get_encoder_entry(layer_idx, encoder_idx).await {
-                    Ok(entry) => layer_encoders.push(entry),
-                    Err(e) => {
-                        failed_encoders += 1;
-                        println!(
-                            "build_full_state: encoder read failed at L{} #{} -> {}",
-                            layer_idx, encoder_idx, e
-                        );
-                        layer_encoders.push(EncoderEntry {
+    let mut all_layers = Vec::with_capacity(layer_count as usize);
+    let mut failed_layers = 0usize;
+
+    for layer_idx in 0..layer_count {
+        job.check()?;
+        let cached = if refresh { None } else { manager.model().encoder_layer(layer_idx, encoder_count) };
+        if let Some(layer_encoders) = cached {
+            all_layers.push(layer_encoders);
+            job.progress(first_step + layer_idx as u32 + 1, total_steps, Some(layer_idx), None);
+            continue;
+        }
+        match manager.get_encoder_layer(layer_idx, encoder_count).await {
+            Ok(layer_encoders) => all_layers.push(layer_encoders),
+            Err(e) => {
+                failed_layers += 1;
+                warn!("read_encoder_layers: encoder read failed for L{} -> {}", layer_idx, e);
+                all_layers.push(
+                    (0..encoder_count)
+                        .map(|encoder_id| EncoderEntry {
                             layer: layer_idx,
-                            encoder_id: encoder_idx,
+                            encoder_id,
                             ccw_keycode: 0,
                             cw_keycode: 0,
                             reserved: 0,
-                        });
-                    }
-                }
+                        })
+                        .collect(),
+                );
             }
-            all_layers.push(layer_encoders);
         }
+        job.progress(first_step + layer_idx as u32 + 1, total_steps, Some(layer_idx), None);
+    }
 
-        println!(
-            "build_full_state: encoders complete ({} failures)",
-            failed_encoders
-        );
-        all_layers
-    };
-
-    Ok(FullState {
-        device_info,
-        keymap,
-        encoders,
-        layout,
-        layer_state,
-    })
+    info!(
+        "read_encoder_layers: encoders complete ({} failures)",
+        failed_layers
+    );
+    Ok(all_layers)
 }
 
 // Batched full-state loader to avoid multiple interleaved invokes
@@ -410,25 +775,40 @@ pub struct FullState {
     pub layer_state: Option<LayerState>,
 }
 
+/// Load the full state in the background; the job's result is a `FullState`.
+/// Served from the cached model unless `refresh` is set.
 #[tauri::command]
-pub async fn load_full_state(state: State<'_, AppState>) -> Result<FullState, String> {
-    let device_info = {
-        let manager = state.read().await;
-        manager.get_device_info().await?
-    };
-
-    let layout = {
-        let manager = state.read().await;
-        match manager.get_board_layout().await {
-            Ok(info) => Some(info),
-            Err(e) => {
-                println!("load_full_state: warning - no layout info available: {}", e);
-                None
+pub async fn load_full_state(
+    refresh: Option<bool>,
+    device_id: Option<String>,
+    state: State<'_, AppState>,
+    jobs: State<'_, Jobs>,
+) -> Result<JobId, ConfigError> {
+    let state = state.inner().clone();
+    let refresh = refresh.unwrap_or(false);
+    Ok(jobs.spawn("load_full_state", move |job| async move {
+        let device_info = {
+            let manager = state.read(device_id.as_deref()).await?;
+            manager.get_device_info().await?
+        };
+
+        let layout = {
+            let manager = state.read(device_id.as_deref()).await?;
+            let cached = if refresh { None } else { manager.model().layout() };
+            match cached {
+                Some(layout) => Some(layout),
+                None => match manager.get_board_layout().await {
+                    Ok(info) => Some(info),
+                    Err(e) => {
+                        warn!("load_full_state: no layout info available: {}", e);
+                        None
+                    }
+                },
             }
-        }
-    };
+        };
 
-    build_full_state(&state, device_info, layout).await
+        build_full_state(&state, device_id.as_deref(), device_info, layout, refresh, &job).await
+    }))
 }
 
 // Enhanced connection status that includes all data in one call
@@ -440,35 +820,25 @@ pub struct EnhancedConnectionStatus {
     pub encoders: Option<Vec<Vec<EncoderEntry>>>,
     pub layout: Option<BoardLayoutInfo>,
     pub layer_state: Option<LayerState>,
-    pub error: Option<String>,
+    pub error: Option<ConfigError>,
 }
 
+/// Connection status plus everything the editor needs, loaded in the background;
+/// the job's result is an `EnhancedConnectionStatus`, with progress per keymap
+/// and encoder layer.
 #[tauri::command]
-pub async fn get_enhanced_connection_status(state: State<'_, AppState>) -> Result<EnhancedConnectionStatus, String> {
-    let manager = state.read().await;
-    
-    println!("Enhanced connection status: is_connected = {}", manager.is_connected());
-    
-    if !manager.is_connected() {
-        return Ok(EnhancedConnectionStatus {
-            connected: false,
-            device_info: None,
-            keymap: None,
-            encoders: None,
-            layout: None,
-            layer_state: None,
-            error: None,
-        });
-    }
+pub async fn get_enhanced_connection_status(
+    device_id: Option<String>,
+    state: State<'_, AppState>,
+    jobs: State<'_, Jobs>,
+) -> Result<JobId, ConfigError> {
+    let state = state.inner().clone();
+    Ok(jobs.spawn("get_enhanced_connection_status", move |job| async move {
+        let manager = state.read(device_id.as_deref()).await?;
 
-    println!("Getting device info...");
-    let device_info = match manager.get_device_info().await {
-        Ok(info) => {
-            println!("Device info retrieved: {}", info.device_name);
-            info
-        }
-        Err(e) => {
-            println!("Failed to get device info: {}", e);
+        debug!("Enhanced connection status: is_connected = {}", manager.is_connected());
+
+        if !manager.is_connected() {
             return Ok(EnhancedConnectionStatus {
                 connected: false,
                 device_info: None,
@@ -476,42 +846,66 @@ pub async fn get_enhanced_connection_status(state: State<'_, AppState>) -> Resul
                 encoders: None,
                 layout: None,
                 layer_state: None,
-                error: Some(format!("Failed to get device info: {}", e)),
+                error: None,
             });
         }
-    };
-    drop(manager);
 
-    let layout = {
-        let manager = state.read().await;
-        match manager.get_board_layout().await {
-            Ok(info) => Some(info),
+        debug!("Getting device info...");
+        let device_info = match manager.get_device_info().await {
+            Ok(info) => {
+                debug!("Device info retrieved: {}", info.device_name);
+                info
+            }
             Err(e) => {
-                println!("Enhanced connection status: layout unavailable: {}", e);
-                None
+                warn!("Failed to get device info: {}", e);
+                return Ok(EnhancedConnectionStatus {
+                    connected: false,
+                    device_info: None,
+                    keymap: None,
+                    encoders: None,
+                    layout: None,
+                    layer_state: None,
+                    error: Some(e),
+                });
             }
-        }
-    };
-
-    let snapshot = build_full_state(&state, device_info, layout).await?;
-    let FullState {
-        device_info,
-        keymap,
-        encoders,
-        layout,
-        layer_state,
-    } = snapshot;
-
-    println!("Enhanced connection status complete: connected=true");
-    Ok(EnhancedConnectionStatus {
-        connected: true,
-        device_info: Some(device_info),
-        keymap: Some(keymap),
-        encoders: Some(encoders),
-        layout,
-        layer_state,
-        error: None,
-    })
+        };
+        drop(manager);
+
+        let layout = {
+            let manager = state.read(device_id.as_deref()).await?;
+            let cached = manager.model().layout();
+            match cached {
+                Some(layout) => Some(layout),
+                None => match manager.get_board_layout().await {
+                    Ok(info) => Some(info),
+                    Err(e) => {
+                        warn!("Enhanced connection status: layout unavailable: {}", e);
+                        None
+                    }
+                },
+            }
+        };
+
+        let snapshot = build_full_state(&state, device_id.as_deref(), device_info, layout, false, &job).await?;
+        let FullState {
+            device_info,
+            keymap,
+            encoders,
+            layout,
+            layer_state,
+        } = snapshot;
+
+        info!("Enhanced connection status complete: connected=true");
+        Ok(EnhancedConnectionStatus {
+            connected: true,
+            device_info: Some(device_info),
+            keymap: Some(keymap),
+            encoders: Some(encoders),
+            layout,
+            layer_state,
+            error: None,
+        })
+    }))
 }
 
 // Keymap management commands
@@ -521,48 +915,68 @@ pub async fn get_keymap_entry(
     layer: u8,
     row: u8,
     col: u8,
+    refresh: Option<bool>,
+    device_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<KeymapEntry, String> {
-    let manager = state.read().await;
-    manager.get_keymap_entry(layer, row, col).await
+) -> Result<KeymapEntry, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    match manager.cached(ModelKey::Keymap { layer, row, col }, refresh.unwrap_or(false)) {
+        Some(ModelEntry::Keymap(entry)) => Ok(entry),
+        _ => manager.get_keymap_entry(layer, row, col).await,
+    }
 }
 
 #[tauri::command]
 pub async fn set_keymap_entry(
     entry: KeymapEntry,
+    device_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<(), String> {
-    let manager = state.read().await;
-    manager.set_keymap_entry(&entry).await
+) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.apply_edit("set_keymap_entry", &[ModelEntry::Keymap(entry)]).await
 }
 
 #[tauri::command]
-pub async fn get_full_keymap(state: State<'_, AppState>) -> Result<Vec<Vec<Vec<KeymapEntry>>>, String> {
-    let device_info = {
-        let manager = state.read().await;
-        manager.get_device_info().await?
-    };
-
-    let snapshot = build_full_state(&state, device_info, None).await?;
-    Ok(snapshot.keymap)
+pub async fn get_full_keymap(
+    refresh: Option<bool>,
+    device_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<Vec<Vec<KeymapEntry>>>, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    let refresh = refresh.unwrap_or(false);
+    let device_info = device_info(&manager, refresh).await?;
+    let job = JobContext::detached("get_full_keymap");
+    let total_steps = device_info.layer_count.max(1) as u32;
+    read_keymap_layers(&manager, &device_info, refresh, &job, 0, total_steps).await
 }
 
+/// Write every layer in the background as one undoable step, reporting
+/// progress per layer. All or nothing: on a failed write or `cancel_job` the
+/// layers are restored (see `HidManager::write_transaction`). With `save` the
+/// keymap is saved to flash once every write went through.
 #[tauri::command]
 pub async fn set_full_keymap(
     keymap: Vec<Vec<Vec<KeymapEntry>>>,
+    save: Option<bool>,
+    device_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<(), String> {
-    let manager = state.read().await;
-    
-    for layer_entries in keymap {
-        for row_entries in layer_entries {
-            for entry in row_entries {
-                manager.set_keymap_entry(&entry).await?;
-            }
-        }
-    }
-    
-    Ok(())
+    jobs: State<'_, Jobs>,
+) -> Result<JobId, ConfigError> {
+    let state = state.inner().clone();
+    Ok(jobs.spawn("set_full_keymap", move |job| async move {
+        let manager = state.read(device_id.as_deref()).await?;
+        write_full_keymap(&manager, keymap, save.unwrap_or(false), &job).await
+    }))
+}
+
+async fn write_full_keymap(
+    manager: &HidManager,
+    keymap: Vec<Vec<Vec<KeymapEntry>>>,
+    save: bool,
+    job: &JobContext,
+) -> Result<(), ConfigError> {
+    let entries: Vec<ModelEntry> = keymap.into_iter().flatten().flatten().map(ModelEntry::Keymap).collect();
+    manager.write_transaction("set_full_keymap", &entries, save, job).await
 }
 
 // Encoder management commands
@@ -571,83 +985,223 @@ pub async fn set_full_keymap(
 pub async fn get_encoder_entry(
     layer: u8,
     encoder_id: u8,
+    refresh: Option<bool>,
+    device_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<EncoderEntry, String> {
-    let manager = state.read().await;
-    manager.get_encoder_entry(layer, encoder_id).await
+) -> Result<EncoderEntry, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    match manager.cached(ModelKey::Encoder { layer, encoder_id }, refresh.unwrap_or(false)) {
+        Some(ModelEntry::Encoder(entry)) => Ok(entry),
+        _ => manager.get_encoder_entry(layer, encoder_id).await,
+    }
 }
 
 #[tauri::command]
 pub async fn set_encoder_entry(
     entry: EncoderEntry,
+    device_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<(), String> {
-    let manager = state.read().await;
-    manager.set_encoder_entry(&entry).await
+) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.apply_edit("set_encoder_entry", &[ModelEntry::Encoder(entry)]).await
 }
 
 #[tauri::command]
-pub async fn get_all_encoders(state: State<'_, AppState>) -> Result<Vec<Vec<EncoderEntry>>, String> {
-    let device_info = {
-        let manager = state.read().await;
-        manager.get_device_info().await?
-    };
-
-    let snapshot = build_full_state(&state, device_info, None).await?;
-    Ok(snapshot.encoders)
+pub async fn get_all_encoders(
+    refresh: Option<bool>,
+    device_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<Vec<EncoderEntry>>, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    let refresh = refresh.unwrap_or(false);
+    let device_info = device_info(&manager, refresh).await?;
+    let job = JobContext::detached("get_all_encoders");
+    let total_steps = device_info.layer_count.max(1) as u32;
+    read_encoder_layers(&manager, &device_info, refresh, &job, 0, total_steps).await
 }
 
+/// All or nothing, like `set_full_keymap`
 #[tauri::command]
 pub async fn set_all_encoders(
     encoders: Vec<Vec<EncoderEntry>>,
+    save: Option<bool>,
+    device_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<(), String> {
-    let manager = state.read().await;
-    
-    for layer_entries in encoders {
-        for entry in layer_entries {
-            manager.set_encoder_entry(&entry).await?;
-        }
-    }
-    
-    Ok(())
+) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    let entries: Vec<ModelEntry> = encoders.into_iter().flatten().map(ModelEntry::Encoder).collect();
+    let job = JobContext::detached("set_all_encoders");
+    manager.write_transaction("set_all_encoders", &entries, save.unwrap_or(false), &job).await
 }
 
 // Configuration management commands
 
+/// Always asks the device: layer keys change the state without the host knowing
 #[tauri::command]
-pub async fn get_layer_state(state: State<'_, AppState>) -> Result<LayerState, String> {
-    let manager = state.read().await;
+pub async fn get_layer_state(device_id: Option<String>, state: State<'_, AppState>) -> Result<LayerState, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     manager.get_layer_state().await
 }
 
 #[tauri::command]
 pub async fn set_layer_state(
     layer_state: LayerState,
+    device_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<LayerState, String> {
-    let manager = state.read().await;
+) -> Result<LayerState, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     manager.set_layer_state(&layer_state).await
 }
 
 #[tauri::command]
-pub async fn save_config(state: State<'_, AppState>) -> Result<(), String> {
-    let manager = state.read().await;
+pub async fn save_config(device_id: Option<String>, state: State<'_, AppState>) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     manager.save_config().await
 }
 
 #[tauri::command]
-pub async fn load_config(state: State<'_, AppState>) -> Result<(), String> {
-    let manager = state.read().await;
+pub async fn load_config(device_id: Option<String>, state: State<'_, AppState>) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     manager.load_config().await
 }
 
 #[tauri::command]
-pub async fn reset_config(state: State<'_, AppState>) -> Result<(), String> {
-    let manager = state.read().await;
+pub async fn reset_config(device_id: Option<String>, state: State<'_, AppState>) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     manager.reset_config().await
 }
 
+/// Export the whole board, slaves included, to a versioned JSON file at
+/// `path` in the background; progress is one step per layer
+#[tauri::command]
+pub async fn export_config(
+    path: String,
+    device_id: Option<String>,
+    state: State<'_, AppState>,
+    jobs: State<'_, Jobs>,
+) -> Result<JobId, ConfigError> {
+    let state = state.inner().clone();
+    Ok(jobs.spawn("export_config", move |job| async move {
+        let manager = state.read(device_id.as_deref()).await?;
+        let file = ConfigFile::read(&manager, &job).await?;
+        file.save(Path::new(&path))
+    }))
+}
+
+/// Import a file written by `export_config` in the background. It's refused
+/// unless the board and its slaves match its dimensions; otherwise it's
+/// written all or nothing, and saved to flash with `save`. The job's result is
+/// the number of values written.
+#[tauri::command]
+pub async fn import_config(
+    path: String,
+    save: Option<bool>,
+    device_id: Option<String>,
+    state: State<'_, AppState>,
+    jobs: State<'_, Jobs>,
+) -> Result<JobId, ConfigError> {
+    let file = ConfigFile::load(Path::new(&path))?;
+    let state = state.inner().clone();
+    Ok(jobs.spawn("import_config", move |job| async move {
+        let manager = state.read(device_id.as_deref()).await?;
+        file.apply(&manager, save.unwrap_or(false), &job).await
+    }))
+}
+
+// Undo/redo commands
+
+/// Revert the last edit on the device; returns the step undone, or null if there was none
+#[tauri::command]
+pub async fn undo(device_id: Option<String>, state: State<'_, AppState>) -> Result<Option<HistoryStep>, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.undo().await
+}
+
+/// Reapply the last undone edit; returns the step redone, or null if there was none
+#[tauri::command]
+pub async fn redo(device_id: Option<String>, state: State<'_, AppState>) -> Result<Option<HistoryStep>, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.redo().await
+}
+
+#[tauri::command]
+pub async fn get_history(device_id: Option<String>, state: State<'_, AppState>) -> Result<HistorySummary, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    let summary = manager.history().summary();
+    Ok(summary)
+}
+
+// Cached device model commands
+
+/// Everything known about the device, staged edits included. Keymap and
+/// encoders are read from the device if they aren't cached or `refresh` is set;
+/// sliders, magnetic switches and slaves show whatever has been read so far.
+#[tauri::command]
+pub async fn get_device_model(
+    refresh: Option<bool>,
+    device_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<ModelSnapshot, ConfigError> {
+    let refresh = refresh.unwrap_or(false);
+    let (device_info, layout) = {
+        let manager = state.read(device_id.as_deref()).await?;
+        let cached = if refresh { None } else { manager.model().device_info() };
+        let device_info = match cached {
+            Some(info) => info,
+            None => manager.get_device_info().await?,
+        };
+        let cached = if refresh { None } else { manager.model().layout() };
+        let layout = match cached {
+            Some(layout) => Some(layout),
+            None => manager.get_board_layout().await.ok(),
+        };
+        (device_info, layout)
+    };
+
+    let job = JobContext::detached("get_device_model");
+    build_full_state(&state, device_id.as_deref(), device_info, layout, refresh, &job).await?;
+    let manager = state.read(device_id.as_deref()).await?;
+    let snapshot = manager.model().snapshot();
+    Ok(snapshot)
+}
+
+/// Stage edits in the cached model without writing them; returns every dirty cell
+#[tauri::command]
+pub async fn stage_changes(
+    changes: Vec<ModelEntry>,
+    device_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<ModelKey>, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    let mut model = manager.model();
+    for entry in changes {
+        model.stage(entry);
+    }
+    Ok(model.dirty().iter().map(ModelEntry::key).collect())
+}
+
+#[tauri::command]
+pub async fn get_pending_changes(device_id: Option<String>, state: State<'_, AppState>) -> Result<Vec<ModelEntry>, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    let dirty = manager.model().dirty();
+    Ok(dirty)
+}
+
+/// Write the staged edits that differ from the device; returns how many were written
+#[tauri::command]
+pub async fn commit_changes(device_id: Option<String>, state: State<'_, AppState>) -> Result<usize, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.commit_changes().await
+}
+
+/// Drop every staged edit; returns how many there were
+#[tauri::command]
+pub async fn discard_changes(device_id: Option<String>, state: State<'_, AppState>) -> Result<usize, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    let discarded = manager.model().discard();
+    Ok(discarded)
+}
+
 // Slave device keymap commands
 
 #[tauri::command]
@@ -656,19 +1210,25 @@ pub async fn get_slave_keymap_entry(
     layer: u8,
     row: u8,
     col: u8,
+    refresh: Option<bool>,
+    device_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<SlaveKeymapEntry, String> {
-    let manager = state.read().await;
-    manager.get_slave_keymap_entry(slave_addr, layer, row, col).await
+) -> Result<SlaveKeymapEntry, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    match manager.cached(ModelKey::SlaveKeymap { slave_addr, layer, row, col }, refresh.unwrap_or(false)) {
+        Some(ModelEntry::SlaveKeymap(entry)) => Ok(entry),
+        _ => manager.get_slave_keymap_entry(slave_addr, layer, row, col).await,
+    }
 }
 
 #[tauri::command]
 pub async fn set_slave_keymap_entry(
     entry: SlaveKeymapEntry,
+    device_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<(), String> {
-    let manager = state.read().await;
-    manager.set_slave_keymap_entry(&entry).await
+) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.apply_edit("set_slave_keymap_entry", &[ModelEntry::SlaveKeymap(entry)]).await
 }
 
 // Slave device encoder commands
@@ -678,48 +1238,83 @@ pub async fn get_slave_encoder_entry(
     slave_addr: u8,
     layer: u8,
     encoder_id: u8,
+    refresh: Option<bool>,
+    device_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<SlaveEncoderEntry, String> {
-    let manager = state.read().await;
-    manager.get_slave_encoder_entry(slave_addr, layer, encoder_id).await
+) -> Result<SlaveEncoderEntry, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    match manager.cached(ModelKey::SlaveEncoder { slave_addr, layer, encoder_id }, refresh.unwrap_or(false)) {
+        Some(ModelEntry::SlaveEncoder(entry)) => Ok(entry),
+        _ => manager.get_slave_encoder_entry(slave_addr, layer, encoder_id).await,
+    }
 }
 
 #[tauri::command]
 pub async fn set_slave_encoder_entry(
     entry: SlaveEncoderEntry,
+    device_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<(), String> {
-    let manager = state.read().await;
-    manager.set_slave_encoder_entry(&entry).await
+) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.apply_edit("set_slave_encoder_entry", &[ModelEntry::SlaveEncoder(entry)]).await
 }
 
 #[tauri::command]
 pub async fn get_slave_info(
     slave_addr: u8,
+    device_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<DeviceInfo, String> {
-    let manager = state.read().await;
+) -> Result<DeviceInfo, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     manager.get_slave_info(slave_addr).await
 }
 
 #[tauri::command]
 pub async fn get_i2c_devices(
+    device_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<Vec<I2CDeviceInfo>, String> {
-    let manager = state.read().await;
+) -> Result<Vec<I2CDeviceInfo>, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     manager.get_i2c_devices().await
 }
 
+/// Read a slave's whole keymap in the background, reporting one step per
+/// row; the job's result is the keymap by layer, row and column. Cached
+/// entries are reused unless `refresh` is set.
 #[tauri::command]
 pub async fn get_full_slave_keymap(
     slave_addr: u8,
+    refresh: Option<bool>,
+    device_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<Vec<Vec<Vec<SlaveKeymapEntry>>>, String> {
-    let manager = state.read().await;
-    
-    // Get slave device info first to know matrix dimensions
-    let device_info = manager.get_slave_info(slave_addr).await?;
+    jobs: State<'_, Jobs>,
+) -> Result<JobId, ConfigError> {
+    let state = state.inner().clone();
+    Ok(jobs.spawn("get_full_slave_keymap", move |job| async move {
+        let manager = state.read(device_id.as_deref()).await?;
+        read_slave_keymap(&manager, slave_addr, refresh.unwrap_or(false), &job).await
+    }))
+}
+
+/// Slave info from the cache, unless `refresh` asks the slave again
+async fn slave_info(manager: &HidManager, slave_addr: u8, refresh: bool) -> Result<DeviceInfo, ConfigError> {
+    if refresh {
+        manager.get_slave_info(slave_addr).await
+    } else {
+        manager.cached_slave_info(slave_addr).await
+    }
+}
+
+async fn read_slave_keymap(
+    manager: &HidManager,
+    slave_addr: u8,
+    refresh: bool,
+    job: &JobContext,
+) -> Result<Vec<Vec<Vec<SlaveKeymapEntry>>>, ConfigError> {
+    // Slave device info first to know matrix dimensions
+    let device_info = slave_info(manager, slave_addr, refresh).await?;
     let layer_count = device_info.layer_count.max(1);
+    let total_rows = layer_count as u32 * device_info.matrix_rows as u32;
     
     let mut keymap = Vec::with_capacity(layer_count as usize);
 
@@ -728,20 +1323,18 @@ pub async fn get_full_slave_keymap(
         for row in 0..device_info.matrix_rows {
             let mut row_entries = Vec::with_capacity(device_info.matrix_cols as usize);
             for col in 0..device_info.matrix_cols {
-                match manager.get_slave_keymap_entry(slave_addr, layer, row, col).await {
-                    Ok(entry) => row_entries.push(entry),
-                    Err(_e) => {
-                        row_entries.push(SlaveKeymapEntry {
-                            slave_addr,
-                            layer,
-                            row,
-                            col,
-                            keycode: 0,
-                        });
-                    }
+                job.check()?;
+                if let Some(ModelEntry::SlaveKeymap(entry)) =
+                    manager.cached(ModelKey::SlaveKeymap { slave_addr, layer, row, col }, refresh)
+                {
+                    row_entries.push(entry);
+                    continue;
                 }
+                row_entries.push(manager.get_slave_keymap_entry(slave_addr, layer, row, col).await?);
             }
             layer_rows.push(row_entries);
+            let done = layer as u32 * device_info.matrix_rows as u32 + row as u32 + 1;
+            job.progress(done, total_rows, Some(layer), Some(row));
         }
         keymap.push(layer_rows);
     }
@@ -752,28 +1345,32 @@ pub async fn get_full_slave_keymap(
 #[tauri::command]
 pub async fn get_full_slave_encoders(
     slave_addr: u8,
+    refresh: Option<bool>,
+    device_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<Vec<Vec<SlaveEncoderEntry>>, String> {
-    let manager = state.read().await;
+) -> Result<Vec<Vec<SlaveEncoderEntry>>, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    read_slave_encoders(&manager, slave_addr, refresh.unwrap_or(false)).await
+}
 
-    let device_info = manager.get_slave_info(slave_addr).await?;
+async fn read_slave_encoders(
+    manager: &HidManager,
+    slave_addr: u8,
+    refresh: bool,
+) -> Result<Vec<Vec<SlaveEncoderEntry>>, ConfigError> {
+    let device_info = slave_info(manager, slave_addr, refresh).await?;
     let layer_count = device_info.layer_count.max(1);
     let mut encoders = Vec::with_capacity(layer_count as usize);
 
     for layer in 0..layer_count {
         let mut layer_encoders = Vec::with_capacity(device_info.encoder_count as usize);
         for encoder_id in 0..device_info.encoder_count {
-            match manager.get_slave_encoder_entry(slave_addr, layer, encoder_id).await {
-                Ok(entry) => layer_encoders.push(entry),
-                Err(_) => layer_encoders.push(SlaveEncoderEntry {
-                    slave_addr,
-                    layer,
-                    encoder_id,
-                    ccw_keycode: 0,
-                    cw_keycode: 0,
-                    reserved: 0,
-                }),
+            let key = ModelKey::SlaveEncoder { slave_addr, layer, encoder_id };
+            if let Some(ModelEntry::SlaveEncoder(entry)) = manager.cached(key, refresh) {
+                layer_encoders.push(entry);
+                continue;
             }
+            layer_encoders.push(manager.get_slave_encoder_entry(slave_addr, layer, encoder_id).await?);
         }
         encoders.push(layer_encoders);
     }
@@ -781,22 +1378,18 @@ pub async fn get_full_slave_encoders(
     Ok(encoders)
 }
 
+/// All or nothing, like `set_full_keymap`
 #[tauri::command]
 pub async fn set_full_slave_keymap(
     keymap: Vec<Vec<Vec<SlaveKeymapEntry>>>,
+    save: Option<bool>,
+    device_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<(), String> {
-    let manager = state.read().await;
-    
-    for layer_rows in keymap {
-        for row_entries in layer_rows {
-            for entry in row_entries {
-                manager.set_slave_keymap_entry(&entry).await?;
-            }
-        }
-    }
-    
-    Ok(())
+) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    let entries: Vec<ModelEntry> = keymap.into_iter().flatten().flatten().map(ModelEntry::SlaveKeymap).collect();
+    let job = JobContext::detached("set_full_slave_keymap");
+    manager.write_transaction("set_full_slave_keymap", &entries, save.unwrap_or(false), &job).await
 }
 
 // I2C device management commands
@@ -804,31 +1397,246 @@ pub async fn set_full_slave_keymap(
 // System commands
 
 #[tauri::command]
-pub async fn reboot_device(state: State<'_, AppState>) -> Result<(), String> {
-    let manager = state.read().await;
+pub async fn reboot_device(device_id: Option<String>, state: State<'_, AppState>) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
     manager.reboot_device().await
 }
 
+// Link tuning commands
+
+#[tauri::command]
+pub async fn get_pipeline_window(device_id: Option<String>, state: State<'_, AppState>) -> Result<usize, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    Ok(manager.pipeline_window())
+}
+
+#[tauri::command]
+pub async fn set_pipeline_window(size: usize, device_id: Option<String>, state: State<'_, AppState>) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.set_pipeline_window(size)
+}
+
+#[tauri::command]
+pub async fn get_retry_policy(device_id: Option<String>, state: State<'_, AppState>) -> Result<RetryPolicy, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    Ok(manager.retry_policy())
+}
+
+/// Replace timeouts, backoff and `Busy` retries, globally and per command
+#[tauri::command]
+pub async fn set_retry_policy(policy: RetryPolicy, device_id: Option<String>, state: State<'_, AppState>) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.set_retry_policy(policy)
+}
+
+/// Start or stop streaming every packet, decoded, as `og:protocol` events
+#[tauri::command]
+pub async fn set_protocol_console(enabled: bool, device_id: Option<String>, state: State<'_, AppState>) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.set_protocol_console(enabled);
+    Ok(())
+}
+
+/// Read every configuration write back and fail with `VerifyFailed` on a mismatch
+#[tauri::command]
+pub async fn set_verify_writes(enabled: bool, device_id: Option<String>, state: State<'_, AppState>) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.set_verify_writes(enabled);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_verify_writes(device_id: Option<String>, state: State<'_, AppState>) -> Result<bool, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    Ok(manager.verify_writes())
+}
+
+/// Per-command counters and latency histograms since connecting
+#[tauri::command]
+pub async fn get_link_stats(device_id: Option<String>, state: State<'_, AppState>) -> Result<LinkStatsSnapshot, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    Ok(manager.link_stats())
+}
+
+#[tauri::command]
+pub async fn reset_link_stats(device_id: Option<String>, state: State<'_, AppState>) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.reset_link_stats();
+    Ok(())
+}
+
+// Large transfer commands
+
+#[tauri::command]
+pub async fn send_large(
+    command: ConfigCommand,
+    data: Vec<u8>,
+    device_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<(), ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.send_large(command, &data).await
+}
+
+#[tauri::command]
+pub async fn receive_large(
+    command: ConfigCommand,
+    args: Vec<u8>,
+    device_id: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<Vec<u8>, ConfigError> {
+    let manager = state.read(device_id.as_deref()).await?;
+    manager.receive_large(command, &args).await
+}
+
 // Utility commands
 
 #[tauri::command]
-pub fn get_keycodes() -> Result<Vec<crate::keycodes::Keycode>, String> {
+pub fn get_keycodes() -> Result<Vec<crate::keycodes::Keycode>, ConfigError> {
     let keymap = crate::keycodes::get_keycodes();
     Ok(keymap.into_values().collect())
 }
 
+/// Write a slave's encoders in the background, reporting one step per entry,
+/// as one undoable step. All or nothing, like `set_full_keymap`.
 #[tauri::command]
 pub async fn set_full_slave_encoders(
     encoders: Vec<Vec<SlaveEncoderEntry>>,
+    save: Option<bool>,
+    device_id: Option<String>,
     state: State<'_, AppState>,
-) -> Result<(), String> {
-    let manager = state.read().await;
+    jobs: State<'_, Jobs>,
+) -> Result<JobId, ConfigError> {
+    let state = state.inner().clone();
+    Ok(jobs.spawn("set_full_slave_encoders", move |job| async move {
+        let manager = state.read(device_id.as_deref()).await?;
+        let entries: Vec<ModelEntry> = encoders.into_iter().flatten().map(ModelEntry::SlaveEncoder).collect();
+        manager.write_transaction("set_full_slave_encoders", &entries, save.unwrap_or(false), &job).await
+    }))
+}
+
+// Background jobs
 
-    for layer_entries in encoders {
-        for entry in layer_entries {
-            manager.set_slave_encoder_entry(&entry).await?;
+/// Stop a job at its next request boundary; false if it had already finished
+#[tauri::command]
+pub fn cancel_job(job_id: JobId, jobs: State<'_, Jobs>) -> bool {
+    jobs.cancel(job_id)
+}
+
+/// Wait for a job and return its result, or the error it stopped with
+#[tauri::command]
+pub async fn await_job(job_id: JobId, jobs: State<'_, Jobs>) -> Result<serde_json::Value, ConfigError> {
+    jobs.wait(job_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{connected_simulator, SimulatedDevice, SimulatorTopology};
+
+    #[tokio::test]
+    async fn cached_reads_send_nothing_and_only_read_what_was_asked() {
+        let topology: SimulatorTopology =
+            serde_json::from_str(r#"{ "slaves": [{ "address": 32, "matrix_rows": 1, "matrix_cols": 2, "encoder_count": 1 }] }"#)
+                .unwrap();
+        let (manager, _sim) = connected_simulator(SimulatedDevice::with_topology(&topology).unwrap());
+        let job = JobContext::detached("test");
+        let requests = |command| {
+            let stats = manager.link_stats();
+            stats.commands.iter().filter(|c| c.command == Some(command)).map(|c| c.requests).sum::<u64>()
+        };
+
+        let info = device_info(&manager, false).await.unwrap();
+        let keymap = read_keymap_layers(&manager, &info, false, &job, 0, 4).await.unwrap();
+        assert_eq!(keymap[0][0][0].keycode, 0x04);
+        assert_eq!(requests(ConfigCommand::GetEncoderMap) + requests(ConfigCommand::GetEncoderMapBulk), 0);
+        read_slave_keymap(&manager, 32, false, &job).await.unwrap();
+
+        let sent = manager.link_stats().totals.requests;
+        let info = device_info(&manager, false).await.unwrap();
+        assert_eq!(read_keymap_layers(&manager, &info, false, &job, 0, 4).await.unwrap(), keymap);
+        read_slave_keymap(&manager, 32, false, &job).await.unwrap();
+        assert_eq!(manager.link_stats().totals.requests, sent);
+
+        // Encoders alone leave the keymap alone, and a refresh goes back to the device
+        read_encoder_layers(&manager, &info, false, &job, 0, 4).await.unwrap();
+        read_slave_encoders(&manager, 32, false).await.unwrap();
+        assert_eq!(requests(ConfigCommand::GetSlaveInfo), 1);
+        let keymap_reads = requests(ConfigCommand::GetKeymap) + requests(ConfigCommand::GetKeymapBulk);
+        read_encoder_layers(&manager, &info, true, &job, 0, 4).await.unwrap();
+        assert_eq!(requests(ConfigCommand::GetKeymap) + requests(ConfigCommand::GetKeymapBulk), keymap_reads);
+        read_slave_encoders(&manager, 32, true).await.unwrap();
+        assert_eq!(requests(ConfigCommand::GetSlaveInfo), 2);
+        device_info(&manager, true).await.unwrap();
+        assert_eq!(requests(ConfigCommand::GetInfo), 2);
+    }
+
+    #[tokio::test]
+    async fn slave_read_errors_are_returned_not_zeroed() {
+        // Each slave answers its info and one entry, then drops off the bus
+        let topology: SimulatorTopology = serde_json::from_str(
+            r#"{ "slaves": [
+                { "address": 32, "matrix_rows": 1, "matrix_cols": 3, "keymap": [[[4, 5, 6]]], "drop_after": 2 },
+                { "address": 33, "matrix_rows": 1, "matrix_cols": 1, "encoder_count": 2,
+                  "encoders": [[[82, 81], [80, 79]]], "drop_after": 2 }
+            ] }"#,
+        )
+        .unwrap();
+        let (manager, _sim) = connected_simulator(SimulatedDevice::with_topology(&topology).unwrap());
+
+        let job = JobContext::detached("test");
+        assert!(read_slave_keymap(&manager, 32, false, &job).await.is_err());
+        assert!(read_slave_encoders(&manager, 33, false).await.is_err());
+
+        // What was read stays cached; nothing was made up for the cells that failed
+        let key = |col| ModelKey::SlaveKeymap { slave_addr: 32, layer: 0, row: 0, col };
+        assert!(matches!(manager.cached(key(0), false), Some(ModelEntry::SlaveKeymap(entry)) if entry.keycode == 4));
+        assert!(manager.cached(key(1), false).is_none());
+        assert!(manager.cached(ModelKey::SlaveEncoder { slave_addr: 33, layer: 0, encoder_id: 1 }, false).is_none());
+    }
+
+    /// Wait for the first progress event of `id`, then cancel it
+    async fn cancel_after_first_step(jobs: &Jobs, events: &mut broadcast::Receiver<JobEvent>, id: JobId) {
+        loop {
+            if let JobEvent::Progress(progress) = events.recv().await.unwrap() {
+                if progress.job_id == id {
+                    break;
+                }
+            }
         }
+        assert!(jobs.cancel(id));
     }
 
-    Ok(())
-}
\ No newline at end of file
+    #[tokio::test]
+    async fn cancelled_keymap_jobs_leave_the_link_usable() {
+        let topology: SimulatorTopology =
+            serde_json::from_str(r#"{ "slaves": [{ "address": 32, "matrix_rows": 4, "matrix_cols": 6 }] }"#).unwrap();
+        let (manager, _sim) = connected_simulator(SimulatedDevice::with_topology(&topology).unwrap());
+        let manager = Arc::new(manager);
+        let jobs = Jobs::default();
+        let mut events = jobs.subscribe();
+
+        // Every other column, so each key is its own write and there's time to cancel in between
+        let row = |layer, row| (0..4).step_by(2).map(|col| KeymapEntry { layer, row, col, keycode: 0x2C }).collect();
+        let keymap: Vec<Vec<Vec<KeymapEntry>>> = (0..4).map(|layer| (0..4).map(|r| row(layer, r)).collect()).collect();
+        let device = manager.clone();
+        let id = jobs.spawn("set_full_keymap", move |job| async move { write_full_keymap(&device, keymap, false, &job).await });
+        cancel_after_first_step(&jobs, &mut events, id).await;
+        assert_eq!(jobs.wait(id).await, Err(ConfigError::Cancelled { job_id: id }));
+        // What was written before the cancel was rolled back
+        assert_eq!(manager.get_keymap_entry(0, 0, 0).await.unwrap().keycode, 0x04);
+        assert!(manager.history().summary().undo.is_empty());
+
+        let device = manager.clone();
+        let id = jobs.spawn("get_full_slave_keymap", move |job| async move { read_slave_keymap(&device, 32, false, &job).await });
+        cancel_after_first_step(&jobs, &mut events, id).await;
+        assert_eq!(jobs.wait(id).await, Err(ConfigError::Cancelled { job_id: id }));
+
+        // Both jobs stopped between requests, so the next ones go through normally
+        let keymap = read_slave_keymap(&manager, 32, true, &JobContext::detached("test")).await.unwrap();
+        assert_eq!((keymap[0].len(), keymap[0][0].len()), (4, 6));
+        let info = device_info(&manager, true).await.unwrap();
+        let keymap = read_keymap_layers(&manager, &info, true, &JobContext::detached("test"), 0, 4).await.unwrap();
+        assert_eq!(keymap[0][0][0].keycode, 0x04);
+    }
+}
@@ -0,0 +1,328 @@
+use crate::error::ConfigError;
+use crate::hid_manager::HidManager;
+use crate::jobs::JobContext;
+use crate::model::{ModelEntry, ModelKey};
+use crate::protocol::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// Written to `version`; files from a newer configurator are refused
+pub const CONFIG_FILE_VERSION: u32 = 1;
+
+/// One slave's part of a `ConfigFile`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlaveConfig {
+    pub slave_addr: u8,
+    pub device_info: DeviceInfo,
+    /// Indexed by layer, row, column
+    pub keymap: Vec<Vec<Vec<SlaveKeymapEntry>>>,
+    /// Indexed by layer, encoder id
+    pub encoders: Vec<Vec<SlaveEncoderEntry>>,
+}
+
+/// The whole board's configuration, as written by `export_config`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigFile {
+    pub version: u32,
+    pub exported_at_ms: u64,
+    pub device_info: DeviceInfo,
+    /// None for firmware without `GetBoardLayout`
+    pub layout: Option<BoardLayoutInfo>,
+    pub layer_state: LayerState,
+    /// Indexed by layer, row, column
+    pub keymap: Vec<Vec<Vec<KeymapEntry>>>,
+    /// Indexed by layer, encoder id
+    pub encoders: Vec<Vec<EncoderEntry>>,
+    /// Every layer of every slider in the layout
+    pub sliders: Vec<SliderConfig>,
+    /// Every layer of every magnetic switch in the layout
+    pub magnetic_switches: Vec<MagneticSwitchConfig>,
+    /// Slaves that were online when exporting
+    pub slaves: Vec<SlaveConfig>,
+}
+
+/// What the connected device is made of, which decides what a file holds
+struct Board {
+    info: DeviceInfo,
+    layout: Option<BoardLayoutInfo>,
+    /// Online slaves and their info
+    slaves: Vec<(u8, DeviceInfo)>,
+}
+
+impl Board {
+    async fn read(manager: &HidManager) -> Result<Self, ConfigError> {
+        let info = manager.get_device_info().await?;
+        let layout = match manager.get_board_layout().await {
+            Ok(layout) => Some(layout),
+            Err(ConfigError::Unsupported { .. }) => None,
+            Err(e) => return Err(e),
+        };
+        let mut slaves = Vec::new();
+        for slave in manager.get_i2c_devices().await? {
+            if slave.status != 0 {
+                slaves.push((slave.address, manager.get_slave_info(slave.address).await?));
+            }
+        }
+        Ok(Board { info, layout, slaves })
+    }
+
+    /// Component ids of some kinds of layout cell, e.g. the sliders
+    fn component_ids(&self, cell_types: &[LayoutCellType]) -> Vec<u8> {
+        let mut ids: Vec<u8> = match &self.layout {
+            Some(layout) => {
+                layout.layout.iter().filter(|c| cell_types.contains(&c.cell_type)).map(|c| c.component_id).collect()
+            }
+            None => Vec::new(),
+        };
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    /// Every configurable value, in the order a `ConfigFile` lists them, one
+    /// chunk per layer of each kind so keymap and encoder layers read in bulk
+    fn chunks(&self) -> Vec<Vec<ModelKey>> {
+        let layers = self.info.layer_count.max(1);
+        let (rows, cols) = (self.info.matrix_rows, self.info.matrix_cols);
+        let mut chunks: Vec<Vec<ModelKey>> = Vec::new();
+        for layer in 0..layers {
+            chunks.push((0..rows).flat_map(|row| (0..cols).map(move |col| ModelKey::Keymap { layer, row, col })).collect());
+        }
+        for layer in 0..layers {
+            chunks.push((0..self.info.encoder_count).map(|encoder_id| ModelKey::Encoder { layer, encoder_id }).collect());
+        }
+        // Potentiometers are configured like sliders
+        let sliders = self.component_ids(&[LayoutCellType::Slider, LayoutCellType::Potentiometer]);
+        for layer in 0..layers {
+            chunks.push(sliders.iter().map(|&slider_id| ModelKey::Slider { layer, slider_id }).collect());
+        }
+        let switches = self.component_ids(&[LayoutCellType::MagneticSwitch]);
+        for layer in 0..layers {
+            chunks.push(switches.iter().map(|&switch_id| ModelKey::MagneticSwitch { layer, switch_id }).collect());
+        }
+        for (slave_addr, info) in &self.slaves {
+            let slave_addr = *slave_addr;
+            let (rows, cols) = (info.matrix_rows, info.matrix_cols);
+            for layer in 0..info.layer_count.max(1) {
+                chunks.push(
+                    (0..rows)
+                        .flat_map(|row| (0..cols).map(move |col| ModelKey::SlaveKeymap { slave_addr, layer, row, col }))
+                        .collect(),
+                );
+            }
+            for layer in 0..info.layer_count.max(1) {
+                chunks.push(
+                    (0..info.encoder_count).map(|encoder_id| ModelKey::SlaveEncoder { slave_addr, layer, encoder_id }).collect(),
+                );
+            }
+        }
+        chunks.retain(|chunk| !chunk.is_empty());
+        chunks
+    }
+}
+
+/// Whether two devices have the same matrix, encoders and layers
+fn same_dimensions(a: &DeviceInfo, b: &DeviceInfo) -> bool {
+    (a.matrix_rows, a.matrix_cols, a.encoder_count, a.layer_count)
+        == (b.matrix_rows, b.matrix_cols, b.encoder_count, b.layer_count)
+}
+
+fn describe(info: &DeviceInfo) -> String {
+    format!(
+        "{}x{} with {} encoders and {} layers",
+        info.matrix_rows, info.matrix_cols, info.encoder_count, info.layer_count
+    )
+}
+
+impl ConfigFile {
+    /// Read the connected board's configuration, from the cached model where
+    /// it's there, reporting one step per layer of each kind of value
+    pub async fn read(manager: &HidManager, job: &JobContext) -> Result<Self, ConfigError> {
+        let board = Board::read(manager).await?;
+        let layer_state = manager.get_layer_state().await?;
+        let chunks = board.chunks();
+        let total = chunks.len() as u32;
+
+        let layers = board.info.layer_count.max(1) as usize;
+        let mut file = ConfigFile {
+            version: CONFIG_FILE_VERSION,
+            exported_at_ms: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64),
+            device_info: board.info.clone(),
+            layout: board.layout.clone(),
+            layer_state,
+            keymap: vec![vec![Vec::new(); board.info.matrix_rows as usize]; layers],
+            encoders: vec![Vec::new(); layers],
+            sliders: Vec::new(),
+            magnetic_switches: Vec::new(),
+            slaves: board
+                .slaves
+                .iter()
+                .map(|(slave_addr, info)| SlaveConfig {
+                    slave_addr: *slave_addr,
+                    device_info: info.clone(),
+                    keymap: vec![vec![Vec::new(); info.matrix_rows as usize]; info.layer_count.max(1) as usize],
+                    encoders: vec![Vec::new(); info.layer_count.max(1) as usize],
+                })
+                .collect(),
+        };
+
+        for (done, chunk) in chunks.into_iter().enumerate() {
+            job.check()?;
+            let values = manager.device_values(chunk.iter().copied()).await?;
+            job.progress(done as u32 + 1, total, values.first().and_then(ModelEntry::layer), None);
+            // Placed by the key asked for, not by what the device echoed
+            for (key, value) in chunk.into_iter().zip(values) {
+                match (key, value) {
+                    (ModelKey::Keymap { layer, row, .. }, ModelEntry::Keymap(e)) => file.keymap[layer as usize][row as usize].push(e),
+                    (ModelKey::Encoder { layer, .. }, ModelEntry::Encoder(e)) => file.encoders[layer as usize].push(e),
+                    (_, ModelEntry::Slider(c)) => file.sliders.push(c),
+                    (_, ModelEntry::MagneticSwitch(c)) => file.magnetic_switches.push(c),
+                    (ModelKey::SlaveKeymap { slave_addr, layer, row, .. }, ModelEntry::SlaveKeymap(e)) => {
+                        if let Some(slave) = file.slaves.iter_mut().find(|s| s.slave_addr == slave_addr) {
+                            slave.keymap[layer as usize][row as usize].push(e);
+                        }
+                    }
+                    (ModelKey::SlaveEncoder { slave_addr, layer, .. }, ModelEntry::SlaveEncoder(e)) => {
+                        if let Some(slave) = file.slaves.iter_mut().find(|s| s.slave_addr == slave_addr) {
+                            slave.encoders[layer as usize].push(e);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(file)
+    }
+
+    /// Load and parse a file; refuses versions this configurator doesn't know
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::io(format!("Failed to read {}: {}", path.display(), e)))?;
+        let file: ConfigFile = serde_json::from_str(&text)
+            .map_err(|e| ConfigError::invalid_input(format!("{} is not a configuration file: {}", path.display(), e)))?;
+        if file.version == 0 || file.version > CONFIG_FILE_VERSION {
+            return Err(ConfigError::invalid_input(format!(
+                "Configuration file version {} is not supported (this configurator reads up to {})",
+                file.version, CONFIG_FILE_VERSION
+            )));
+        }
+        Ok(file)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| ConfigError::io(format!("Failed to serialize configuration: {}", e)))?;
+        std::fs::write(path, json).map_err(|e| ConfigError::io(format!("Failed to write {}: {}", path.display(), e)))
+    }
+
+    /// Every value in the file, the layer state last so the board only switches
+    /// layers once everything else is in place
+    pub fn entries(&self) -> Vec<ModelEntry> {
+        let mut entries: Vec<ModelEntry> = self.keymap.iter().flatten().flatten().cloned().map(ModelEntry::Keymap).collect();
+        entries.extend(self.encoders.iter().flatten().cloned().map(ModelEntry::Encoder));
+        entries.extend(self.sliders.iter().cloned().map(ModelEntry::Slider));
+        entries.extend(self.magnetic_switches.iter().cloned().map(ModelEntry::MagneticSwitch));
+        for slave in &self.slaves {
+            entries.extend(slave.keymap.iter().flatten().flatten().cloned().map(ModelEntry::SlaveKeymap));
+            entries.extend(slave.encoders.iter().flatten().cloned().map(ModelEntry::SlaveEncoder));
+        }
+        entries.push(ModelEntry::LayerState(self.layer_state.clone()));
+        entries
+    }
+
+    /// Check the file fits `board`: the same dimensions, every slave present
+    /// and online with its own dimensions, and no value outside them
+    fn check_against(&self, board: &Board) -> Result<(), ConfigError> {
+        if !same_dimensions(&self.device_info, &board.info) {
+            return Err(ConfigError::invalid_input(format!(
+                "Configuration is for a {} board, the connected device is {}",
+                describe(&self.device_info),
+                describe(&board.info)
+            )));
+        }
+        for slave in &self.slaves {
+            match board.slaves.iter().find(|(addr, _)| *addr == slave.slave_addr) {
+                Some((_, info)) if same_dimensions(&slave.device_info, info) => {}
+                Some((_, info)) => {
+                    return Err(ConfigError::invalid_input(format!(
+                        "Configuration has slave 0x{:02X} as {}, the connected one is {}",
+                        slave.slave_addr,
+                        describe(&slave.device_info),
+                        describe(info)
+                    )))
+                }
+                None => {
+                    return Err(ConfigError::invalid_input(format!(
+                        "Configuration has slave 0x{:02X}, which isn't connected",
+                        slave.slave_addr
+                    )))
+                }
+            }
+        }
+
+        let keys: BTreeSet<ModelKey> = board.chunks().into_iter().flatten().collect();
+        if let Some(entry) = self.entries().iter().find(|e| !matches!(e, ModelEntry::LayerState(_)) && !keys.contains(&e.key())) {
+            return Err(ConfigError::invalid_input(format!("{:?} doesn't exist on the connected device", entry.key())));
+        }
+        Ok(())
+    }
+
+    /// Write the file to the connected board, all or nothing (see
+    /// `HidManager::write_transaction`), and return how many values it held
+    pub async fn apply(&self, manager: &HidManager, save: bool, job: &JobContext) -> Result<usize, ConfigError> {
+        let board = Board::read(manager).await?;
+        self.check_against(&board)?;
+        let entries = self.entries();
+        info!("Importing configuration '{}' ({} values)", self.device_info.device_name, entries.len());
+        manager.write_transaction("import_config", &entries, save, job).await?;
+        Ok(entries.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{connected_simulator, SimulatedDevice, SimulatorTopology};
+
+    fn board(slave_rows: u8) -> SimulatedDevice {
+        let topology: SimulatorTopology = serde_json::from_str(&format!(
+            r#"{{ "slaves": [{{ "address": 32, "matrix_rows": {}, "matrix_cols": 3, "encoder_count": 1 }}] }}"#,
+            slave_rows
+        ))
+        .unwrap();
+        SimulatedDevice::with_topology(&topology).unwrap()
+    }
+
+    #[tokio::test]
+    async fn exported_config_imports_onto_a_matching_board_only() {
+        let job = JobContext::detached("test");
+        let (source, _sim) = connected_simulator(board(2));
+        source.set_keymap_entry(&KeymapEntry { layer: 1, row: 2, col: 3, keycode: 0x2C }).await.unwrap();
+        let slave_key = SlaveKeymapEntry { slave_addr: 32, layer: 0, row: 1, col: 2, keycode: 0x2A };
+        source.set_slave_keymap_entry(&slave_key).await.unwrap();
+
+        let path = std::env::temp_dir().join(format!("og-config-{}.json", std::process::id()));
+        let exported = ConfigFile::read(&source, &job).await.unwrap();
+        assert_eq!((exported.keymap.len(), exported.keymap[0].len(), exported.keymap[0][0].len()), (4, 4, 4));
+        assert_eq!((exported.sliders.len(), exported.magnetic_switches.len()), (8, 8));
+        // Keymap layers were read in bulk, not key by key
+        let stats = source.link_stats();
+        assert!(stats.commands.iter().all(|c| c.command != Some(ConfigCommand::GetKeymap)));
+        exported.save(&path).unwrap();
+        let loaded = ConfigFile::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let (target, _sim) = connected_simulator(board(2));
+        assert_eq!(loaded.apply(&target, true, &job).await.unwrap(), loaded.entries().len());
+        assert_eq!(target.get_keymap_entry(1, 2, 3).await.unwrap().keycode, 0x2C);
+        assert_eq!(target.get_slave_keymap_entry(32, 0, 1, 2).await.unwrap(), slave_key);
+
+        // A slave with another matrix is refused before anything is written
+        let (other, _sim) = connected_simulator(board(1));
+        assert!(matches!(loaded.apply(&other, false, &job).await, Err(ConfigError::InvalidInput { .. })));
+        assert_ne!(other.get_keymap_entry(1, 2, 3).await.unwrap().keycode, 0x2C);
+    }
+}
@@ -0,0 +1,66 @@
+use crate::protocol::{ConfigCommand, ConfigPacket, StatusCode};
+use crate::trace::TraceDirection;
+use crate::transport::Report;
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One packet as the protocol console shows it, decoded as far as possible
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsoleEntry {
+    pub timestamp_ms: u64,
+    pub direction: TraceDirection,
+    /// `ConfigCommand` name, or the raw byte in hex if it's not one we know
+    pub command: String,
+    pub sequence: u8,
+    /// Only meaningful on responses
+    pub status: Option<StatusCode>,
+    pub unsolicited: bool,
+    /// The valid payload bytes as lowercase hex
+    pub payload: String,
+    /// Why the packet couldn't be decoded, if it couldn't
+    pub error: Option<String>,
+}
+
+impl ConsoleEntry {
+    pub fn decode(direction: TraceDirection, report: &Report) -> Self {
+        let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+
+        match ConfigPacket::from_bytes(report) {
+            Ok(packet) => ConsoleEntry {
+                timestamp_ms,
+                direction,
+                command: command_name(packet.command),
+                sequence: packet.sequence,
+                status: match direction {
+                    TraceDirection::Rx => packet.status().ok(),
+                    TraceDirection::Tx => None,
+                },
+                unsolicited: packet.is_unsolicited(),
+                payload: hex(packet.payload_bytes()),
+                error: None,
+            },
+            // Still show what arrived; the bytes are what matters when debugging a bad packet
+            Err(e) => ConsoleEntry {
+                timestamp_ms,
+                direction,
+                command: command_name(report[2]),
+                sequence: report[4],
+                status: None,
+                unsolicited: false,
+                payload: hex(report),
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+fn command_name(byte: u8) -> String {
+    match ConfigCommand::try_from(byte) {
+        Ok(command) => format!("{:?}", command),
+        Err(_) => format!("0x{:02X}", byte),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,209 @@
+use crate::error::ConfigError;
+use crate::protocol::BoardLayoutInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// File in the app data dir holding everything we remember about devices
+pub const DEVICE_STORE_FILE: &str = "devices.json";
+
+/// Stable id for a board: its serial number, or for boards without one a hash
+/// of path and product name (stable as long as it stays on the same port)
+pub fn device_key(serial_number: Option<&str>, path: &str, product_string: Option<&str>) -> String {
+    if let Some(serial) = serial_number.map(str::trim).filter(|s| !s.is_empty()) {
+        return serial.to_string();
+    }
+
+    // FNV-1a, so the id doesn't change with the Rust version like DefaultHasher may
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in path.bytes().chain([0]).chain(product_string.unwrap_or("").bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("path-{:016x}", hash)
+}
+
+/// What we keep per device between sessions
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceSettings {
+    /// Name the user gave the board
+    pub nickname: Option<String>,
+    /// Layout reported the last time it was connected, for showing it while offline
+    pub last_layout: Option<BoardLayoutInfo>,
+    /// Profile the user wants loaded for this board
+    pub preferred_profile: Option<String>,
+    /// Unix time in milliseconds of the last successful connection
+    pub last_connected_ms: Option<u64>,
+}
+
+/// A remembered device, as listed by `list_known_devices`
+#[derive(Debug, Clone, Serialize)]
+pub struct KnownDevice {
+    pub device_id: String,
+    pub last_used: bool,
+    #[serde(flatten)]
+    pub settings: DeviceSettings,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct StoredDevices {
+    /// Device most recently connected as the primary device
+    last_used: Option<String>,
+    devices: BTreeMap<String, DeviceSettings>,
+}
+
+/// Per-device settings persisted as JSON in the app data dir
+pub struct DeviceStore {
+    /// None keeps everything in memory only
+    path: Option<PathBuf>,
+    data: Mutex<StoredDevices>,
+}
+
+impl DeviceStore {
+    /// Load the store at `path`. A missing file is an empty store; an unreadable
+    /// one is logged and replaced on the next save rather than blocking startup.
+    pub fn open(path: PathBuf) -> Self {
+        let data = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+                warn!("Ignoring unreadable device settings {}: {}", path.display(), e);
+                StoredDevices::default()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => StoredDevices::default(),
+            Err(e) => {
+                warn!("Failed to read device settings {}: {}", path.display(), e);
+                StoredDevices::default()
+            }
+        };
+        DeviceStore { path: Some(path), data: Mutex::new(data) }
+    }
+
+    /// A store that forgets everything when the app exits
+    pub fn in_memory() -> Self {
+        DeviceStore { path: None, data: Mutex::new(StoredDevices::default()) }
+    }
+
+    pub fn settings(&self, device_id: &str) -> DeviceSettings {
+        self.data.lock().unwrap().devices.get(device_id).cloned().unwrap_or_default()
+    }
+
+    /// Device to try first when auto-connecting
+    pub fn last_used(&self) -> Option<String> {
+        self.data.lock().unwrap().last_used.clone()
+    }
+
+    /// Remembered devices, most recently connected first
+    pub fn known_devices(&self) -> Vec<KnownDevice> {
+        let data = self.data.lock().unwrap();
+        let mut devices: Vec<KnownDevice> = data
+            .devices
+            .iter()
+            .map(|(device_id, settings)| KnownDevice {
+                device_id: device_id.clone(),
+                last_used: data.last_used.as_deref() == Some(device_id.as_str()),
+                settings: settings.clone(),
+            })
+            .collect();
+        devices.sort_by_key(|d| std::cmp::Reverse(d.settings.last_connected_ms));
+        devices
+    }
+
+    /// Note a successful connection; `primary` makes it the auto-connect favourite
+    pub fn record_connection(&self, device_id: &str, primary: bool) -> Result<(), ConfigError> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+        self.update(|data| {
+            data.devices.entry(device_id.to_string()).or_default().last_connected_ms = Some(now);
+            if primary {
+                data.last_used = Some(device_id.to_string());
+            }
+        })
+    }
+
+    /// Change one device's settings and save
+    pub fn update_settings(&self, device_id: &str, change: impl FnOnce(&mut DeviceSettings)) -> Result<DeviceSettings, ConfigError> {
+        let mut updated = DeviceSettings::default();
+        self.update(|data| {
+            let settings = data.devices.entry(device_id.to_string()).or_default();
+            change(settings);
+            updated = settings.clone();
+        })?;
+        Ok(updated)
+    }
+
+    /// Forget a device entirely
+    pub fn forget(&self, device_id: &str) -> Result<bool, ConfigError> {
+        let mut removed = false;
+        self.update(|data| {
+            removed = data.devices.remove(device_id).is_some();
+            if data.last_used.as_deref() == Some(device_id) {
+                data.last_used = None;
+            }
+        })?;
+        Ok(removed)
+    }
+
+    fn update(&self, change: impl FnOnce(&mut StoredDevices)) -> Result<(), ConfigError> {
+        let mut data = self.data.lock().unwrap();
+        change(&mut data);
+        match &self.path {
+            Some(path) => save(path, &data),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Write via a temporary file so a crash mid-save can't leave half a file behind
+fn save(path: &Path, data: &StoredDevices) -> Result<(), ConfigError> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| ConfigError::io(format!("Failed to create {}: {}", dir.display(), e)))?;
+    }
+    let json = serde_json::to_string_pretty(data)
+        .map_err(|e| ConfigError::io(format!("Failed to serialize device settings: {}", e)))?;
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, json).map_err(|e| ConfigError::io(format!("Failed to write {}: {}", tmp.display(), e)))?;
+    std::fs::rename(&tmp, path).map_err(|e| ConfigError::io(format!("Failed to replace {}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_key_prefers_serial_and_hashes_otherwise() {
+        assert_eq!(device_key(Some("OG-1234"), "/dev/hidraw3", None), "OG-1234");
+
+        let a = device_key(None, "/dev/hidraw3", Some("OpenGrader Configuration"));
+        assert_eq!(a, device_key(Some("  "), "/dev/hidraw3", Some("OpenGrader Configuration")));
+        assert!(a.starts_with("path-"));
+        assert_ne!(a, device_key(None, "/dev/hidraw4", Some("OpenGrader Configuration")));
+        assert_ne!(a, device_key(None, "/dev/hidraw3", Some("Other")));
+    }
+
+    #[test]
+    fn settings_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("og-devices-{}", std::process::id())).join(DEVICE_STORE_FILE);
+        let _ = std::fs::remove_file(&path);
+
+        let store = DeviceStore::open(path.clone());
+        store.record_connection("OG-A", true).unwrap();
+        store.record_connection("OG-B", false).unwrap();
+        store.update_settings("OG-B", |s| s.nickname = Some("Desk".to_string())).unwrap();
+
+        let reopened = DeviceStore::open(path.clone());
+        assert_eq!(reopened.last_used().as_deref(), Some("OG-A"));
+        assert_eq!(reopened.settings("OG-B").nickname.as_deref(), Some("Desk"));
+        assert_eq!(reopened.known_devices().len(), 2);
+
+        assert!(reopened.forget("OG-A").unwrap());
+        assert_eq!(DeviceStore::open(path.clone()).last_used(), None);
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(DeviceStore::open(path.clone()).known_devices().is_empty());
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -0,0 +1,624 @@
+use crate::console::ConsoleEntry;
+use crate::error::ConfigError;
+use crate::events::DeviceEvent;
+use crate::protocol::*;
+use crate::retry::{RetryPolicy, RetrySettings};
+use crate::stats::{LinkHealth, LinkStats, LinkStatsSnapshot};
+use crate::trace::{TraceDirection, TraceRecorder};
+use crate::transport::{ConfigTransport, Report};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, oneshot, Semaphore};
+use tokio::time::timeout;
+use tracing::{debug, info, warn, Level};
+
+/// Requests allowed in flight at once unless configured otherwise
+pub const DEFAULT_PIPELINE_WINDOW: usize = 4;
+
+/// Upper bound for the window; sequence numbers are a u8 and must stay unique
+pub const MAX_PIPELINE_WINDOW: usize = 64;
+
+/// Longest the reader blocks in one read. HID writes share the device handle
+/// with the reader, so this is also the worst-case delay added to a write.
+const READ_POLL_MS: i32 = 5;
+
+/// Events buffered per subscriber before a slow one starts missing them
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Packets buffered per protocol console subscriber
+const CONSOLE_CHANNEL_CAPACITY: usize = 1024;
+
+type Waiter = oneshot::Sender<Result<ConfigPacket, ConfigError>>;
+
+/// Lets several requests be outstanding at once and routes each response to
+/// its caller by sequence number.
+///
+/// A reader thread owns the receive side of the transport while connected:
+/// responses go to whoever is waiting on their sequence number and packets
+/// flagged as unsolicited are published as `DeviceEvent`s.
+pub struct Dispatcher {
+    window: Mutex<Arc<Semaphore>>,
+    window_size: Mutex<usize>,
+    shared: Arc<Shared>,
+    /// Cleared to stop the current reader; the reader clears it itself when the transport fails
+    reader: Mutex<Option<Arc<AtomicBool>>>,
+    sequence_counter: Mutex<u8>,
+    retry: Mutex<RetryPolicy>,
+}
+
+/// State the reader thread needs alongside the dispatcher
+struct Shared {
+    pending: Mutex<HashMap<u8, Waiter>>,
+    recorder: Arc<Mutex<Option<Arc<TraceRecorder>>>>,
+    events: broadcast::Sender<DeviceEvent>,
+    /// Every packet sent or received, decoded, while the protocol console is open
+    console: broadcast::Sender<ConsoleEntry>,
+    console_enabled: AtomicBool,
+    stats: LinkStats,
+}
+
+/// Removes a sequence number from the pending map however the request ends
+struct PendingSlot<'a> {
+    dispatcher: &'a Dispatcher,
+    sequence: u8,
+}
+
+impl Drop for PendingSlot<'_> {
+    fn drop(&mut self) {
+        self.dispatcher.shared.pending.lock().unwrap().remove(&self.sequence);
+    }
+}
+
+impl Dispatcher {
+    pub fn new(recorder: Arc<Mutex<Option<Arc<TraceRecorder>>>>) -> Self {
+        Dispatcher {
+            window: Mutex::new(Arc::new(Semaphore::new(DEFAULT_PIPELINE_WINDOW))),
+            window_size: Mutex::new(DEFAULT_PIPELINE_WINDOW),
+            shared: Arc::new(Shared {
+                pending: Mutex::new(HashMap::new()),
+                recorder,
+                events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+                console: broadcast::channel(CONSOLE_CHANNEL_CAPACITY).0,
+                console_enabled: AtomicBool::new(false),
+                stats: LinkStats::default(),
+            }),
+            reader: Mutex::new(None),
+            sequence_counter: Mutex::new(0),
+            retry: Mutex::new(RetryPolicy::default()),
+        }
+    }
+
+    pub fn window_size(&self) -> usize {
+        *self.window_size.lock().unwrap()
+    }
+
+    /// Change how many requests may be in flight. Requests already holding a
+    /// slot in the old window finish normally.
+    pub fn set_window_size(&self, size: usize) -> Result<(), ConfigError> {
+        if !(1..=MAX_PIPELINE_WINDOW).contains(&size) {
+            return Err(ConfigError::invalid_input(format!(
+                "Pipeline window must be between 1 and {}, got {}",
+                MAX_PIPELINE_WINDOW, size
+            )));
+        }
+        *self.window.lock().unwrap() = Arc::new(Semaphore::new(size));
+        *self.window_size.lock().unwrap() = size;
+        Ok(())
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry.lock().unwrap().clone()
+    }
+
+    /// Replace the timeouts and retries; applies to requests sent from now on
+    pub fn set_retry_policy(&self, policy: RetryPolicy) -> Result<(), ConfigError> {
+        policy.validate()?;
+        *self.retry.lock().unwrap() = policy;
+        Ok(())
+    }
+
+    /// Start reading from a new connection, replacing any previous one
+    pub fn attach(&self, transport: Arc<dyn ConfigTransport>) {
+        self.detach();
+        // Restart sequence numbering so a session is reproducible from a trace
+        *self.sequence_counter.lock().unwrap() = 0;
+        self.shared.stats.reset();
+
+        let running = Arc::new(AtomicBool::new(true));
+        *self.reader.lock().unwrap() = Some(running.clone());
+        let shared = self.shared.clone();
+        std::thread::Builder::new()
+            .name("og-hid-reader".to_string())
+            .spawn(move || shared.read_loop(transport, running))
+            .expect("failed to spawn HID reader thread");
+    }
+
+    /// Stop the reader and fail everything still waiting with `NotConnected`
+    pub fn detach(&self) {
+        if let Some(running) = self.reader.lock().unwrap().take() {
+            running.store(false, Ordering::SeqCst);
+        }
+        // Dropping the senders wakes their waiters
+        self.shared.pending.lock().unwrap().clear();
+    }
+
+    /// False once the reader stopped, either on `detach` or because the transport failed
+    pub fn is_reading(&self) -> bool {
+        self.reader.lock().unwrap().as_ref().is_some_and(|running| running.load(Ordering::SeqCst))
+    }
+
+    /// Receive every unsolicited event from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.shared.events.subscribe()
+    }
+
+    /// Receive every packet from now on while the console is enabled
+    pub fn subscribe_console(&self) -> broadcast::Receiver<ConsoleEntry> {
+        self.shared.console.subscribe()
+    }
+
+    /// Decoding every packet for the console only happens while someone looks at it
+    pub fn set_console_enabled(&self, enabled: bool) {
+        self.shared.console_enabled.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Link counters since this connection was attached (or the stats were reset)
+    pub fn link_stats(&self) -> LinkStatsSnapshot {
+        self.shared.stats.snapshot()
+    }
+
+    pub fn reset_link_stats(&self) {
+        self.shared.stats.reset();
+    }
+
+    /// Link quality since the previous call, for the periodic health report
+    pub fn link_health(&self, device_id: Option<String>, interval: Duration) -> LinkHealth {
+        self.shared.stats.health(device_id, interval)
+    }
+
+    /// Publish an event produced on the host side, as if the device had sent it
+    pub fn publish(&self, event: DeviceEvent) {
+        let _ = self.shared.events.send(event);
+    }
+
+    /// Claim the next sequence number that isn't already in flight
+    fn register(&self) -> Result<(u8, oneshot::Receiver<Result<ConfigPacket, ConfigError>>), ConfigError> {
+        let (tx, rx) = oneshot::channel();
+        let mut pending = self.shared.pending.lock().unwrap();
+        // Checked under the pending lock: a failing reader stops first, then clears pending
+        if !self.is_reading() {
+            return Err(ConfigError::NotConnected);
+        }
+        let mut seq = self.sequence_counter.lock().unwrap();
+        loop {
+            *seq = seq.wrapping_add(1);
+            if let Entry::Vacant(slot) = pending.entry(*seq) {
+                slot.insert(tx);
+                return Ok((*seq, rx));
+            }
+        }
+    }
+
+    /// Send one request and wait for its response, resending on silence and
+    /// backing off while the device reports `Busy`, as the retry policy says
+    #[tracing::instrument(level = "debug", name = "command", skip(self, transport, payload), fields(len = payload.len()))]
+    pub async fn execute(
+        &self,
+        transport: Arc<dyn ConfigTransport>,
+        command: ConfigCommand,
+        payload: &[u8],
+    ) -> Result<ConfigPacket, ConfigError> {
+        let window = self.window.lock().unwrap().clone();
+        let _permit = window
+            .acquire_owned()
+            .await
+            .map_err(|_| ConfigError::io("Command window closed"))?;
+
+        let settings = self.retry.lock().unwrap().for_command(command);
+        self.shared.stats.request(command);
+        let mut busy_retries = 0;
+        loop {
+            let response = self.exchange(&transport, command, payload, &settings).await?;
+            if matches!(response.status(), Ok(StatusCode::Busy)) && busy_retries < settings.busy_retries {
+                busy_retries += 1;
+                self.shared.stats.busy_retry(command);
+                debug!(retry = busy_retries, of = settings.busy_retries, "device busy");
+                tokio::time::sleep(settings.backoff(busy_retries as u32)).await;
+                continue;
+            }
+            return Ok(response);
+        }
+    }
+
+    /// One request under its own sequence number, resent until answered or out of attempts
+    #[tracing::instrument(level = "debug", skip_all, fields(seq = tracing::field::Empty))]
+    async fn exchange(
+        &self,
+        transport: &Arc<dyn ConfigTransport>,
+        command: ConfigCommand,
+        payload: &[u8],
+        settings: &RetrySettings,
+    ) -> Result<ConfigPacket, ConfigError> {
+        let (sequence, mut rx) = self.register()?;
+        let _slot = PendingSlot { dispatcher: self, sequence };
+        tracing::Span::current().record("seq", sequence);
+        let packet_bytes = ConfigPacket::new(command, sequence, payload)?.to_bytes();
+
+        let started = Instant::now();
+        let mut attempt: u8 = 0;
+        loop {
+            attempt += 1;
+            // Recorded first: the reader may log the response before write_report returns
+            self.shared.record(TraceDirection::Tx, &packet_bytes);
+            transport.write_report(&packet_bytes)?;
+
+            match timeout(settings.timeout(), &mut rx).await {
+                Ok(Ok(routed)) => {
+                    let response = routed?;
+                    response.check_echo(command)?;
+                    self.shared.stats.response(command, started.elapsed());
+                    return Ok(response);
+                }
+                // The reader went away (disconnect or transport failure)
+                Ok(Err(_closed)) => return Err(ConfigError::NotConnected),
+                Err(_elapsed) => {
+                    warn!(?command, sequence, attempt, of = settings.attempts, "no response after {}ms", settings.timeout_ms);
+                    if attempt < settings.attempts {
+                        // Resend with the same sequence; a late answer to the first send still counts
+                        self.shared.stats.retry(command);
+                        tokio::time::sleep(settings.backoff(attempt as u32)).await;
+                        continue;
+                    }
+                    self.shared.stats.timeout(command);
+                    return Err(ConfigError::Timeout { command, sequence });
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Dispatcher {
+    fn drop(&mut self) {
+        self.detach();
+    }
+}
+
+impl Shared {
+    /// Write a packet to the trace file, the debug log and the protocol console
+    fn record(&self, dir: TraceDirection, report: &Report) {
+        let recorder = self.recorder.lock().unwrap().clone();
+        if let Some(recorder) = recorder {
+            recorder.record(dir, report);
+        }
+
+        let console = self.console_enabled.load(Ordering::SeqCst);
+        if console || tracing::enabled!(Level::DEBUG) {
+            let entry = ConsoleEntry::decode(dir, report);
+            debug!(
+                dir = ?entry.direction,
+                command = %entry.command,
+                seq = entry.sequence,
+                status = ?entry.status,
+                unsolicited = entry.unsolicited,
+                payload = %entry.payload,
+                "packet"
+            );
+            if console {
+                let _ = self.console.send(entry);
+            }
+        }
+    }
+
+    /// Body of the reader thread; runs until `running` is cleared or the transport fails
+    fn read_loop(&self, transport: Arc<dyn ConfigTransport>, running: Arc<AtomicBool>) {
+        while running.load(Ordering::SeqCst) {
+            match transport.read_report(READ_POLL_MS) {
+                // Detached while we were blocked: this report belongs to the old connection
+                Ok(Some(_)) if !running.load(Ordering::SeqCst) => break,
+                Ok(Some(data)) => self.route(&data),
+                // Not every transport blocks for the timeout
+                Ok(None) => std::thread::sleep(Duration::from_millis(1)),
+                Err(e) => {
+                    info!("HID reader stopped: {}", e);
+                    // A reader that was already detached must leave the next connection's waiters alone
+                    if running.swap(false, Ordering::SeqCst) {
+                        self.pending.lock().unwrap().clear();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hand one report to its waiter, or publish it if the device sent it on its own
+    fn route(&self, data: &Report) {
+        self.record(TraceDirection::Rx, data);
+
+        // A corrupt packet still carries a sequence byte; fail that request instead of stalling it
+        let (sequence, routed) = match ConfigPacket::from_bytes(data) {
+            Ok(packet) if packet.is_unsolicited() => {
+                // No subscribers is fine, the event is simply dropped
+                let _ = self.events.send(DeviceEvent::from_packet(&packet));
+                return;
+            }
+            Ok(response) => (response.sequence, Ok(response)),
+            Err(e) => (data[4], Err(e)),
+        };
+        match self.pending.lock().unwrap().remove(&sequence) {
+            Some(waiter) => {
+                let _ = waiter.send(routed);
+            }
+            None => {
+                debug!(sequence, "dropping response nobody is waiting for");
+                if let Ok(command) = ConfigCommand::try_from(data[2]) {
+                    self.stats.unexpected_sequence(command);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hid_manager::HidManager;
+    use crate::simulator::{connected_simulator, SimulatedDevice, SimulatorTransport};
+    use futures::future::try_join_all;
+    use std::collections::VecDeque;
+
+    /// Holds responses back until `batch` requests are outstanding, then
+    /// releases them newest first
+    struct ReorderingTransport {
+        inner: SimulatorTransport,
+        batch: usize,
+        held: Mutex<Vec<Report>>,
+        released: Mutex<VecDeque<Report>>,
+    }
+
+    impl ConfigTransport for ReorderingTransport {
+        fn write_report(&self, report: &Report) -> Result<(), ConfigError> {
+            self.inner.write_report(report)?;
+            let response = self.inner.read_report(0)?.unwrap();
+            let mut held = self.held.lock().unwrap();
+            held.push(response);
+            if held.len() == self.batch {
+                self.released.lock().unwrap().extend(held.drain(..).rev());
+            }
+            Ok(())
+        }
+
+        fn read_report(&self, _timeout_ms: i32) -> Result<Option<Report>, ConfigError> {
+            Ok(self.released.lock().unwrap().pop_front())
+        }
+    }
+
+    fn connect_reordering(batch: usize) -> HidManager {
+        let manager = HidManager::new().unwrap();
+        manager.connect_transport(Arc::new(ReorderingTransport {
+            inner: SimulatorTransport::new(SimulatedDevice::new()),
+            batch,
+            held: Mutex::new(Vec::new()),
+            released: Mutex::new(VecDeque::new()),
+        }));
+        manager
+    }
+
+    #[tokio::test]
+    async fn out_of_order_responses_reach_their_callers() {
+        let manager = connect_reordering(4);
+        manager.set_pipeline_window(4).unwrap();
+
+        // Only answered once all four are in flight, and then in reverse order
+        let entries = try_join_all((0..4).map(|col| manager.get_keymap_entry(0, 1, col))).await.unwrap();
+        let keycodes: Vec<u16> = entries.iter().map(|e| e.keycode).collect();
+        assert_eq!(keycodes, vec![0x04 + 4, 0x04 + 5, 0x04 + 6, 0x04 + 7]);
+        assert!(entries.iter().enumerate().all(|(col, e)| e.col as usize == col));
+    }
+
+    #[tokio::test]
+    async fn window_limits_requests_in_flight() {
+        // Needs both requests plus the first one's retry before anything comes back
+        let manager = connect_reordering(3);
+        manager.set_pipeline_window(1).unwrap();
+
+        // With one slot the second request is never sent, so the first can't complete
+        let result = try_join_all((0..2).map(|col| manager.get_keymap_entry(0, 0, col))).await;
+        assert!(matches!(result, Err(ConfigError::Timeout { .. })));
+
+        assert!(manager.set_pipeline_window(0).is_err());
+        assert!(manager.set_pipeline_window(MAX_PIPELINE_WINDOW + 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn unsolicited_events_are_published_not_routed() {
+        let (manager, transport) = connected_simulator(SimulatedDevice::new());
+        let mut events = manager.subscribe_events();
+
+        // Queued ahead of the response, so the reader has to sort them out of the way
+        transport.push_event(ConfigCommand::KeyEvent, &[2, 5, 1]);
+        transport.push_event(ConfigCommand::GetSliderValue, &[1, 99]);
+        transport.push_event(ConfigCommand::GetLayerState, &[0b11, 1]);
+        transport.push_event(ConfigCommand::MidiNoteOn, &[0x90, 60]);
+        let entry = manager.get_keymap_entry(0, 0, 0).await.unwrap();
+        assert_eq!(entry.keycode, 0x04);
+
+        let mut names = Vec::new();
+        for _ in 0..4 {
+            let event = timeout(Duration::from_secs(1), events.recv()).await.unwrap().unwrap();
+            names.push(event.tauri_event());
+            if let DeviceEvent::KeyEvent { row, col, pressed } = event {
+                assert_eq!((row, col, pressed), (2, 5, true));
+            }
+        }
+        assert_eq!(names, vec!["og:key-event", "og:slider-moved", "og:layer-changed", "og:device-event"]);
+    }
+
+    #[tokio::test]
+    async fn console_shows_decoded_packets_only_while_enabled() {
+        let (manager, _sim) = connected_simulator(SimulatedDevice::new());
+        let mut packets = manager.subscribe_console();
+
+        manager.get_keymap_entry(0, 0, 0).await.unwrap();
+        assert!(packets.try_recv().is_err());
+
+        manager.set_protocol_console(true);
+        manager.get_keymap_entry(0, 0, 1).await.unwrap();
+        let request = timeout(Duration::from_secs(1), packets.recv()).await.unwrap().unwrap();
+        let response = timeout(Duration::from_secs(1), packets.recv()).await.unwrap().unwrap();
+        assert_eq!((request.direction, request.command.as_str()), (TraceDirection::Tx, "GetKeymap"));
+        assert_eq!(request.payload, "000001");
+        assert_eq!(request.status, None);
+        assert_eq!((response.direction, response.status), (TraceDirection::Rx, Some(StatusCode::Ok)));
+        assert_eq!(response.sequence, request.sequence);
+    }
+
+    /// Answers nothing and fails every read, like an unplugged device
+    struct UnpluggedTransport;
+
+    impl ConfigTransport for UnpluggedTransport {
+        fn write_report(&self, _report: &Report) -> Result<(), ConfigError> {
+            Ok(())
+        }
+
+        fn read_report(&self, _timeout_ms: i32) -> Result<Option<Report>, ConfigError> {
+            std::thread::sleep(Duration::from_millis(20));
+            Err(ConfigError::io("device unplugged"))
+        }
+    }
+
+    /// Answers the first `busy` requests with `Busy`, then lets the simulator handle them
+    struct BusyTransport {
+        inner: SimulatorTransport,
+        busy: Mutex<u32>,
+        queued: Mutex<VecDeque<Report>>,
+    }
+
+    impl ConfigTransport for BusyTransport {
+        fn write_report(&self, report: &Report) -> Result<(), ConfigError> {
+            let mut busy = self.busy.lock().unwrap();
+            if *busy == 0 {
+                return self.inner.write_report(report);
+            }
+            *busy -= 1;
+            let mut response = ConfigPacket::from_bytes(report)?;
+            response.status = StatusCode::Busy as u8;
+            response.payload_length = 0;
+            self.queued.lock().unwrap().push_back(response.to_bytes());
+            Ok(())
+        }
+
+        fn read_report(&self, timeout_ms: i32) -> Result<Option<Report>, ConfigError> {
+            match self.queued.lock().unwrap().pop_front() {
+                Some(report) => Ok(Some(report)),
+                None => self.inner.read_report(timeout_ms),
+            }
+        }
+    }
+
+    fn connect_busy(busy: u32) -> HidManager {
+        let manager = HidManager::new().unwrap();
+        manager.connect_transport(Arc::new(BusyTransport {
+            inner: SimulatorTransport::new(SimulatedDevice::new()),
+            busy: Mutex::new(busy),
+            queued: Mutex::new(VecDeque::new()),
+        }));
+        manager
+    }
+
+    #[tokio::test]
+    async fn busy_responses_are_retried_within_the_policy() {
+        let manager = connect_busy(2);
+        assert_eq!(manager.get_keymap_entry(0, 0, 0).await.unwrap().keycode, 0x04);
+        let stats = manager.link_stats().totals;
+        assert_eq!((stats.requests, stats.busy_retries, stats.timeouts), (1, 2, 0));
+
+        let manager = connect_busy(2);
+        let mut policy = RetryPolicy::default();
+        policy.overrides.insert(ConfigCommand::GetKeymap, RetrySettings { busy_retries: 1, ..RetrySettings::DIRECT });
+        manager.set_retry_policy(policy).unwrap();
+        let result = manager.get_keymap_entry(0, 0, 0).await;
+        assert!(
+            matches!(result, Err(ConfigError::DeviceStatus { status: StatusCode::Busy, .. })),
+            "{:?}",
+            result
+        );
+    }
+
+    /// Blocks in `read_report` until `fail` is set, then fails, like a device
+    /// unplugged in the middle of a read
+    #[derive(Default)]
+    struct StuckTransport {
+        fail: AtomicBool,
+    }
+
+    impl ConfigTransport for StuckTransport {
+        fn write_report(&self, _report: &Report) -> Result<(), ConfigError> {
+            Ok(())
+        }
+
+        fn read_report(&self, _timeout_ms: i32) -> Result<Option<Report>, ConfigError> {
+            while !self.fail.load(Ordering::SeqCst) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            Err(ConfigError::io("device unplugged"))
+        }
+    }
+
+    /// Holds every response back until `open` is set
+    struct GatedTransport {
+        inner: SimulatorTransport,
+        open: AtomicBool,
+    }
+
+    impl ConfigTransport for GatedTransport {
+        fn write_report(&self, report: &Report) -> Result<(), ConfigError> {
+            self.inner.write_report(report)
+        }
+
+        fn read_report(&self, timeout_ms: i32) -> Result<Option<Report>, ConfigError> {
+            if !self.open.load(Ordering::SeqCst) {
+                std::thread::sleep(Duration::from_millis(1));
+                return Ok(None);
+            }
+            self.inner.read_report(timeout_ms)
+        }
+    }
+
+    #[tokio::test]
+    async fn replaced_reader_failing_late_leaves_the_new_connection_alone() {
+        let dispatcher = Arc::new(Dispatcher::new(Arc::new(Mutex::new(None))));
+        let old = Arc::new(StuckTransport::default());
+        dispatcher.attach(old.clone());
+        let new = Arc::new(GatedTransport { inner: SimulatorTransport::new(SimulatedDevice::new()), open: AtomicBool::new(false) });
+        dispatcher.attach(new.clone());
+
+        let request = tokio::spawn({
+            let (dispatcher, transport) = (dispatcher.clone(), new.clone() as Arc<dyn ConfigTransport>);
+            async move { dispatcher.execute(transport, ConfigCommand::GetKeymap, &[0, 0, 0]).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        // The old reader finally sees its device gone while the new request is in flight
+        old.fail.store(true, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        new.open.store(true, Ordering::SeqCst);
+
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(KeymapEntry::from_payload(response.payload_bytes()).unwrap().keycode, 0x04);
+        assert!(dispatcher.is_reading());
+    }
+
+    #[tokio::test]
+    async fn reader_failure_fails_waiters_without_a_timeout() {
+        let manager = HidManager::new().unwrap();
+        manager.connect_transport(Arc::new(UnpluggedTransport));
+
+        let started = std::time::Instant::now();
+        let result = manager.get_keymap_entry(0, 0, 0).await;
+        assert!(matches!(result, Err(ConfigError::NotConnected)), "{:?}", result);
+        assert!(started.elapsed() < Duration::from_millis(800));
+        assert!(!manager.is_connected());
+    }
+}
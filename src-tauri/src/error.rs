@@ -0,0 +1,215 @@
+use crate::model::{ModelEntry, ModelKey};
+use crate::protocol::{ConfigCommand, StatusCode};
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+
+/// Every way a backend operation can fail.
+///
+/// Serialized for the frontend as `{ "kind": "<Variant>", "message": "...", ...fields }`
+/// so the UI can branch on `kind` while still having a readable message to show.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum ConfigError {
+    #[error("No device connected")]
+    NotConnected,
+
+    #[error("No connected device with id {device_id}")]
+    UnknownDevice { device_id: String },
+
+    #[error("Command timeout: {command:?} (seq {sequence})")]
+    Timeout { command: ConfigCommand, sequence: u8 },
+
+    #[error("Device returned error: {status:?} for {command:?}")]
+    DeviceStatus { command: ConfigCommand, status: StatusCode },
+
+    #[error("{command:?} is not supported by the connected device")]
+    Unsupported { command: ConfigCommand },
+
+    #[error("Device speaks protocol v{device}, configurator supports v{min}-v{max}")]
+    IncompatibleProtocol { device: u8, min: u8, max: u8 },
+
+    #[error("Unknown command byte 0x{byte:02X}")]
+    UnknownCommand { byte: u8 },
+
+    #[error("Unknown status byte 0x{byte:02X}")]
+    UnknownStatus { byte: u8 },
+
+    #[error("Response echoes command 0x{actual:02X}, expected {expected:?}")]
+    CommandMismatch { expected: ConfigCommand, actual: u8 },
+
+    #[error("Payload length {length} exceeds the {max}-byte maximum")]
+    PayloadLength { length: u8, max: usize },
+
+    #[error("Payload of {length} bytes exceeds the {max}-byte limit")]
+    PayloadTooLarge { length: usize, max: usize },
+
+    #[error("Checksum mismatch in transfer chunk at offset {offset}")]
+    ChecksumMismatch { offset: u16 },
+
+    #[error("{what} payload truncated: expected {expected} bytes, got {actual}")]
+    Truncated { what: &'static str, expected: usize, actual: usize },
+
+    #[error("Protocol error: {reason}")]
+    Protocol { reason: String },
+
+    #[error("I/O error: {message}")]
+    Io { message: String },
+
+    #[error("Invalid input: {message}")]
+    InvalidInput { message: String },
+
+    #[error("Job {job_id} was cancelled")]
+    Cancelled { job_id: u64 },
+
+    /// A bulk write didn't go through completely. With `rolled_back` the device
+    /// holds what it held before; otherwise `written` lists the cells that kept
+    /// the new value because restoring them failed too, and `unknown` the failed
+    /// writes whose restore failed as well, which may hold either value.
+    #[error("{label}: {} of {total} writes failed{}", .failed.len(), if *.rolled_back { ", rolled back" } else { "" })]
+    WriteFailed {
+        label: String,
+        total: usize,
+        failed: Vec<EntryFailure>,
+        written: Vec<ModelKey>,
+        unknown: Vec<ModelKey>,
+        rolled_back: bool,
+    },
+
+    /// With write verification on: the device acked a write but reads back something else
+    #[error("Read-back of {key:?} doesn't match what was written")]
+    VerifyFailed { key: ModelKey, written: ModelEntry, read: ModelEntry },
+}
+
+/// One cell a bulk write couldn't write, and why
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EntryFailure {
+    pub key: ModelKey,
+    pub message: String,
+}
+
+impl ConfigError {
+    pub fn io(message: impl Into<String>) -> Self {
+        ConfigError::Io { message: message.into() }
+    }
+
+    pub fn protocol(reason: impl Into<String>) -> Self {
+        ConfigError::Protocol { reason: reason.into() }
+    }
+
+    pub fn invalid_input(message: impl Into<String>) -> Self {
+        ConfigError::InvalidInput { message: message.into() }
+    }
+
+    /// Check that `payload` holds at least `expected` bytes
+    pub fn check_len(what: &'static str, payload: &[u8], expected: usize) -> Result<(), Self> {
+        if payload.len() < expected {
+            return Err(ConfigError::Truncated { what, expected, actual: payload.len() });
+        }
+        Ok(())
+    }
+
+    /// Variant name used as the `kind` tag on the wire
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ConfigError::NotConnected => "NotConnected",
+            ConfigError::UnknownDevice { .. } => "UnknownDevice",
+            ConfigError::Timeout { .. } => "Timeout",
+            ConfigError::DeviceStatus { .. } => "DeviceStatus",
+            ConfigError::Unsupported { .. } => "Unsupported",
+            ConfigError::IncompatibleProtocol { .. } => "IncompatibleProtocol",
+            ConfigError::UnknownCommand { .. } => "UnknownCommand",
+            ConfigError::UnknownStatus { .. } => "UnknownStatus",
+            ConfigError::CommandMismatch { .. } => "CommandMismatch",
+            ConfigError::PayloadLength { .. } => "PayloadLength",
+            ConfigError::PayloadTooLarge { .. } => "PayloadTooLarge",
+            ConfigError::ChecksumMismatch { .. } => "ChecksumMismatch",
+            ConfigError::Truncated { .. } => "Truncated",
+            ConfigError::Protocol { .. } => "Protocol",
+            ConfigError::Io { .. } => "Io",
+            ConfigError::InvalidInput { .. } => "InvalidInput",
+            ConfigError::Cancelled { .. } => "Cancelled",
+            ConfigError::WriteFailed { .. } => "WriteFailed",
+            ConfigError::VerifyFailed { .. } => "VerifyFailed",
+        }
+    }
+}
+
+impl Serialize for ConfigError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("kind", self.kind())?;
+        map.serialize_entry("message", &self.to_string())?;
+        match self {
+            ConfigError::NotConnected => {}
+            ConfigError::UnknownDevice { device_id } => map.serialize_entry("device_id", device_id)?,
+            ConfigError::Timeout { command, sequence } => {
+                map.serialize_entry("command", command)?;
+                map.serialize_entry("sequence", sequence)?;
+            }
+            ConfigError::DeviceStatus { command, status } => {
+                map.serialize_entry("command", command)?;
+                map.serialize_entry("status", status)?;
+            }
+            ConfigError::Unsupported { command } => map.serialize_entry("command", command)?,
+            ConfigError::IncompatibleProtocol { device, min, max } => {
+                map.serialize_entry("device", device)?;
+                map.serialize_entry("min", min)?;
+                map.serialize_entry("max", max)?;
+            }
+            ConfigError::UnknownCommand { byte } | ConfigError::UnknownStatus { byte } => {
+                map.serialize_entry("byte", byte)?;
+            }
+            ConfigError::CommandMismatch { expected, actual } => {
+                map.serialize_entry("expected", expected)?;
+                map.serialize_entry("actual", actual)?;
+            }
+            ConfigError::PayloadLength { length, max } => {
+                map.serialize_entry("length", length)?;
+                map.serialize_entry("max", max)?;
+            }
+            ConfigError::PayloadTooLarge { length, max } => {
+                map.serialize_entry("length", length)?;
+                map.serialize_entry("max", max)?;
+            }
+            ConfigError::ChecksumMismatch { offset } => map.serialize_entry("offset", offset)?,
+            ConfigError::Truncated { what, expected, actual } => {
+                map.serialize_entry("what", what)?;
+                map.serialize_entry("expected", expected)?;
+                map.serialize_entry("actual", actual)?;
+            }
+            ConfigError::Protocol { reason } => map.serialize_entry("reason", reason)?,
+            ConfigError::Io { .. } | ConfigError::InvalidInput { .. } => {}
+            ConfigError::Cancelled { job_id } => map.serialize_entry("job_id", job_id)?,
+            ConfigError::WriteFailed { label, total, failed, written, unknown, rolled_back } => {
+                map.serialize_entry("label", label)?;
+                map.serialize_entry("total", total)?;
+                map.serialize_entry("failed", failed)?;
+                map.serialize_entry("written", written)?;
+                map.serialize_entry("unknown", unknown)?;
+                map.serialize_entry("rolled_back", rolled_back)?;
+            }
+            ConfigError::VerifyFailed { key, written, read } => {
+                map.serialize_entry("key", key)?;
+                map.serialize_entry("written", written)?;
+                map.serialize_entry("read", read)?;
+            }
+        }
+        map.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_as_tagged_object() {
+        let busy = ConfigError::DeviceStatus { command: ConfigCommand::SetKeymap, status: StatusCode::Busy };
+        let json = serde_json::to_value(&busy).unwrap();
+        assert_eq!(json["kind"], "DeviceStatus");
+        assert_eq!(json["status"], serde_json::to_value(StatusCode::Busy).unwrap());
+        assert!(json["message"].as_str().unwrap().contains("Busy"));
+
+        let json = serde_json::to_value(ConfigError::NotConnected).unwrap();
+        assert_eq!(json["kind"], "NotConnected");
+    }
+}
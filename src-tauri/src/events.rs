@@ -0,0 +1,74 @@
+use crate::protocol::{ConfigCommand, ConfigPacket, LayerState};
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One slider or potentiometer position
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SliderReading {
+    pub slider_id: u8,
+    pub value: u8,
+}
+
+/// Something the device reported on its own, decoded from an unsolicited packet
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DeviceEvent {
+    /// Active layers changed on the device (layer key, MIDI, ...)
+    LayerChanged { active_mask: u8, default_layer: u8 },
+    SliderMoved { slider_id: u8, value: u8 },
+    /// Every streamed slider at once; `timestamp_ms` is host time (Unix epoch) at reception
+    SliderValues { timestamp_ms: u64, values: Vec<SliderReading> },
+    KeyEvent { row: u8, col: u8, pressed: bool },
+    /// An event this configurator doesn't know how to decode, passed on as-is
+    Other { command: u8, payload: Vec<u8> },
+}
+
+impl DeviceEvent {
+    /// Decode an unsolicited packet; anything unexpected becomes `Other`
+    pub fn from_packet(packet: &ConfigPacket) -> Self {
+        let payload = packet.payload_bytes();
+        match (packet.command(), payload) {
+            (Ok(ConfigCommand::GetLayerState | ConfigCommand::SetLayerState), _) => {
+                if let Ok(state) = LayerState::from_payload(payload) {
+                    return DeviceEvent::LayerChanged {
+                        active_mask: state.active_mask,
+                        default_layer: state.default_layer,
+                    };
+                }
+            }
+            (Ok(ConfigCommand::GetSliderValue), &[slider_id, value, ..]) => {
+                return DeviceEvent::SliderMoved { slider_id, value };
+            }
+            // One value per slider, indexed by slider id
+            (Ok(ConfigCommand::SliderValues), _) => {
+                let values = (0..).zip(payload).map(|(slider_id, &value)| SliderReading { slider_id, value }).collect();
+                return DeviceEvent::slider_values(values);
+            }
+            (Ok(ConfigCommand::KeyEvent), &[row, col, pressed, ..]) => {
+                return DeviceEvent::KeyEvent { row, col, pressed: pressed != 0 };
+            }
+            _ => {}
+        }
+        DeviceEvent::Other {
+            command: packet.command,
+            payload: payload.to_vec(),
+        }
+    }
+
+    /// Slider readings stamped with the current host time
+    pub fn slider_values(values: Vec<SliderReading>) -> Self {
+        let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        DeviceEvent::SliderValues { timestamp_ms, values }
+    }
+
+    /// Name of the Tauri event the frontend listens for
+    pub fn tauri_event(&self) -> &'static str {
+        match self {
+            DeviceEvent::LayerChanged { .. } => "og:layer-changed",
+            DeviceEvent::SliderMoved { .. } => "og:slider-moved",
+            DeviceEvent::SliderValues { .. } => "og:slider-values",
+            DeviceEvent::KeyEvent { .. } => "og:key-event",
+            DeviceEvent::Other { .. } => "og:device-event",
+        }
+    }
+}
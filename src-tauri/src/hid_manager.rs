@@ -1,13 +1,67 @@
+use crate::console::ConsoleEntry;
+use crate::device_store::device_key;
+use crate::dispatcher::Dispatcher;
+use crate::error::{ConfigError, EntryFailure};
+use crate::events::{DeviceEvent, SliderReading};
+use crate::history::{Change, History, HistoryStep};
+use crate::hotplug::HotplugEvent;
+use crate::jobs::JobContext;
+use crate::model::{DeviceModel, ModelEntry, ModelKey};
 use crate::protocol::*;
-use hidapi::{HidApi, HidDevice};
-use std::sync::{Arc, Mutex};
+use crate::replay::ReplayTransport;
+use crate::retry::RetryPolicy;
+use crate::simulator::{SimulatedDevice, SimulatorTransport, SIMULATOR_DEVICE_PATH, SIMULATOR_SERIAL};
+use crate::stats::{LinkHealth, LinkStatsSnapshot};
+use crate::trace::TraceRecorder;
+use crate::transport::{ConfigTransport, HidTransport};
+use futures::future::try_join_all;
+use hidapi::HidApi;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::Duration;
-use tokio::time::timeout;
-use tokio::sync::Semaphore;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
 
 pub const OPENGRADER_VID: u16 = 0xCAFE; // Matches firmware USB_VID in usb_descriptors.c
 pub const OPENGRADER_PID: u16 = 0x4011; // Matches firmware USB_PID in usb_descriptors.c
 
+/// How often one chunk of a large transfer is resent before giving up
+const LARGE_TRANSFER_RETRANSMITS: u32 = 3;
+
+/// Slider update interval when the frontend doesn't ask for one
+pub const SLIDER_STREAM_DEFAULT_INTERVAL_MS: u16 = 50;
+/// Fastest and slowest slider update intervals accepted
+pub const SLIDER_STREAM_INTERVAL_RANGE_MS: std::ops::RangeInclusive<u16> = 10..=1000;
+
+/// Where live slider values come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SliderStreamMode {
+    /// The firmware pushes `SliderValues` events
+    Device,
+    /// Older firmware: a backend task reads every slider each interval
+    Polling,
+}
+
+enum SliderStream {
+    Device,
+    Polling(JoinHandle<()>),
+}
+
+/// What `handle_hotplug` did about a device coming or going
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotplugAction {
+    None,
+    /// The connected device was unplugged
+    Disconnected,
+    /// The last used device came back and is connected again
+    Reconnected,
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct DeviceDescriptor {
     pub vendor_id: u16,
@@ -19,33 +73,74 @@ pub struct DeviceDescriptor {
     pub usage_page: u16,
 }
 
+impl DeviceDescriptor {
+    /// Stable id of the board this interface belongs to, see `device_key`
+    pub fn device_id(&self) -> String {
+        device_key(self.serial_number.as_deref(), &self.path, self.product_string.as_deref())
+    }
+}
+
+/// Which physical device a connection was opened on
+#[derive(Debug, Clone)]
+struct ConnectedDevice {
+    path: String,
+    device_id: String,
+}
+
 pub struct HidManager {
     api: Arc<Mutex<HidApi>>,
-    device: Arc<Mutex<Option<HidDevice>>>,
-    connected_path: Arc<Mutex<Option<String>>>,
-    sequence_counter: Arc<Mutex<u8>>,
-    is_mock_device: Arc<Mutex<bool>>,
-    cmd_sem: Arc<Semaphore>,
+    transport: Arc<Mutex<Option<Arc<dyn ConfigTransport>>>>,
+    dispatcher: Arc<Dispatcher>,
+    recorder: Arc<Mutex<Option<Arc<TraceRecorder>>>>,
+    capabilities: Arc<Mutex<Option<DeviceCapabilities>>>,
+    /// Commands the device answered as missing since connecting, so fallbacks
+    /// probe once instead of on every call
+    missing_commands: Arc<Mutex<HashSet<ConfigCommand>>>,
+    slider_stream: Arc<Mutex<Option<SliderStream>>>,
+    /// Device opened by `connect`, while connected
+    connected: Arc<Mutex<Option<ConnectedDevice>>>,
+    /// Id of the device most recently opened by `connect`
+    last_device_id: Arc<Mutex<Option<String>>>,
+    auto_reconnect: Arc<AtomicBool>,
+    /// Read every configuration write back and compare, see `set_verify_writes`
+    verify_writes: Arc<AtomicBool>,
+    /// Cached configuration of the connected device
+    model: Arc<Mutex<DeviceModel>>,
+    /// Undo/redo of configuration edits on the connected device
+    history: Arc<Mutex<History>>,
 }
 
 impl HidManager {
-    pub fn new() -> Result<Self, String> {
-        let api = HidApi::new().map_err(|e| format!("Failed to initialize HID API: {}", e))?;
-        
-        Ok(HidManager {
-            api: Arc::new(Mutex::new(api)),
-            device: Arc::new(Mutex::new(None)),
-            connected_path: Arc::new(Mutex::new(None)),
-            sequence_counter: Arc::new(Mutex::new(0)),
-            is_mock_device: Arc::new(Mutex::new(false)),
-            cmd_sem: Arc::new(Semaphore::new(1)),
-        })
+    pub fn new() -> Result<Self, ConfigError> {
+        let api = HidApi::new().map_err(|e| ConfigError::io(format!("Failed to initialize HID API: {}", e)))?;
+        Ok(Self::with_api(Arc::new(Mutex::new(api))))
+    }
+
+    /// A manager sharing an already initialised hidapi context with others
+    pub fn with_api(api: Arc<Mutex<HidApi>>) -> Self {
+        let recorder = Arc::new(Mutex::new(None));
+
+        HidManager {
+            api,
+            transport: Arc::new(Mutex::new(None)),
+            dispatcher: Arc::new(Dispatcher::new(recorder.clone())),
+            recorder,
+            capabilities: Arc::new(Mutex::new(None)),
+            missing_commands: Arc::new(Mutex::new(HashSet::new())),
+            slider_stream: Arc::new(Mutex::new(None)),
+            connected: Arc::new(Mutex::new(None)),
+            last_device_id: Arc::new(Mutex::new(None)),
+            auto_reconnect: Arc::new(AtomicBool::new(true)),
+            verify_writes: Arc::new(AtomicBool::new(false)),
+            model: Arc::new(Mutex::new(DeviceModel::default())),
+            history: Arc::new(Mutex::new(History::default())),
+        }
     }
 
     /// Scan for OpenGrader devices
-    pub async fn scan_devices(&self) -> Result<Vec<DeviceDescriptor>, String> {
+    pub async fn scan_devices(&self) -> Result<Vec<DeviceDescriptor>, ConfigError> {
         let mut api = self.api.lock().unwrap();
-        api.refresh_devices().map_err(|e| format!("Failed to refresh devices: {}", e))?;
+        api.refresh_devices().map_err(|e| ConfigError::io(format!("Failed to refresh devices: {}", e)))?;
         
         let mut devices = Vec::new();
         
@@ -64,7 +159,7 @@ impl HidManager {
                                device_info.interface_number() == 2; // Our config interface is #2
 
             if is_opengrader || has_opengrader_name || is_custom_hid {
-                println!(
+                debug!(
                     "HID dev: path={} vid={:04X} pid={:04X} if={} usage={:04X} name={}",
                     device_info.path().to_string_lossy(),
                     device_info.vendor_id(),
@@ -90,9 +185,9 @@ impl HidManager {
             devices.push(DeviceDescriptor {
                 vendor_id: OPENGRADER_VID,
                 product_id: OPENGRADER_PID,
-                serial_number: Some("MOCK001".to_string()),
+                serial_number: Some(SIMULATOR_SERIAL.to_string()),
                 product_string: Some("OpenGrader Mock Device".to_string()),
-                path: "MOCK_DEVICE_PATH".to_string(),
+                path: SIMULATOR_DEVICE_PATH.to_string(),
                 interface_number: 2,
                 usage_page: 0xFF00,
             });
@@ -102,22 +197,22 @@ impl HidManager {
     }
 
     /// Connect to a specific device
-    pub fn connect(&self, device_path: &str) -> Result<(), String> {
-        // Handle mock device
-        if device_path == "MOCK_DEVICE_PATH" {
-            // For mock device, we don't actually create a HID connection
-            // but we set the device as connected
-            *self.device.lock().unwrap() = None; // Mock device doesn't have real HID device
-            *self.is_mock_device.lock().unwrap() = true;
+    pub fn connect(&self, device_path: &str) -> Result<(), ConfigError> {
+        // The simulated device gets an in-process transport instead of a HID handle
+        if device_path == SIMULATOR_DEVICE_PATH {
+            let device = SimulatedDevice::from_env()?;
+            let device_id = device_key(Some(SIMULATOR_SERIAL), device_path, None);
+            self.attach(Arc::new(SimulatorTransport::new(device)), Some(&device_id));
+            self.remember_connection(device_path, device_id);
             return Ok(());
         }
         
         let mut api = self.api.lock().unwrap();
         
-        println!("Attempting to open HID path: {}", device_path);
+        info!("Attempting to open HID path: {}", device_path);
         let mut actual_path = device_path.to_string();
         let c_path = std::ffi::CString::new(device_path)
-            .map_err(|e| format!("Invalid device path: {}", e))?;
+            .map_err(|e| ConfigError::invalid_input(format!("Invalid device path: {}", e)))?;
         let device = match api.open_path(&c_path) {
             Ok(d) => d,
             Err(e) => {
@@ -126,7 +221,7 @@ impl HidManager {
                 let mut alt_path: Option<String> = None;
                 for di in api.device_list() {
                     let name = di.product_string().unwrap_or("");
-                    println!(
+                    debug!(
                         "HID dev candidate: path={} vid={:04X} pid={:04X} if={} usage={:04X} name={}",
                         di.path().to_string_lossy(),
                         di.vendor_id(),
@@ -144,106 +239,206 @@ impl HidManager {
                     }
                 }
                 if let Some(p) = alt_path {
-                    println!("Primary open_path failed: {}. Trying alt path: {}", e, p);
+                    warn!("Primary open_path failed: {}. Trying alt path: {}", e, p);
                     let c_alt = std::ffi::CString::new(p.clone())
-                        .map_err(|e2| format!("Invalid fallback path: {}", e2))?;
+                        .map_err(|e2| ConfigError::invalid_input(format!("Invalid fallback path: {}", e2)))?;
                     match api.open_path(&c_alt) {
                         Ok(d2) => { actual_path = p; d2 },
                         Err(e2) => {
-                            return Err(format!("Failed to open HID device. primary='{}' fallback='{}'", e, e2));
+                            return Err(ConfigError::io(format!("Failed to open HID device. primary='{}' fallback='{}'", e, e2)));
                         }
                     }
                 } else {
-                    return Err(format!("Failed to open HID device at path {}: {}", device_path, e));
+                    return Err(ConfigError::io(format!("Failed to open HID device at path {}: {}", device_path, e)));
                 }
             }
         };
+        drop(api);
         
-        // Set non-blocking mode
-        device.set_blocking_mode(false)
-            .map_err(|e| format!("Failed to set non-blocking mode: {}", e))?;
-        
-        *self.device.lock().unwrap() = Some(device);
-        *self.connected_path.lock().unwrap() = Some(actual_path.clone());
-        println!("Connected to HID path: {}", actual_path);
+        let serial_number = device.get_serial_number_string().ok().flatten();
+        let product_string = device.get_product_string().ok().flatten();
+        let transport = HidTransport::new(device)?;
+        let device_id = device_key(serial_number.as_deref(), &actual_path, product_string.as_deref());
+        self.attach(Arc::new(transport), Some(&device_id));
+        self.remember_connection(&actual_path, device_id);
+        info!("Connected to HID path: {}", actual_path);
         
         Ok(())
     }
 
+    /// Use an already-open transport as the active connection
+    pub fn connect_transport(&self, transport: Arc<dyn ConfigTransport>) {
+        self.attach(transport, None);
+    }
+
+    /// Switch to `transport`, dropping what was cached about the previous
+    /// connection. The undo history is kept only if `device_id` names the
+    /// board it was recorded on.
+    fn attach(&self, transport: Arc<dyn ConfigTransport>, device_id: Option<&str>) {
+        self.dispatcher.attach(transport.clone());
+        self.stop_slider_stream();
+        *self.connected.lock().unwrap() = None;
+        *self.capabilities.lock().unwrap() = None;
+        self.missing_commands.lock().unwrap().clear();
+        self.model().clear();
+        self.history().switch_device(device_id);
+        *self.transport.lock().unwrap() = Some(transport);
+    }
+
+    fn remember_connection(&self, path: &str, device_id: String) {
+        *self.last_device_id.lock().unwrap() = Some(device_id.clone());
+        *self.connected.lock().unwrap() = Some(ConnectedDevice { path: path.to_string(), device_id });
+    }
+
+    /// Pretend a transport was opened on `path` for a board with `serial_number`
+    #[cfg(test)]
+    pub fn set_identity(&self, path: &str, serial_number: &str) {
+        self.remember_connection(path, device_key(Some(serial_number), path, None));
+    }
+
+    /// Path of the device opened by `connect`
+    pub fn connected_path(&self) -> Option<String> {
+        self.connected.lock().unwrap().as_ref().map(|d| d.path.clone())
+    }
+
+    /// Stable id of the connected device, see `device_key`
+    pub fn device_id(&self) -> Option<String> {
+        self.connected.lock().unwrap().as_ref().map(|d| d.device_id.clone())
+    }
+
+    /// Connect to a recorded session instead of a device. The connection's
+    /// path is the trace file and its device id `replay:<path>`.
+    pub fn connect_replay(&self, trace_path: &Path) -> Result<(), ConfigError> {
+        let transport = ReplayTransport::from_trace_file(trace_path)?;
+        info!("Replaying {} recorded requests from {}", transport.remaining(), trace_path.display());
+        self.connect_transport(Arc::new(transport));
+        let path = trace_path.display().to_string();
+        *self.connected.lock().unwrap() = Some(ConnectedDevice { device_id: format!("replay:{}", path), path });
+        Ok(())
+    }
+
+    /// Start writing every sent/received report to a trace file
+    pub fn start_recording(&self, trace_path: &Path) -> Result<(), ConfigError> {
+        let recorder = TraceRecorder::create(trace_path)?;
+        *self.recorder.lock().unwrap() = Some(Arc::new(recorder));
+        info!("Recording HID traffic to {}", trace_path.display());
+        Ok(())
+    }
+
+    /// Stop recording; returns whether a recording was active
+    pub fn stop_recording(&self) -> bool {
+        self.recorder.lock().unwrap().take().is_some()
+    }
+
+    /// Events the device pushes on its own (layer changes, slider movement, key presses)
+    pub fn subscribe_events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.dispatcher.subscribe()
+    }
+
+    /// Every packet sent or received, decoded, while the protocol console is enabled
+    pub fn subscribe_console(&self) -> broadcast::Receiver<ConsoleEntry> {
+        self.dispatcher.subscribe_console()
+    }
+
+    pub fn set_protocol_console(&self, enabled: bool) {
+        self.dispatcher.set_console_enabled(enabled);
+    }
+
     /// Disconnect from the current device
     pub fn disconnect(&self) {
-        *self.device.lock().unwrap() = None;
-        *self.is_mock_device.lock().unwrap() = false;
-        *self.connected_path.lock().unwrap() = None;
+        self.dispatcher.detach();
+        self.stop_slider_stream();
+        *self.connected.lock().unwrap() = None;
+        *self.transport.lock().unwrap() = None;
+        *self.capabilities.lock().unwrap() = None;
+        self.missing_commands.lock().unwrap().clear();
+        self.model().clear();
     }
 
-    /// Check if we're connected to a device
+    /// Check if we're connected to a device. Unplugging is noticed by the reader
+    /// failing or by the hotplug watcher, so this doesn't touch the device list.
     pub fn is_connected(&self) -> bool {
-        // If mock, treat as connected
-        if *self.is_mock_device.lock().unwrap() { return true; }
+        if self.current_transport().is_none() {
+            return false;
+        }
 
-        // Verify current device handle and that OS still reports the same path
-        let has_handle = self.device.lock().unwrap().is_some();
-        if !has_handle { return false; }
+        // The reader stops as soon as the transport reports an error
+        if !self.dispatcher.is_reading() {
+            self.disconnect();
+            return false;
+        }
+        true
+    }
 
-        // Refresh device list to check if device is still present
-        let current_path = self.connected_path.lock().unwrap().clone();
-        
-        let device_found = {
-            let mut api = self.api.lock().unwrap();
-            let _ = api.refresh_devices();
-            if let Some(path) = &current_path {
-                api.device_list().any(|di| di.path().to_string_lossy() == *path)
-            } else {
-                false
+    /// Reconnect automatically when the last used device is plugged back in
+    pub fn set_auto_reconnect(&self, enabled: bool) {
+        self.auto_reconnect.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn auto_reconnect(&self) -> bool {
+        self.auto_reconnect.load(Ordering::SeqCst)
+    }
+
+    /// Read back every keymap, encoder, slider and magnetic switch write (slaves
+    /// included) and fail with `ConfigError::VerifyFailed` if the device holds
+    /// something else. An ack alone doesn't prove the value landed.
+    pub fn set_verify_writes(&self, enabled: bool) {
+        self.verify_writes.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn verify_writes(&self) -> bool {
+        self.verify_writes.load(Ordering::SeqCst)
+    }
+
+    /// Drop the connection if its device went away, or reconnect when the last
+    /// used device (matched by device id) comes back
+    pub async fn handle_hotplug(&self, event: &HotplugEvent) -> Result<HotplugAction, ConfigError> {
+        let device = event.device();
+        match event {
+            HotplugEvent::Removed { .. } => {
+                let ours = self.connected_path().as_deref() == Some(device.path.as_str());
+                if !ours {
+                    return Ok(HotplugAction::None);
+                }
+                info!("Connected device {} was removed", device.path);
+                self.disconnect();
+                Ok(HotplugAction::Disconnected)
             }
-        };
-        
-        if device_found {
-            // Device still present, but let's also verify we can still communicate
-            return self.verify_communication();
-        }
-        
-        // Device not present anymore, clean up
-        self.disconnect();
-        false
-    }
-
-    /// Verify we can still communicate with the device
-    fn verify_communication(&self) -> bool {
-        // Try a simple read to see if device is responsive
-        let device_guard = self.device.lock().unwrap();
-        if let Some(device) = device_guard.as_ref() {
-            let mut buffer = [0u8; 1];
-            // Non-blocking read with very short timeout
-            match device.read_timeout(&mut buffer, 1) {
-                Ok(_) => true,  // Got data or no data available (both OK)
-                Err(hidapi::HidError::HidApiError { message }) if message.contains("timeout") => true, // Timeout is OK
-                Err(_) => {
-                    // Communication error, device likely disconnected
-                    drop(device_guard);
+            HotplugEvent::Added { .. } => {
+                let last_device_id = self.last_device_id.lock().unwrap().clone();
+                let returning = last_device_id.as_deref() == Some(device.device_id().as_str());
+                if !returning || !self.auto_reconnect() || self.is_connected() {
+                    return Ok(HotplugAction::None);
+                }
+                // Each interface of the board arrives separately; only the config one can answer
+                if device.usage_page != 0xFF00 && device.interface_number != 2 {
+                    return Ok(HotplugAction::None);
+                }
+                info!("Last used device is back at {}, reconnecting", device.path);
+                self.connect(&device.path)?;
+                if let Err(e) = self.handshake().await {
                     self.disconnect();
-                    false
+                    return Err(e);
                 }
+                Ok(HotplugAction::Reconnected)
             }
-        } else {
-            false
         }
     }
 
-    /// Attempt to auto-connect to an OpenGrader device by VID/PID/name/interface
-    pub fn auto_connect(&self) -> Result<bool, String> {
+    /// Attempt to auto-connect to an OpenGrader device by VID/PID/name/interface,
+    /// trying interfaces of the `preferred` device (usually the last used one) first
+    pub fn auto_connect(&self, preferred: Option<&str>) -> Result<bool, ConfigError> {
         // Check if already connected
         if self.is_connected() {
-            println!("auto_connect: already connected, skipping scan");
+            debug!("auto_connect: already connected, skipping scan");
             return Ok(true);
         }
         
         let mut api = self.api.lock().unwrap();
-        api.refresh_devices().map_err(|e| format!("Failed to refresh devices: {}", e))?;
+        api.refresh_devices().map_err(|e| ConfigError::io(format!("Failed to refresh devices: {}", e)))?;
 
         // Determine ranked candidate paths while holding the lock (prefer 'Configuration' interface)
-        let mut candidates: Vec<(i32, String)> = Vec::new();
+        let mut candidates: Vec<(bool, i32, String)> = Vec::new();
         for di in api.device_list() {
             // Only consider our device VID/PID
             let vidpid = di.vendor_id() == OPENGRADER_VID && di.product_id() == OPENGRADER_PID;
@@ -265,15 +460,18 @@ impl HidManager {
                         else { 0 };
 
             if score > 0 {
-                candidates.push((score, di.path().to_string_lossy().to_string()));
+                let path = di.path().to_string_lossy().to_string();
+                let known = preferred.is_some()
+                    && preferred == Some(device_key(di.serial_number(), &path, di.product_string()).as_str());
+                candidates.push((known, score, path));
             }
         }
-        // Sort by score desc and try each until one connects
-        candidates.sort_by(|a, b| b.0.cmp(&a.0));
-        println!("auto_connect: candidates={:?}", candidates);
+        // Preferred device first, then by score desc; try each until one connects
+        candidates.sort_by_key(|c| std::cmp::Reverse((c.0, c.1)));
+        debug!("auto_connect: candidates={:?}", candidates);
         drop(api);
 
-        for (_score, path) in candidates {
+        for (_known, _score, path) in candidates {
             if self.connect(&path).is_ok() {
                 return Ok(true);
             }
@@ -281,198 +479,231 @@ impl HidManager {
         Ok(false)
     }
 
-    /// Get next sequence number
-    fn get_next_sequence(&self) -> u8 {
-        let mut seq = self.sequence_counter.lock().unwrap();
-        *seq = seq.wrapping_add(1);
-        *seq
+    /// Active transport, if connected
+    fn current_transport(&self) -> Option<Arc<dyn ConfigTransport>> {
+        self.transport.lock().unwrap().clone()
+    }
+
+    /// Number of requests allowed in flight at once
+    pub fn pipeline_window(&self) -> usize {
+        self.dispatcher.window_size()
+    }
+
+    pub fn set_pipeline_window(&self, size: usize) -> Result<(), ConfigError> {
+        self.dispatcher.set_window_size(size)
+    }
+
+    /// Per-command request, retry, timeout and latency counters for this connection
+    pub fn link_stats(&self) -> LinkStatsSnapshot {
+        self.dispatcher.link_stats()
+    }
+
+    pub fn reset_link_stats(&self) {
+        self.dispatcher.reset_link_stats();
+    }
+
+    /// Link quality since the previous call
+    pub fn link_health(&self, interval: Duration) -> LinkHealth {
+        self.dispatcher.link_health(self.device_id(), interval)
+    }
+
+    /// Timeouts and retries used for each command
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.dispatcher.retry_policy()
+    }
+
+    pub fn set_retry_policy(&self, policy: RetryPolicy) -> Result<(), ConfigError> {
+        self.dispatcher.set_retry_policy(policy)
     }
 
     pub async fn send_command(
         &self,
         command: ConfigCommand,
         payload: &[u8],
-    ) -> Result<ConfigPacket, String> {
-        // Serialize all HID traffic to avoid interleaving (e.g., ping vs keymap fetch)
-        let _permit = self
-            .cmd_sem
-            .clone()
-            .acquire_owned()
-            .await
-            .map_err(|_| "Command semaphore closed".to_string())?;
-        let sequence = self.get_next_sequence();
-        let packet = ConfigPacket::new(command, sequence, payload);
-        let packet_bytes = packet.to_bytes();
-
-        // Debug: Log the first 8 bytes being sent
-        println!("Sending bytes: {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X}",
-                 packet_bytes[0], packet_bytes[1], packet_bytes[2], packet_bytes[3],
-                 packet_bytes[4], packet_bytes[5], packet_bytes[6], packet_bytes[7]);
-
-        // Retry loop: a couple of attempts with reasonable timeouts
-        const MAX_RETRIES: u8 = 2;
-        let mut attempt: u8 = 0;
-        let response = loop {
-            attempt = attempt.wrapping_add(1);
-
-            // Send the packet - do this in a separate scope to release the lock
-            {
-                let device_guard = self.device.lock().unwrap();
-                let device = device_guard.as_ref()
-                    .ok_or("No device connected")?;
-
-                // On Windows, HIDAPI expects the first byte to be the report ID (0x00 if none)
-                // and will NOT send that byte to the device. If we don't provide it, the first
-                // byte of our payload becomes the report ID and is dropped, shifting our packet.
-                #[cfg(target_os = "windows")]
-                {
-                    let mut write_buf = [0u8; CONFIG_PACKET_SIZE + 1];
-                    write_buf[0] = 0x00; // report ID 0
-                    write_buf[1..].copy_from_slice(&packet_bytes);
-                    device
-                        .write(&write_buf)
-                        .map_err(|e| format!("Failed to write packet: {}", e))?;
-                }
-
-                #[cfg(not(target_os = "windows"))]
-                {
-                    device
-                        .write(&packet_bytes)
-                        .map_err(|e| format!("Failed to write packet: {}", e))?;
-                }
+    ) -> Result<ConfigPacket, ConfigError> {
+        // Fail fast on commands the handshake found missing instead of waiting for a timeout
+        if let Some(caps) = self.capabilities.lock().unwrap().as_ref() {
+            if !caps.supports(command) {
+                return Err(ConfigError::Unsupported { command });
             }
+        }
 
-            // Per-attempt response wait with a reasonable deadline
-            let attempt_result = timeout(Duration::from_millis(800), async {
-                let mut read_attempts = 0;
-                let max_read_attempts = 8; // 8 * 100ms = 800ms max
-                
-                loop {
-                    read_attempts += 1;
-                    if read_attempts > max_read_attempts {
-                        return Err("Too many read attempts without valid response".to_string());
-                    }
-                    
-                    // On Windows, reads include the report ID byte (total 65 bytes)
-                    #[cfg(target_os = "windows")]
-                    let mut buffer65 = [0u8; CONFIG_PACKET_SIZE + 1];
-
-                    #[cfg(not(target_os = "windows"))]
-                    let mut buffer64 = [0u8; CONFIG_PACKET_SIZE];
-
-                    // Read response - do this in a separate scope to release the lock
-                    let read_result = {
-                        let device_guard = self.device.lock().unwrap();
-                        let device = device_guard.as_ref()
-                            .ok_or("Device disconnected during operation")?;
-
-                        #[cfg(target_os = "windows")]
-                        {
-                            device.read_timeout(&mut buffer65, 100)
-                        }
+        let transport = self.current_transport().ok_or(ConfigError::NotConnected)?;
+        self.dispatcher.execute(transport, command, payload).await
+    }
 
-                        #[cfg(not(target_os = "windows"))]
-                        {
-                            device.read_timeout(&mut buffer64, 100)
-                        }
-                    };
-
-                    match read_result {
-                        // Windows: 65 bytes -> drop report ID; 64 bytes -> use as-is
-                        #[cfg(target_os = "windows")]
-                        Ok(bytes_read) if bytes_read == CONFIG_PACKET_SIZE + 1 || bytes_read == CONFIG_PACKET_SIZE => {
-                            let data: &[u8] = if bytes_read == CONFIG_PACKET_SIZE + 1 { &buffer65[1..] } else { &buffer65[..CONFIG_PACKET_SIZE] };
-                            println!(
-                                "Received bytes (win): {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X}",
-                                data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7]
-                            );
-                            match ConfigPacket::from_bytes(data) {
-                                Ok(response) if response.sequence == sequence => {
-                                    return Ok(response);
-                                }
-                                Ok(response) => {
-                                    println!("Wrong sequence received: got {}, expected {}", response.sequence, sequence);
-                                    continue; // Wrong sequence, keep waiting
-                                }
-                                Err(e) => return Err(format!("Invalid response packet: {}", e)),
-                            }
-                        }
+    /// Send a command and turn a non-Ok status into `ConfigError::DeviceStatus`
+    async fn request(&self, command: ConfigCommand, payload: &[u8]) -> Result<ConfigPacket, ConfigError> {
+        let response = self.send_command(command, payload).await?;
+        let status = response.status()?;
+        if status != StatusCode::Ok {
+            return Err(ConfigError::DeviceStatus { command, status });
+        }
+        Ok(response)
+    }
 
-                        // Non-Windows: 64 bytes as-is
-                        #[cfg(not(target_os = "windows"))]
-                        Ok(bytes_read) if bytes_read == CONFIG_PACKET_SIZE => {
-                            println!(
-                                "Received bytes: {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X}",
-                                buffer64[0], buffer64[1], buffer64[2], buffer64[3], buffer64[4], buffer64[5], buffer64[6], buffer64[7]
-                            );
-                            match ConfigPacket::from_bytes(&buffer64) {
-                                Ok(response) if response.sequence == sequence => {
-                                    return Ok(response);
-                                }
-                                Ok(response) => {
-                                    println!("Wrong sequence received: got {}, expected {}", response.sequence, sequence);
-                                    continue; // Wrong sequence, keep waiting
-                                }
-                                Err(e) => return Err(format!("Invalid response packet: {}", e)),
-                            }
-                        }
-                        Ok(_) => {
-                            println!("Incomplete read, attempt {}/{}", read_attempts, max_read_attempts);
-                            continue; // Incomplete read, keep trying
-                        }
-                        Err(hidapi::HidError::HidApiError { message }) if message.contains("timeout") => {
-                            println!("Read timeout, attempt {}/{}", read_attempts, max_read_attempts);
-                            tokio::time::sleep(Duration::from_millis(5)).await;
-                            continue;
-                        }
-                        Err(e) => return Err(format!("Failed to read response: {}", e)),
-                    }
+    /// Send a payload of any size to `target` as a series of CRC-guarded `LargeWrite` chunks.
+    /// Chunks the device didn't accept (gap or bad CRC) are resent from the offset it acks.
+    pub async fn send_large(&self, target: ConfigCommand, data: &[u8]) -> Result<(), ConfigError> {
+        if data.len() > LARGE_TRANSFER_MAX {
+            return Err(ConfigError::PayloadTooLarge { length: data.len(), max: LARGE_TRANSFER_MAX });
+        }
+        let total = data.len() as u16;
+
+        let mut offset = 0usize;
+        let mut retransmits = 0;
+        loop {
+            let end = (offset + LARGE_WRITE_CHUNK).min(data.len());
+            let chunk = LargeWriteChunk {
+                target: target as u8,
+                offset: offset as u16,
+                total,
+                data: data[offset..end].to_vec(),
+            };
+            let response = self.request(ConfigCommand::LargeWrite, &chunk.to_payload()).await?;
+            ConfigError::check_len("Large write ack", response.payload_bytes(), 2)?;
+            let acked = u16::from_le_bytes([response.payload[0], response.payload[1]]) as usize;
+
+            if acked == end {
+                if end == data.len() {
+                    return Ok(());
                 }
-            })
-        .await;
-
-            match attempt_result {
-                Ok(Ok(resp)) => break resp,
-                Ok(Err(e)) => return Err(e),
-                Err(_elapsed) => {
-                    println!("send_command timeout: cmd={:?} seq={} after 800ms", command, sequence);
-                    if attempt < MAX_RETRIES {
-                        // quick retry with same sequence
-                        tokio::time::sleep(Duration::from_millis(20)).await;
-                        continue;
-                    } else {
-                        return Err("Command timeout".to_string());
-                    }
+                offset = end;
+                retransmits = 0;
+                continue;
+            }
+            if acked > end || retransmits >= LARGE_TRANSFER_RETRANSMITS {
+                return Err(ConfigError::protocol(format!(
+                    "Large write to {:?} stalled: sent {}..{}, device acked {}",
+                    target, offset, end, acked
+                )));
+            }
+            info!("send_large: device acked {} after chunk {}..{}, retransmitting", acked, offset, end);
+            retransmits += 1;
+            offset = acked;
+        }
+    }
+
+    /// Fetch the full (possibly > 56 byte) response of `target` via `LargeRead` chunks,
+    /// re-requesting any chunk that arrives out of order or fails its CRC
+    pub async fn receive_large(&self, target: ConfigCommand, args: &[u8]) -> Result<Vec<u8>, ConfigError> {
+        let mut data = Vec::new();
+        let mut total = None;
+        let mut retransmits = 0;
+        while total != Some(data.len()) {
+            let offset = data.len() as u16;
+            let request = LargeReadChunk::request_payload(target as u8, offset, args);
+            let response = self.request(ConfigCommand::LargeRead, &request).await?;
+
+            let chunk = match LargeReadChunk::from_payload(response.payload_bytes()) {
+                Ok(chunk) if chunk.offset == offset && (chunk.total as usize) >= data.len() + chunk.data.len() => chunk,
+                Ok(_) | Err(ConfigError::ChecksumMismatch { .. }) if retransmits < LARGE_TRANSFER_RETRANSMITS => {
+                    info!("receive_large: bad chunk at offset {}, re-requesting", offset);
+                    retransmits += 1;
+                    continue;
                 }
+                Ok(chunk) => {
+                    return Err(ConfigError::protocol(format!(
+                        "Large read of {:?} stalled: asked offset {}, got {}",
+                        target, offset, chunk.offset
+                    )))
+                }
+                Err(e) => return Err(e),
+            };
+            if chunk.data.is_empty() && (chunk.total as usize) > data.len() {
+                return Err(ConfigError::protocol(format!("Large read of {:?} returned an empty chunk", target)));
             }
-        };
 
-        Ok(response)
+            total = Some(chunk.total as usize);
+            data.extend(chunk.data);
+            retransmits = 0;
+        }
+        Ok(data)
     }
 
-    /// Get board layout metadata
-    pub async fn get_board_layout(&self) -> Result<BoardLayoutInfo, String> {
-        if *self.is_mock_device.lock().unwrap() {
-            return Ok(BoardLayoutInfo {
-                version: 1,
-                matrix_rows: 4,
-                matrix_cols: 4,
-                encoder_count: 2,
-                first_encoder_column: 2,
-                encoders_per_row: 2,
-                bitmap_length: 1,
-                encoder_bitmap: vec![0b00111100],
-                layout: Vec::new(), // Mock layout would need to be populated too
+    /// Capabilities learned by the last `handshake`, if any
+    pub fn capabilities(&self) -> Option<DeviceCapabilities> {
+        self.capabilities.lock().unwrap().clone()
+    }
+
+    /// Check the device's protocol version and learn which commands and
+    /// layout cell types it implements. Run once right after connecting.
+    pub async fn handshake(&self) -> Result<DeviceInfo, ConfigError> {
+        *self.capabilities.lock().unwrap() = None;
+        self.missing_commands.lock().unwrap().clear();
+
+        let info = self.get_device_info().await?;
+        let version = info.protocol_version;
+        if !(CONFIG_MIN_PROTOCOL_VERSION..=CONFIG_PROTOCOL_VERSION).contains(&version) {
+            return Err(ConfigError::IncompatibleProtocol {
+                device: version,
+                min: CONFIG_MIN_PROTOCOL_VERSION,
+                max: CONFIG_PROTOCOL_VERSION,
             });
         }
 
-        let response = self.send_command(ConfigCommand::GetLayoutInfo, &[]).await?;
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
+        let capabilities = if version >= 2 {
+            let response = self.request(ConfigCommand::GetCapabilities, &[]).await?;
+            DeviceCapabilities::from_payload(response.payload_bytes())?
+        } else {
+            self.probe_legacy_capabilities(version).await?
+        };
+        info!(
+            "Handshake: protocol v{}, {} commands, cell types {:?}",
+            version,
+            capabilities.commands().len(),
+            capabilities.cell_types()
+        );
+
+        *self.capabilities.lock().unwrap() = Some(capabilities);
+        Ok(info)
+    }
+
+    /// v1 firmware has no `GetCapabilities`: assume the core command set and
+    /// probe the optional slider and magnetic switch groups with harmless reads
+    async fn probe_legacy_capabilities(&self, version: u8) -> Result<DeviceCapabilities, ConfigError> {
+        use LayoutCellType::*;
+
+        let mut commands: Vec<ConfigCommand> = ConfigCommand::ALL
+            .iter()
+            .copied()
+            .filter(|&c| (c as u8) <= ConfigCommand::GetLayoutCellComponentId as u8)
+            .collect();
+        let mut cell_types = vec![Empty, Switch, Encoder];
+
+        if self.probe(ConfigCommand::GetSliderValue, &[0]).await? {
+            commands.extend([ConfigCommand::GetSliderValue, ConfigCommand::GetSliderConfig, ConfigCommand::SetSliderConfig]);
+            cell_types.extend([Slider, Potentiometer]);
+        }
+        if self.probe(ConfigCommand::GetMagneticSwitchValue, &[0]).await? {
+            commands.extend([
+                ConfigCommand::GetMagneticSwitchValue,
+                ConfigCommand::GetMagneticSwitchConfig,
+                ConfigCommand::SetMagneticSwitchConfig,
+                ConfigCommand::CalibrateMagneticSwitch,
+                ConfigCommand::SetMagneticSwitchSensitivity,
+            ]);
+            cell_types.push(MagneticSwitch);
+        }
+
+        Ok(DeviceCapabilities::from_parts(version, &commands, &cell_types))
+    }
+
+    /// Whether the firmware recognises `command`; a rejected parameter still counts as implemented
+    async fn probe(&self, command: ConfigCommand, payload: &[u8]) -> Result<bool, ConfigError> {
+        match self.request(command, payload).await {
+            Ok(_) => Ok(true),
+            Err(ConfigError::DeviceStatus { status: StatusCode::InvalidParam, .. }) => Ok(true),
+            Err(ConfigError::DeviceStatus { .. }) | Err(ConfigError::Timeout { .. }) => Ok(false),
+            Err(e) => Err(e),
         }
+    }
 
-        let mut layout_info = BoardLayoutInfo::from_payload(&response.payload[..response.payload_length as usize])?;
+    /// Get board layout metadata
+    pub async fn get_board_layout(&self) -> Result<BoardLayoutInfo, ConfigError> {
+        let response = self.request(ConfigCommand::GetLayoutInfo, &[]).await?;
+
+        let mut layout_info = BoardLayoutInfo::from_payload(response.payload_bytes())?;
         
         // Populate the layout cells by querying each position
         let total_cells = (layout_info.matrix_rows as usize) * (layout_info.matrix_cols as usize);
@@ -492,118 +723,126 @@ impl HidManager {
             }
         }
         
+        self.model().set_layout(&layout_info);
         Ok(layout_info)
     }
 
     /// Get layout cell type at specific matrix position
-    pub async fn get_layout_cell_type(&self, row: u8, col: u8) -> Result<u8, String> {
-        if *self.is_mock_device.lock().unwrap() {
-            // Mock data: switches in first 2 columns, encoders in columns 2-3
-            return Ok(if col < 2 { 1 } else { 2 }); // 1=Switch, 2=Encoder
-        }
-
+    pub async fn get_layout_cell_type(&self, row: u8, col: u8) -> Result<u8, ConfigError> {
         let payload = [row, col];
-        let response = self.send_command(ConfigCommand::GetLayoutCellType, &payload).await?;
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
-        }
+        let response = self.request(ConfigCommand::GetLayoutCellType, &payload).await?;
 
-        if response.payload_length < 1 {
-            return Err("Invalid response payload length".to_string());
-        }
+        ConfigError::check_len("Response", response.payload_bytes(), 1)?;
 
         Ok(response.payload[0])
     }
 
     /// Get layout cell component ID at specific matrix position
-    pub async fn get_layout_cell_component_id(&self, row: u8, col: u8) -> Result<u8, String> {
-        if *self.is_mock_device.lock().unwrap() {
-            // Mock data: return sequential IDs
-            return Ok(row * 4 + col);
-        }
-
+    pub async fn get_layout_cell_component_id(&self, row: u8, col: u8) -> Result<u8, ConfigError> {
         let payload = [row, col];
-        let response = self.send_command(ConfigCommand::GetLayoutCellComponentId, &payload).await?;
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
-        }
+        let response = self.request(ConfigCommand::GetLayoutCellComponentId, &payload).await?;
 
-        if response.payload_length < 1 {
-            return Err("Invalid response payload length".to_string());
-        }
+        ConfigError::check_len("Response", response.payload_bytes(), 1)?;
 
         Ok(response.payload[0])
     }
 
     /// Get current slider value
-    pub async fn get_slider_value(&self, slider_id: u8) -> Result<u8, String> {
+    pub async fn get_slider_value(&self, slider_id: u8) -> Result<u8, ConfigError> {
         let payload = [slider_id];
-        let response = self.send_command(ConfigCommand::GetSliderValue, &payload).await?;
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
+        let response = self.request(ConfigCommand::GetSliderValue, &payload).await?;
+
+        ConfigError::check_len("Response", response.payload_bytes(), 1)?;
+
+        Ok(response.payload[0])
+    }
+
+    /// Start delivering slider values as `SliderValues` events every `interval_ms`.
+    /// Firmware that can't stream is polled instead, reading all of `slider_ids` per tick.
+    pub async fn subscribe_sliders(&self, slider_ids: &[u8], interval_ms: u16) -> Result<SliderStreamMode, ConfigError> {
+        if !SLIDER_STREAM_INTERVAL_RANGE_MS.contains(&interval_ms) {
+            return Err(ConfigError::invalid_input(format!(
+                "Slider interval must be between {} and {} ms, got {}",
+                SLIDER_STREAM_INTERVAL_RANGE_MS.start(),
+                SLIDER_STREAM_INTERVAL_RANGE_MS.end(),
+                interval_ms
+            )));
         }
+        self.unsubscribe_sliders().await?;
 
-        if response.payload_length < 1 {
-            return Err("Invalid response payload length".to_string());
+        if self.may_support(ConfigCommand::SubscribeSliders) {
+            match self.request(ConfigCommand::SubscribeSliders, &interval_ms.to_le_bytes()).await {
+                Ok(_) => {
+                    *self.slider_stream.lock().unwrap() = Some(SliderStream::Device);
+                    return Ok(SliderStreamMode::Device);
+                }
+                Err(e) if is_missing_command(&e) => {
+                    self.found_missing(ConfigCommand::SubscribeSliders);
+                    info!("Firmware can't stream sliders, polling instead")
+                }
+                Err(e) => return Err(e),
+            }
         }
 
-        Ok(response.payload[0])
+        if !self.may_support(ConfigCommand::GetSliderValue) {
+            return Err(ConfigError::Unsupported { command: ConfigCommand::GetSliderValue });
+        }
+        let transport = self.current_transport().ok_or(ConfigError::NotConnected)?;
+        let task = tokio::spawn(poll_sliders(
+            self.dispatcher.clone(),
+            transport,
+            slider_ids.to_vec(),
+            Duration::from_millis(interval_ms as u64),
+        ));
+        *self.slider_stream.lock().unwrap() = Some(SliderStream::Polling(task));
+        Ok(SliderStreamMode::Polling)
     }
 
-    /// Get slider configuration
-    pub async fn get_slider_config(&self, layer: u8, slider_id: u8) -> Result<SliderConfig, String> {
-        let payload = [layer, slider_id];
-        let response = self.send_command(ConfigCommand::GetSliderConfig, &payload).await?;
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
-        }
-
-        // Debug: log payload length and first bytes to help diagnose mismatches
-        println!("DEBUG get_slider_config: layer={} slider_id={} status_byte=0x{:02X} payload_length={}",
-                 layer, slider_id, response.status, response.payload_length);
-        // Print first up to 16 bytes of payload for inspection
-        let len = response.payload_length as usize;
-        let show_len = if len > 16 { 16 } else { len };
-        if show_len > 0 {
-            let slice = &response.payload[..show_len];
-            print!("DEBUG payload first bytes:");
-            for b in slice {
-                print!(" {:02X}", b);
+    /// Stop slider updates started by `subscribe_sliders`
+    pub async fn unsubscribe_sliders(&self) -> Result<(), ConfigError> {
+        let stream = self.slider_stream.lock().unwrap().take();
+        match stream {
+            Some(SliderStream::Device) => {
+                self.request(ConfigCommand::UnsubscribeSliders, &[]).await?;
             }
-            println!();
+            Some(SliderStream::Polling(task)) => task.abort(),
+            None => {}
         }
+        Ok(())
+    }
 
-        SliderConfig::from_payload(&response.payload[..response.payload_length as usize])
+    /// Forget the slider stream of a connection that is going away
+    fn stop_slider_stream(&self) {
+        if let Some(SliderStream::Polling(task)) = self.slider_stream.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+
+    /// Get slider configuration
+    pub async fn get_slider_config(&self, layer: u8, slider_id: u8) -> Result<SliderConfig, ConfigError> {
+        let payload = [layer, slider_id];
+        let response = self.request(ConfigCommand::GetSliderConfig, &payload).await?;
+
+        let config = SliderConfig::from_payload(response.payload_bytes())?;
+        self.model().record_read(ModelEntry::Slider(config.clone()));
+        Ok(config)
     }
 
     /// Set slider configuration
-    pub async fn set_slider_config(&self, config: &SliderConfig) -> Result<(), String> {
+    pub async fn set_slider_config(&self, config: &SliderConfig) -> Result<(), ConfigError> {
         let payload = config.to_payload();
-        let response = self.send_command(ConfigCommand::SetSliderConfig, &payload).await?;
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
-        }
-
-        Ok(())
+        self.request(ConfigCommand::SetSliderConfig, &payload).await?;
+        let written = ModelEntry::Slider(config.clone());
+        self.model().record_written(written.clone());
+        self.verify_entry(&written).await
     }
 
     /// Get current magnetic switch value (0-100% press)
-    pub async fn get_magnetic_switch_value(&self, switch_id: u8) -> Result<u8, String> {
+    pub async fn get_magnetic_switch_value(&self, switch_id: u8) -> Result<u8, ConfigError> {
         let payload = [switch_id];
-        let response = self.send_command(ConfigCommand::GetMagneticSwitchValue, &payload).await?;
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
-        }
+        let response = self.request(ConfigCommand::GetMagneticSwitchValue, &payload).await?;
 
-        if response.payload_length < 3 {
-            return Err("Invalid response payload length".to_string());
-        }
+        ConfigError::check_len("Response", response.payload_bytes(), 3)?;
 
         // Response payload: [raw_low, raw_high, percentage]
         // We want the percentage (third byte)
@@ -611,155 +850,71 @@ impl HidManager {
     }
 
     /// Get magnetic switch configuration
-    pub async fn get_magnetic_switch_config(&self, layer: u8, switch_id: u8) -> Result<MagneticSwitchConfig, String> {
+    pub async fn get_magnetic_switch_config(&self, layer: u8, switch_id: u8) -> Result<MagneticSwitchConfig, ConfigError> {
         let payload = [layer, switch_id];
-        let response = self.send_command(ConfigCommand::GetMagneticSwitchConfig, &payload).await?;
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
-        }
+        let response = self.request(ConfigCommand::GetMagneticSwitchConfig, &payload).await?;
 
-        MagneticSwitchConfig::from_payload(&response.payload[..response.payload_length as usize])
+        let config = MagneticSwitchConfig::from_payload(response.payload_bytes())?;
+        self.model().record_read(ModelEntry::MagneticSwitch(config.clone()));
+        Ok(config)
     }
 
     /// Set magnetic switch configuration
-    pub async fn set_magnetic_switch_config(&self, config: &MagneticSwitchConfig) -> Result<(), String> {
+    pub async fn set_magnetic_switch_config(&self, config: &MagneticSwitchConfig) -> Result<(), ConfigError> {
         let payload = config.to_payload();
-        let response = self.send_command(ConfigCommand::SetMagneticSwitchConfig, &payload).await?;
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
-        }
-
-        Ok(())
+        self.request(ConfigCommand::SetMagneticSwitchConfig, &payload).await?;
+        let written = ModelEntry::MagneticSwitch(config.clone());
+        self.model().record_written(written.clone());
+        self.verify_entry(&written).await
     }
 
     /// Calibrate magnetic switch (step: 0=start, 1=set_unpressed, 2=set_pressed, 3=complete)
-    pub async fn calibrate_magnetic_switch(&self, switch_id: u8, step: u8) -> Result<(), String> {
+    pub async fn calibrate_magnetic_switch(&self, switch_id: u8, step: u8) -> Result<(), ConfigError> {
         let payload = [switch_id, step];
-        let response = self.send_command(ConfigCommand::CalibrateMagneticSwitch, &payload).await?;
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
-        }
-
+        self.request(ConfigCommand::CalibrateMagneticSwitch, &payload).await?;
+        self.forget_magnetic_switch(switch_id);
         Ok(())
     }
 
     /// Set magnetic switch sensitivity (0-100%)
-    pub async fn set_magnetic_switch_sensitivity(&self, switch_id: u8, sensitivity: u8) -> Result<(), String> {
+    pub async fn set_magnetic_switch_sensitivity(&self, switch_id: u8, sensitivity: u8) -> Result<(), ConfigError> {
         let payload = [switch_id, sensitivity];
-        let response = self.send_command(ConfigCommand::SetMagneticSwitchSensitivity, &payload).await?;
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
-        }
-
+        self.request(ConfigCommand::SetMagneticSwitchSensitivity, &payload).await?;
+        self.forget_magnetic_switch(switch_id);
         Ok(())
     }
 
+    /// Calibration and sensitivity change the stored config behind the cache's back
+    fn forget_magnetic_switch(&self, switch_id: u8) {
+        self.model().forget(|key| matches!(key, ModelKey::MagneticSwitch { switch_id: id, .. } if *id == switch_id));
+    }
+
     /// Get device information
-    pub async fn get_device_info(&self) -> Result<DeviceInfo, String> {
-        // Handle mock device
-        if *self.is_mock_device.lock().unwrap() {
-            return Ok(DeviceInfo {
-                device_name: "Mock OpenGrader".to_string(),
-                protocol_version: 1,
-                firmware_version_major: 1,
-                firmware_version_minor: 0,
-                firmware_version_patch: 0,
-                device_type: 1, // Master
-                matrix_rows: 4,
-                matrix_cols: 4,
-                encoder_count: 2,
-                layer_count: 4,
-                i2c_devices: 0,
-            });
-        }
-        
-        let response = self.send_command(ConfigCommand::GetInfo, &[]).await?;
-        
-        println!("DEBUG get_device_info: status_byte=0x{:02X}, sequence={}, payload_length={}", 
-                 response.status, response.sequence, response.payload_length);
-        
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            println!("DEBUG get_device_info: status check failed, got {:?} instead of Ok", status);
-            return Err(format!("Device returned error: {:?}", status));
-        }
+    pub async fn get_device_info(&self) -> Result<DeviceInfo, ConfigError> {
+        let response = self.request(ConfigCommand::GetInfo, &[]).await?;
 
-        DeviceInfo::from_payload(&response.payload[..response.payload_length as usize])
+        let info = DeviceInfo::from_payload(response.payload_bytes())?;
+        self.model().set_device_info(&info);
+        Ok(info)
     }
 
     /// Get keymap entry for specific layer/row/col
-    pub async fn get_keymap_entry(&self, layer: u8, row: u8, col: u8) -> Result<KeymapEntry, String> {
-        // Handle mock device
-        if *self.is_mock_device.lock().unwrap() {
-            // Return mock keymap data - simple layout with letters
-            let keycode = if layer == 0 {
-                match (row, col) {
-                    (0, 0) => 0x04, // KC_A
-                    (0, 1) => 0x05, // KC_B
-                    (0, 2) => 0x06, // KC_C  
-                    (0, 3) => 0x07, // KC_D
-                    (1, 0) => 0x08, // KC_E
-                    (1, 1) => 0x09, // KC_F
-                    (1, 2) => 0x0A, // KC_G
-                    (1, 3) => 0x0B, // KC_H
-                    (2, 0) => 0x0C, // KC_I
-                    (2, 1) => 0x0D, // KC_J
-                    (2, 2) => 0x0E, // KC_K
-                    (2, 3) => 0x0F, // KC_L
-                    (3, 0) => 0x10, // KC_M
-                    (3, 1) => 0x11, // KC_N
-                    (3, 2) => 0x12, // KC_O
-                    (3, 3) => 0x13, // KC_P
-                    _ => 0x00, // KC_NO
-                }
-            } else {
-                0x00
-            };
-            
-            return Ok(KeymapEntry {
-                layer,
-                row,
-                col,
-                keycode,
-            });
-        }
-        
+    pub async fn get_keymap_entry(&self, layer: u8, row: u8, col: u8) -> Result<KeymapEntry, ConfigError> {
         let payload = [layer, row, col];
-        let response = self.send_command(ConfigCommand::GetKeymap, &payload).await?;
-        
-        println!("DEBUG get_keymap_entry: layer={} status_byte=0x{:02X}, sequence={}, payload_length={}", 
-                 layer, response.status, response.sequence, response.payload_length);
-        
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            println!("DEBUG get_keymap_entry: status check failed, got {:?} instead of Ok", status);
-            return Err(format!("Device returned error: {:?}", status));
-        }
+        let response = self.request(ConfigCommand::GetKeymap, &payload).await?;
 
-        KeymapEntry::from_payload(&response.payload[..response.payload_length as usize])
+        let entry = KeymapEntry::from_payload(response.payload_bytes())?;
+        self.model().record_read(ModelEntry::Keymap(entry.clone()));
+        Ok(entry)
     }
 
     /// Set keymap entry for specific row/col
-    pub async fn set_keymap_entry(&self, entry: &KeymapEntry) -> Result<(), String> {
-        // Handle mock device
-        if *self.is_mock_device.lock().unwrap() {
-            // For mock device, just pretend to set the keymap
-            return Ok(());
-        }
-        
+    pub async fn set_keymap_entry(&self, entry: &KeymapEntry) -> Result<(), ConfigError> {
         let payload = entry.to_payload();
-        let response = self.send_command(ConfigCommand::SetKeymap, &payload).await?;
-        
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
-        }
-
-        Ok(())
+        self.request(ConfigCommand::SetKeymap, &payload).await?;
+        let written = ModelEntry::Keymap(entry.clone());
+        self.model().record_written(written.clone());
+        self.verify_entry(&written).await
     }
     
     /// Get keymap entry from a specific slave device
@@ -769,57 +924,23 @@ impl HidManager {
         layer: u8,
         row: u8,
         col: u8,
-    ) -> Result<SlaveKeymapEntry, String> {
-        // Handle mock device
-        if *self.is_mock_device.lock().unwrap() {
-            // Return mock slave keymap data
-            let keycode = if layer == 0 {
-                match (row, col) {
-                    (0, 0) => 0x04, // KC_A
-                    (0, 1) => 0x05, // KC_B
-                    _ => 0x00, // KC_NO
-                }
-            } else {
-                0x00
-            };
-            
-            return Ok(SlaveKeymapEntry {
-                slave_addr,
-                layer,
-                row,
-                col,
-                keycode,
-            });
-        }
-        
+    ) -> Result<SlaveKeymapEntry, ConfigError> {
         let payload = [slave_addr, layer, row, col];
-        let response = self.send_command(ConfigCommand::GetSlaveKeymap, &payload).await?;
+        let response = self.request(ConfigCommand::GetSlaveKeymap, &payload).await?;
         
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
-        }
 
-        SlaveKeymapEntry::from_payload(slave_addr, &response.payload[..response.payload_length as usize])
+        let entry = SlaveKeymapEntry::from_payload(slave_addr, response.payload_bytes())?;
+        self.model().record_read(ModelEntry::SlaveKeymap(entry.clone()));
+        Ok(entry)
     }
 
     /// Set keymap entry on a specific slave device
-    pub async fn set_slave_keymap_entry(&self, entry: &SlaveKeymapEntry) -> Result<(), String> {
-        // Handle mock device
-        if *self.is_mock_device.lock().unwrap() {
-            // For mock device, just pretend to set the slave keymap
-            return Ok(());
-        }
-        
+    pub async fn set_slave_keymap_entry(&self, entry: &SlaveKeymapEntry) -> Result<(), ConfigError> {
         let payload = entry.to_payload();
-        let response = self.send_command(ConfigCommand::SetSlaveKeymap, &payload).await?;
-        
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
-        }
-
-        Ok(())
+        self.request(ConfigCommand::SetSlaveKeymap, &payload).await?;
+        let written = ModelEntry::SlaveKeymap(entry.clone());
+        self.model().record_written(written.clone());
+        self.verify_entry(&written).await
     }
 
     /// Get encoder mapping from a specific slave device
@@ -828,209 +949,579 @@ impl HidManager {
         slave_addr: u8,
         layer: u8,
         encoder_id: u8,
-    ) -> Result<SlaveEncoderEntry, String> {
-        if *self.is_mock_device.lock().unwrap() {
-            return Ok(SlaveEncoderEntry {
-                slave_addr,
-                layer,
-                encoder_id,
-                ccw_keycode: 0x52,
-                cw_keycode: 0x51,
-                reserved: 0,
-            });
+    ) -> Result<SlaveEncoderEntry, ConfigError> {
+        let payload = [slave_addr, layer, encoder_id];
+        let response = self.request(ConfigCommand::GetSlaveEncoder, &payload).await?;
+
+        let entry = SlaveEncoderEntry::from_payload(slave_addr, response.payload_bytes())?;
+        self.model().record_read(ModelEntry::SlaveEncoder(entry.clone()));
+        Ok(entry)
+    }
+
+    /// Set encoder mapping on a specific slave device
+    pub async fn set_slave_encoder_entry(&self, entry: &SlaveEncoderEntry) -> Result<(), ConfigError> {
+        let payload = entry.to_payload();
+        self.request(ConfigCommand::SetSlaveEncoder, &payload).await?;
+        let written = ModelEntry::SlaveEncoder(entry.clone());
+        self.model().record_written(written.clone());
+        self.verify_entry(&written).await
+    }
+    
+    /// Get device info from a specific slave device
+    pub async fn get_slave_info(&self, slave_addr: u8) -> Result<DeviceInfo, ConfigError> {
+        let payload = [slave_addr];
+        let response = self.request(ConfigCommand::GetSlaveInfo, &payload).await?;
+
+        let info = DeviceInfo::from_payload(response.payload_bytes())?;
+        self.model().set_slave_info(slave_addr, &info);
+        Ok(info)
+    }
+
+    /// Get encoder mapping
+    pub async fn get_encoder_entry(&self, layer: u8, encoder_id: u8) -> Result<EncoderEntry, ConfigError> {
+        let payload = [layer, encoder_id];
+        let response = self.request(ConfigCommand::GetEncoderMap, &payload).await?;
+
+        let entry = EncoderEntry::from_payload(response.payload_bytes())?;
+        self.model().record_read(ModelEntry::Encoder(entry.clone()));
+        Ok(entry)
+    }
+
+    /// Set encoder mapping
+    pub async fn set_encoder_entry(&self, entry: &EncoderEntry) -> Result<(), ConfigError> {
+        let payload = entry.to_payload();
+        self.request(ConfigCommand::SetEncoderMap, &payload).await?;
+        let written = ModelEntry::Encoder(entry.clone());
+        self.model().record_written(written.clone());
+        self.verify_entry(&written).await
+    }
+
+    /// Read a whole keymap layer, up to `KEYMAP_BULK_MAX_KEYS` cells per packet.
+    /// Falls back to one `GetKeymap` per key on firmware without bulk commands.
+    pub async fn get_keymap_layer(&self, layer: u8, rows: u8, cols: u8) -> Result<Vec<Vec<KeymapEntry>>, ConfigError> {
+        let cells = rows as usize * cols as usize;
+        let mut keycodes = Vec::with_capacity(cells);
+
+        if self.may_support(ConfigCommand::GetKeymapBulk) {
+            match self.read_keymap_range(layer, cells).await {
+                Ok(read) => keycodes = read,
+                Err(e) if is_missing_command(&e) => {
+                    self.found_missing(ConfigCommand::GetKeymapBulk);
+                    info!("Bulk keymap read unavailable ({}), using per-key reads", e)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if keycodes.is_empty() {
+            // Per-key reads are issued together so the dispatcher can pipeline them
+            let reads = (0..rows).flat_map(|row| (0..cols).map(move |col| (row, col)));
+            let entries = try_join_all(reads.map(|(row, col)| self.get_keymap_entry(layer, row, col))).await?;
+            keycodes = entries.into_iter().map(|entry| entry.keycode).collect();
         }
 
-        let payload = [slave_addr, layer, encoder_id];
-        let response = self.send_command(ConfigCommand::GetSlaveEncoder, &payload).await?;
+        let entries: Vec<Vec<KeymapEntry>> = keycodes
+            .chunks(cols.max(1) as usize)
+            .zip(0..rows)
+            .map(|(row_keycodes, row)| {
+                row_keycodes
+                    .iter()
+                    .zip(0..cols)
+                    .map(|(&keycode, col)| KeymapEntry { layer, row, col, keycode })
+                    .collect()
+            })
+            .collect();
 
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
+        let mut model = self.model();
+        for entry in entries.iter().flatten() {
+            model.record_read(ModelEntry::Keymap(entry.clone()));
         }
+        drop(model);
+        Ok(entries)
+    }
 
-        SlaveEncoderEntry::from_payload(slave_addr, &response.payload[..response.payload_length as usize])
+    async fn read_keymap_range(&self, layer: u8, cells: usize) -> Result<Vec<u16>, ConfigError> {
+        let chunks = (0..cells).step_by(KEYMAP_BULK_MAX_KEYS).map(|start| {
+            let count = (cells - start).min(KEYMAP_BULK_MAX_KEYS) as u8;
+            self.read_keymap_chunk(layer, start as u16, count)
+        });
+        Ok(try_join_all(chunks).await?.concat())
     }
 
-    /// Set encoder mapping on a specific slave device
-    pub async fn set_slave_encoder_entry(&self, entry: &SlaveEncoderEntry) -> Result<(), String> {
-        if *self.is_mock_device.lock().unwrap() {
-            return Ok(());
+    async fn read_keymap_chunk(&self, layer: u8, start: u16, count: u8) -> Result<Vec<u16>, ConfigError> {
+        let response = self
+            .request(ConfigCommand::GetKeymapBulk, &KeymapRange::request_payload(layer, start, count))
+            .await?;
+        let range = KeymapRange::from_payload(response.payload_bytes())?;
+        if range.layer != layer || range.start != start || range.keycodes.len() != count as usize {
+            return Err(ConfigError::protocol(format!(
+                "Keymap range mismatch: asked L{} {}+{}, got L{} {}+{}",
+                layer, start, count, range.layer, range.start, range.keycodes.len()
+            )));
         }
+        Ok(range.keycodes)
+    }
 
-        let payload = entry.to_payload();
-        let response = self.send_command(ConfigCommand::SetSlaveEncoder, &payload).await?;
-
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
+    /// Write a whole keymap layer (rows of entries, row-major), falling back to per-key writes,
+    /// and read it back if `verify_writes` is on.
+    /// Bulk packets address cells by `row * matrix_cols + col`, so they're only used when
+    /// the rows start at row 0 and span the full matrix width.
+    pub async fn set_keymap_layer(&self, layer: u8, rows: &[Vec<KeymapEntry>]) -> Result<(), ConfigError> {
+        let cols = rows.first().map_or(0, |row| row.len());
+        let row_major = rows.iter().all(|row| row.len() == cols)
+            && rows.iter().enumerate().all(|(r, row)| {
+                row.iter().enumerate().all(|(c, e)| e.layer == layer && e.row as usize == r && e.col as usize == c)
+            });
+        let full_width = row_major && cols == self.cached_device_info().await?.matrix_cols as usize;
+
+        if full_width && self.may_support(ConfigCommand::SetKeymapBulk) {
+            let keycodes: Vec<u16> = rows.iter().flatten().map(|e| e.keycode).collect();
+            let mut written = 0;
+            let mut result = Ok(());
+            for chunk in keycodes.chunks(KEYMAP_BULK_MAX_KEYS) {
+                let range = KeymapRange { layer, start: written as u16, keycodes: chunk.to_vec() };
+                result = self.request(ConfigCommand::SetKeymapBulk, &range.to_payload()).await.map(|_| ());
+                if result.is_err() {
+                    break;
+                }
+                written += chunk.len();
+            }
+            match result {
+                Ok(()) => {
+                    {
+                        let mut model = self.model();
+                        for entry in rows.iter().flatten() {
+                            model.record_written(ModelEntry::Keymap(entry.clone()));
+                        }
+                    }
+                    if self.verify_writes() {
+                        let read = self.get_keymap_layer(layer, rows.len() as u8, cols as u8).await?;
+                        for (written, read) in rows.iter().flatten().zip(read.into_iter().flatten()) {
+                            check_read_back(&ModelEntry::Keymap(written.clone()), ModelEntry::Keymap(read))?;
+                        }
+                    }
+                    return Ok(());
+                }
+                // Nothing was written yet, so the per-key path can start from scratch
+                Err(e) if written == 0 && is_missing_command(&e) => {
+                    self.found_missing(ConfigCommand::SetKeymapBulk);
+                    info!("Bulk keymap write unavailable ({}), using per-key writes", e)
+                }
+                Err(e) => return Err(e),
+            }
         }
 
+        try_join_all(rows.iter().flatten().map(|entry| self.set_keymap_entry(entry))).await?;
         Ok(())
     }
-    
-    /// Get device info from a specific slave device
-    pub async fn get_slave_info(&self, slave_addr: u8) -> Result<DeviceInfo, String> {
-        // Handle mock device
-        if *self.is_mock_device.lock().unwrap() {
-            return Ok(DeviceInfo {
-                device_name: format!("Mock Slave {}", slave_addr),
-                protocol_version: 1,
-                firmware_version_major: 1,
-                firmware_version_minor: 0,
-                firmware_version_patch: 0,
-                device_type: 2, // Slave
-                matrix_rows: 2,
-                matrix_cols: 2,
-                encoder_count: 0,
-                layer_count: 4,
-                i2c_devices: 0,
-            });
+
+    /// Read every encoder binding on a layer, falling back to one `GetEncoderMap` per encoder
+    pub async fn get_encoder_layer(&self, layer: u8, encoder_count: u8) -> Result<Vec<EncoderEntry>, ConfigError> {
+        if self.may_support(ConfigCommand::GetEncoderMapBulk) {
+            match self.read_encoder_range(layer, encoder_count).await {
+                Ok(entries) => {
+                    let mut model = self.model();
+                    for entry in &entries {
+                        model.record_read(ModelEntry::Encoder(entry.clone()));
+                    }
+                    return Ok(entries);
+                }
+                Err(e) if is_missing_command(&e) => {
+                    self.found_missing(ConfigCommand::GetEncoderMapBulk);
+                    info!("Bulk encoder read unavailable ({}), using per-encoder reads", e)
+                }
+                Err(e) => return Err(e),
+            }
         }
-        
-        let payload = [slave_addr];
-        let response = self.send_command(ConfigCommand::GetSlaveInfo, &payload).await?;
-        
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
+
+        try_join_all((0..encoder_count).map(|encoder_id| self.get_encoder_entry(layer, encoder_id))).await
+    }
+
+    async fn read_encoder_range(&self, layer: u8, encoder_count: u8) -> Result<Vec<EncoderEntry>, ConfigError> {
+        let mut entries = Vec::with_capacity(encoder_count as usize);
+        while entries.len() < encoder_count as usize {
+            let start = entries.len() as u8;
+            let count = (encoder_count - start).min(ENCODER_BULK_MAX as u8);
+            let response = self.request(ConfigCommand::GetEncoderMapBulk, &[layer, start, count]).await?;
+            let range = EncoderRange::from_payload(response.payload_bytes())?;
+            if range.layer != layer || range.start != start || range.bindings.len() != count as usize {
+                return Err(ConfigError::protocol(format!(
+                    "Encoder range mismatch: asked L{} {}+{}, got L{} {}+{}",
+                    layer, start, count, range.layer, range.start, range.bindings.len()
+                )));
+            }
+            entries.extend(range.bindings.into_iter().zip(start..).map(|((ccw_keycode, cw_keycode), encoder_id)| {
+                EncoderEntry { layer, encoder_id, ccw_keycode, cw_keycode, reserved: 0 }
+            }));
         }
+        Ok(entries)
+    }
 
-        DeviceInfo::from_payload(&response.payload[..response.payload_length as usize])
+    /// Device info from the cache, read from the device only if it isn't there
+    pub async fn cached_device_info(&self) -> Result<DeviceInfo, ConfigError> {
+        let cached = self.model().device_info();
+        match cached {
+            Some(info) => Ok(info),
+            None => self.get_device_info().await,
+        }
     }
 
-    /// Get encoder mapping
-    pub async fn get_encoder_entry(&self, layer: u8, encoder_id: u8) -> Result<EncoderEntry, String> {
-        // Handle mock device
-        if *self.is_mock_device.lock().unwrap() {
-            let (ccw_keycode, cw_keycode) = match encoder_id {
-                0 => (0x52, 0x51), // Up/Down arrows for encoder 0
-                1 => (0x50, 0x4F), // Left/Right arrows for encoder 1
-                _ => (0x00, 0x00), // KC_NO
-            };
-            
-            return Ok(EncoderEntry {
-                layer,
-                encoder_id,
-                ccw_keycode,
-                cw_keycode,
-                reserved: 0,
-            });
+    /// Slave info from the cache, read from the slave only if it isn't there
+    pub async fn cached_slave_info(&self, slave_addr: u8) -> Result<DeviceInfo, ConfigError> {
+        let cached = self.model().slave_info(slave_addr);
+        match cached {
+            Some(info) => Ok(info),
+            None => self.get_slave_info(slave_addr).await,
         }
-        
-        let payload = [layer, encoder_id];
-        let response = self.send_command(ConfigCommand::GetEncoderMap, &payload).await?;
-        
-        println!("DEBUG get_encoder_entry: layer={} status_byte=0x{:02X}, sequence={}, payload_length={}", 
-                 layer, response.status, response.sequence, response.payload_length);
-        
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            println!("DEBUG get_encoder_entry: status check failed, got {:?} instead of Ok", status);
-            return Err(format!("Device returned error: {:?}", status));
+    }
+
+    /// False only if the handshake or an earlier attempt positively found `command` missing
+    fn may_support(&self, command: ConfigCommand) -> bool {
+        !self.missing_commands.lock().unwrap().contains(&command)
+            && self.capabilities.lock().unwrap().as_ref().is_none_or(|caps| caps.supports(command))
+    }
+
+    /// Remember that the device rejected `command` as not implemented
+    fn found_missing(&self, command: ConfigCommand) {
+        self.missing_commands.lock().unwrap().insert(command);
+    }
+
+    /// Set active layer state (mask/default) and return applied values
+    pub async fn set_layer_state(&self, state: &LayerState) -> Result<LayerState, ConfigError> {
+        let payload = state.to_payload();
+        let response = self.request(ConfigCommand::SetLayerState, &payload).await?;
+
+        let applied = LayerState::from_payload(response.payload_bytes())?;
+        self.model().record_written(ModelEntry::LayerState(applied.clone()));
+        Ok(applied)
+    }
+
+    /// Retrieve current layer state (active mask/default layer)
+    pub async fn get_layer_state(&self) -> Result<LayerState, ConfigError> {
+        let response = self.request(ConfigCommand::GetLayerState, &[]).await?;
+
+        let state = LayerState::from_payload(response.payload_bytes())?;
+        self.model().record_read(ModelEntry::LayerState(state.clone()));
+        Ok(state)
+    }
+
+    /// Save configuration to EEPROM
+    pub async fn save_config(&self) -> Result<(), ConfigError> {
+        self.request(ConfigCommand::SaveConfig, &[]).await?;
+        Ok(())
+    }
+
+    /// Load configuration from EEPROM
+    pub async fn load_config(&self) -> Result<(), ConfigError> {
+        self.request(ConfigCommand::LoadConfig, &[]).await?;
+        self.forget_config();
+        Ok(())
+    }
+
+    /// Reset configuration to defaults
+    pub async fn reset_config(&self) -> Result<(), ConfigError> {
+        self.request(ConfigCommand::ResetConfig, &[]).await?;
+        self.forget_config();
+        Ok(())
+    }
+
+    /// Every cached value may have changed; staged edits are kept
+    fn forget_config(&self) {
+        self.model().forget(|_| true);
+    }
+
+    // Cached device model
+
+    /// The host-side copy of the device's configuration
+    pub fn model(&self) -> MutexGuard<'_, DeviceModel> {
+        self.model.lock().unwrap()
+    }
+
+    /// The cached (or staged) value for `key`; None if it isn't cached or `refresh` asks for the device
+    pub fn cached(&self, key: ModelKey, refresh: bool) -> Option<ModelEntry> {
+        if refresh {
+            return None;
         }
+        self.model().get(&key)
+    }
 
-        EncoderEntry::from_payload(&response.payload[..response.payload_length as usize])
+    /// Read one value from the device, bypassing the cache
+    pub async fn read_entry(&self, key: ModelKey) -> Result<ModelEntry, ConfigError> {
+        Ok(match key {
+            ModelKey::LayerState => ModelEntry::LayerState(self.get_layer_state().await?),
+            ModelKey::Keymap { layer, row, col } => ModelEntry::Keymap(self.get_keymap_entry(layer, row, col).await?),
+            ModelKey::Encoder { layer, encoder_id } => ModelEntry::Encoder(self.get_encoder_entry(layer, encoder_id).await?),
+            ModelKey::Slider { layer, slider_id } => ModelEntry::Slider(self.get_slider_config(layer, slider_id).await?),
+            ModelKey::MagneticSwitch { layer, switch_id } => {
+                ModelEntry::MagneticSwitch(self.get_magnetic_switch_config(layer, switch_id).await?)
+            }
+            ModelKey::SlaveKeymap { slave_addr, layer, row, col } => {
+                ModelEntry::SlaveKeymap(self.get_slave_keymap_entry(slave_addr, layer, row, col).await?)
+            }
+            ModelKey::SlaveEncoder { slave_addr, layer, encoder_id } => {
+                ModelEntry::SlaveEncoder(self.get_slave_encoder_entry(slave_addr, layer, encoder_id).await?)
+            }
+        })
     }
 
-    /// Set encoder mapping
-    pub async fn set_encoder_entry(&self, entry: &EncoderEntry) -> Result<(), String> {
-        // Handle mock device
-        if *self.is_mock_device.lock().unwrap() {
-            // For mock device, just pretend to set the encoder
-            return Ok(());
+    /// What the device holds for each key: the cached value where there is
+    /// one, a fresh read otherwise
+    pub async fn device_values(&self, keys: impl IntoIterator<Item = ModelKey>) -> Result<Vec<ModelEntry>, ConfigError> {
+        let keys: Vec<ModelKey> = keys.into_iter().collect();
+        let missing: Vec<ModelKey> = {
+            let model = self.model();
+            keys.iter().filter(|key| model.device_value(key).is_none()).copied().collect()
+        };
+        if !missing.is_empty() {
+            self.prefetch(&missing).await?;
         }
-        
-        let payload = entry.to_payload();
-        let response = self.send_command(ConfigCommand::SetEncoderMap, &payload).await?;
-        
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
+
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            let cached = self.model().device_value(&key);
+            values.push(match cached {
+                Some(value) => value,
+                None => self.read_entry(key).await?,
+            });
         }
+        Ok(values)
+    }
 
+    /// Read `keys` into the cache: whole keymap and encoder layers in bulk where
+    /// more than one of their cells is wanted, everything else pipelined
+    async fn prefetch(&self, keys: &[ModelKey]) -> Result<(), ConfigError> {
+        let mut keymap_layers: BTreeMap<u8, usize> = BTreeMap::new();
+        let mut encoder_layers: BTreeMap<u8, usize> = BTreeMap::new();
+        for key in keys {
+            match *key {
+                ModelKey::Keymap { layer, .. } => *keymap_layers.entry(layer).or_default() += 1,
+                ModelKey::Encoder { layer, .. } => *encoder_layers.entry(layer).or_default() += 1,
+                _ => {}
+            }
+        }
+        let keymap_layers: Vec<u8> = keymap_layers.into_iter().filter(|&(_, n)| n > 1).map(|(layer, _)| layer).collect();
+        let encoder_layers: Vec<u8> = encoder_layers.into_iter().filter(|&(_, n)| n > 1).map(|(layer, _)| layer).collect();
+
+        if !keymap_layers.is_empty() || !encoder_layers.is_empty() {
+            let info = self.cached_device_info().await?;
+            for &layer in &keymap_layers {
+                self.get_keymap_layer(layer, info.matrix_rows, info.matrix_cols).await?;
+            }
+            for &layer in &encoder_layers {
+                self.get_encoder_layer(layer, info.encoder_count).await?;
+            }
+        }
+
+        let rest = keys.iter().filter(|key| match **key {
+            ModelKey::Keymap { layer, .. } => !keymap_layers.contains(&layer),
+            ModelKey::Encoder { layer, .. } => !encoder_layers.contains(&layer),
+            _ => true,
+        });
+        try_join_all(rest.map(|&key| self.read_entry(key))).await?;
         Ok(())
     }
 
-    /// Set active layer state (mask/default) and return applied values
-    pub async fn set_layer_state(&self, state: &LayerState) -> Result<LayerState, String> {
-        if *self.is_mock_device.lock().unwrap() {
-            return Ok(state.clone());
+    /// Write one value to the device through its setter
+    pub async fn write_entry(&self, entry: &ModelEntry) -> Result<(), ConfigError> {
+        match entry {
+            ModelEntry::LayerState(state) => self.set_layer_state(state).await.map(|_| ()),
+            ModelEntry::Keymap(e) => self.set_keymap_entry(e).await,
+            ModelEntry::Encoder(e) => self.set_encoder_entry(e).await,
+            ModelEntry::Slider(c) => self.set_slider_config(c).await,
+            ModelEntry::MagneticSwitch(c) => self.set_magnetic_switch_config(c).await,
+            ModelEntry::SlaveKeymap(e) => self.set_slave_keymap_entry(e).await,
+            ModelEntry::SlaveEncoder(e) => self.set_slave_encoder_entry(e).await,
         }
+    }
 
-        let payload = state.to_payload();
-        let response = self.send_command(ConfigCommand::SetLayerState, &payload).await?;
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
+    /// With `verify_writes` on, read `written` back from the device and compare.
+    /// The layer state isn't checked: the device applies it its own way, and
+    /// layer keys change it anyway.
+    async fn verify_entry(&self, written: &ModelEntry) -> Result<(), ConfigError> {
+        if !self.verify_writes() || matches!(written, ModelEntry::LayerState(_)) {
+            return Ok(());
         }
+        let read = self.read_entry(written.key()).await?;
+        check_read_back(written, read)
+    }
 
-        LayerState::from_payload(&response.payload[..response.payload_length as usize])
+    /// Write every staged edit as one undoable step and return how many were
+    /// written. If any write fails the device is rolled back and the edits
+    /// stay staged.
+    pub async fn commit_changes(&self) -> Result<usize, ConfigError> {
+        let dirty = self.model().dirty();
+        info!("Committing {} staged changes", dirty.len());
+        let result = self.write_transaction("commit_changes", &dirty, false, &JobContext::detached("commit_changes")).await;
+        if let Err(e) = result {
+            // Writes and rollback writes both cleared the cells they touched.
+            // Staging again keeps every edit the device doesn't hold yet.
+            let mut model = self.model();
+            for entry in dirty {
+                model.stage(entry);
+            }
+            return Err(e);
+        }
+        Ok(dirty.len())
     }
 
-    /// Retrieve current layer state (active mask/default layer)
-    pub async fn get_layer_state(&self) -> Result<LayerState, String> {
-        if *self.is_mock_device.lock().unwrap() {
-            return Ok(LayerState {
-                active_mask: 0x01,
-                default_layer: 0,
-            });
+    // Undo/redo
+
+    pub fn history(&self) -> MutexGuard<'_, History> {
+        self.history.lock().unwrap()
+    }
+
+    /// Write `entries` in order as one undoable step labelled `label`. If a
+    /// write fails, the cells written so far still form a step so they can be undone.
+    pub async fn apply_edit(&self, label: &str, entries: &[ModelEntry]) -> Result<(), ConfigError> {
+        let before = self.device_values(entries.iter().map(ModelEntry::key)).await?;
+        let mut changes = Vec::with_capacity(entries.len());
+        let mut result = Ok(());
+        for (before, after) in before.into_iter().zip(entries) {
+            if let Err(e) = self.write_entry(after).await {
+                warn!("{} stopped after {} of {} writes: {}", label, changes.len(), entries.len(), e);
+                result = Err(e);
+                break;
+            }
+            changes.push(Change { before, after: after.clone() });
         }
+        self.history().record(label, changes);
+        result
+    }
 
-        let response = self.send_command(ConfigCommand::GetLayerState, &[]).await?;
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
+    /// Write `entries` all or nothing, as one undoable step. The affected cells
+    /// are snapshotted first and every write is attempted; if any fails (or the
+    /// job is cancelled) everything attempted is restored from the snapshot.
+    /// What the device is left with is reported in `ConfigError::WriteFailed`.
+    /// `SaveConfig` is only sent, with `save`, once every write went through
+    /// (and read back correctly, with `verify_writes` on).
+    pub async fn write_transaction(
+        &self,
+        label: &str,
+        entries: &[ModelEntry],
+        save: bool,
+        job: &JobContext,
+    ) -> Result<(), ConfigError> {
+        let snapshot = self.device_values(entries.iter().map(ModelEntry::key)).await?;
+        let has_keymap = entries.iter().any(|e| matches!(e, ModelEntry::Keymap(_)));
+        let matrix = if has_keymap {
+            let info = self.cached_device_info().await?;
+            Some((info.matrix_rows, info.matrix_cols))
+        } else {
+            None
+        };
+        let units = write_units(entries, matrix);
+
+        let mut attempted = 0;
+        let mut failed: Vec<EntryFailure> = Vec::new();
+        let mut cancelled = None;
+        for unit in &units {
+            if let Err(e) = job.check() {
+                cancelled = Some(e);
+                break;
+            }
+            attempted += unit.len();
+            if let Err(e) = self.write_unit(unit).await {
+                failed.extend(unit.iter().map(|entry| EntryFailure { key: entry.key(), message: e.to_string() }));
+            }
+            job.progress(attempted as u32, entries.len() as u32, unit[0].layer(), None);
         }
 
-        LayerState::from_payload(&response.payload[..response.payload_length as usize])
+        if failed.is_empty() && cancelled.is_none() {
+            let changes = snapshot.into_iter().zip(entries).map(|(before, after)| Change { before, after: after.clone() });
+            self.history().record(label, changes.collect());
+            if save {
+                self.save_config().await?;
+            }
+            return Ok(());
+        }
+
+        // Restore everything attempted, failed writes included: a write that
+        // timed out may still have landed
+        warn!("{}: {} of {} writes failed, rolling back {}", label, failed.len(), entries.len(), attempted);
+        let mut written = Vec::new();
+        let mut unknown = Vec::new();
+        let mut changes = Vec::new();
+        // Same keys in the same order, so this splits exactly like `units`
+        for (unit, original) in write_units(&snapshot[..attempted], matrix).iter().zip(&units) {
+            if let Err(e) = self.write_unit(unit).await {
+                warn!("{}: rollback failed for {} entries: {}", label, unit.len(), e);
+                for (before, after) in unit.iter().zip(original.iter()) {
+                    if failed.iter().any(|f| f.key == after.key()) {
+                        unknown.push(after.key());
+                    } else {
+                        written.push(after.key());
+                    }
+                    changes.push(Change { before: before.clone(), after: after.clone() });
+                }
+            }
+        }
+        // Whatever couldn't be restored stays undoable
+        self.history().record(label, changes);
+
+        let rolled_back = written.is_empty() && unknown.is_empty();
+        if let Some(e) = cancelled {
+            if rolled_back {
+                return Err(e);
+            }
+        }
+        Err(ConfigError::WriteFailed {
+            label: label.to_string(),
+            total: entries.len(),
+            failed,
+            written,
+            unknown,
+            rolled_back,
+        })
     }
 
-    /// Save configuration to EEPROM
-    pub async fn save_config(&self) -> Result<(), String> {
-        let response = self.send_command(ConfigCommand::SaveConfig, &[]).await?;
-        
-        let status = StatusCode::from(response.status);
-        match status {
-            StatusCode::Ok => Ok(()),
-            StatusCode::NotSupported => Err("Save config not yet implemented in firmware".to_string()),
-            _ => Err(format!("Device returned error: {:?}", status)),
+    /// Write one unit from `write_units`: a single entry, or a whole keymap layer in bulk
+    async fn write_unit(&self, unit: &[ModelEntry]) -> Result<(), ConfigError> {
+        if let [entry] = unit {
+            return self.write_entry(entry).await;
+        }
+        let mut rows: Vec<Vec<KeymapEntry>> = Vec::new();
+        for entry in unit {
+            let ModelEntry::Keymap(entry) = entry else { continue };
+            match rows.last_mut() {
+                Some(row) if row[0].row == entry.row => row.push(entry.clone()),
+                _ => rows.push(vec![entry.clone()]),
+            }
         }
+        self.set_keymap_layer(rows[0][0].layer, &rows).await
     }
 
-    /// Load configuration from EEPROM
-    pub async fn load_config(&self) -> Result<(), String> {
-        let response = self.send_command(ConfigCommand::LoadConfig, &[]).await?;
-        
-        let status = StatusCode::from(response.status);
-        match status {
-            StatusCode::Ok => Ok(()),
-            StatusCode::NotSupported => Err("Load config not yet implemented in firmware".to_string()),
-            _ => Err(format!("Device returned error: {:?}", status)),
+    /// Revert the most recent step on the device and return it; None if there's nothing to undo
+    pub async fn undo(&self) -> Result<Option<HistoryStep>, ConfigError> {
+        let Some(step) = self.history().pop_undo() else { return Ok(None) };
+        for change in step.changes.iter().rev() {
+            if let Err(e) = self.write_entry(&change.before).await {
+                warn!("Undo of '{}' failed: {}", step.label, e);
+                self.history().undo_failed(step);
+                return Err(e);
+            }
         }
+        info!("Undid '{}' ({} changes)", step.label, step.changes.len());
+        self.history().undone(step.clone());
+        Ok(Some(step))
     }
 
-    /// Reset configuration to defaults
-    pub async fn reset_config(&self) -> Result<(), String> {
-        let response = self.send_command(ConfigCommand::ResetConfig, &[]).await?;
-        
-        let status = StatusCode::from(response.status);
-        match status {
-            StatusCode::Ok => Ok(()),
-            StatusCode::NotSupported => Err("Reset config not yet implemented in firmware".to_string()),
-            _ => Err(format!("Device returned error: {:?}", status)),
+    /// Write the most recently undone step again and return it; None if there's nothing to redo
+    pub async fn redo(&self) -> Result<Option<HistoryStep>, ConfigError> {
+        let Some(step) = self.history().pop_redo() else { return Ok(None) };
+        for change in &step.changes {
+            if let Err(e) = self.write_entry(&change.after).await {
+                warn!("Redo of '{}' failed: {}", step.label, e);
+                self.history().redo_failed(step);
+                return Err(e);
+            }
         }
+        info!("Redid '{}' ({} changes)", step.label, step.changes.len());
+        self.history().redone(step.clone());
+        Ok(Some(step))
     }
 
     /// Get I2C devices
-    pub async fn get_i2c_devices(&self) -> Result<Vec<I2CDeviceInfo>, String> {
-        let response = self.send_command(ConfigCommand::GetI2CDevices, &[]).await?;
-        println!("DEBUG: Raw response payload length: {}", response.payload_length);
-        println!("DEBUG: First 32 bytes: {:02X?}", &response.payload[..32.min(response.payload_length as usize)]);
-        
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
-        }
+    pub async fn get_i2c_devices(&self) -> Result<Vec<I2CDeviceInfo>, ConfigError> {
+        let response = self.request(ConfigCommand::GetI2CDevices, &[]).await?;
 
         if response.payload_length < 1 {
             return Ok(Vec::new()); // No devices
@@ -1038,7 +1529,7 @@ impl HidManager {
 
         // First byte is the device count (from firmware)
         let device_count = response.payload[0] as usize;
-        println!("DEBUG: Device count from firmware: {}", device_count);
+        debug!("Device count from firmware: {}", device_count);
         
         let mut i2c_devices = Vec::new();
         let entry_size = 2usize; // address + status
@@ -1046,15 +1537,15 @@ impl HidManager {
         for i in 0..device_count {
             let base = 1 + (i * entry_size);
             if base + entry_size > response.payload_length as usize {
-                println!("WARN: Payload too short for device {}", i);
+                warn!("Payload too short for device {}", i);
                 break;
             }
 
             let address = response.payload[base];
             let status = response.payload[base + 1];
 
-            println!(
-                "DEBUG: Discovered slave {} at 0x{:02X} (status={})",
+            debug!(
+                "Discovered slave {} at 0x{:02X} (status={})",
                 i, address, status
             );
 
@@ -1067,15 +1558,15 @@ impl HidManager {
             match self.get_slave_info(address).await {
                 Ok(device_info) => {
                     let info = I2CDeviceInfo::from_device_info(address, status, &device_info);
-                    println!(
-                        "DEBUG: Fetched info for slave {}: addr=0x{:02X}, name={}",
+                    debug!(
+                        "Fetched info for slave {}: addr=0x{:02X}, name={}",
                         i, info.address, info.name
                     );
                     i2c_devices.push(info);
                 }
                 Err(e) => {
-                    println!(
-                        "WARN: Failed to fetch detailed info for device {} at 0x{:02X}: {}",
+                    warn!(
+                        "Failed to fetch detailed info for device {} at 0x{:02X}: {}",
                         i, address, e
                     );
                     i2c_devices.push(I2CDeviceInfo::with_fallback(address, status));
@@ -1087,13 +1578,8 @@ impl HidManager {
     }
 
     /// Reboot device
-    pub async fn reboot_device(&self) -> Result<(), String> {
-        let response = self.send_command(ConfigCommand::Reboot, &[]).await?;
-        
-        let status = StatusCode::from(response.status);
-        if !matches!(status, StatusCode::Ok) {
-            return Err(format!("Device returned error: {:?}", status));
-        }
+    pub async fn reboot_device(&self) -> Result<(), ConfigError> {
+        self.request(ConfigCommand::Reboot, &[]).await?;
 
         // Device will disconnect after reboot
         self.disconnect();
@@ -1105,4 +1591,407 @@ impl Default for HidManager {
     fn default() -> Self {
         Self::new().expect("Failed to create HidManager")
     }
-}
\ No newline at end of file
+}
+
+/// Backend polling for firmware without slider streaming: each tick reads every
+/// slider as one pipelined batch and publishes the result like a streamed event
+async fn poll_sliders(
+    dispatcher: Arc<Dispatcher>,
+    transport: Arc<dyn ConfigTransport>,
+    slider_ids: Vec<u8>,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    // A slow round trip shouldn't be followed by a burst of catch-up reads
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        ticker.tick().await;
+        let reads = slider_ids.iter().map(|&slider_id| {
+            let dispatcher = &dispatcher;
+            let transport = transport.clone();
+            async move {
+                let command = ConfigCommand::GetSliderValue;
+                let response = dispatcher.execute(transport, command, &[slider_id]).await?;
+                let status = response.status()?;
+                if status != StatusCode::Ok {
+                    return Err(ConfigError::DeviceStatus { command, status });
+                }
+                ConfigError::check_len("Response", response.payload_bytes(), 1)?;
+                Ok(SliderReading { slider_id, value: response.payload[0] })
+            }
+        });
+        match try_join_all(reads).await {
+            Ok(values) => dispatcher.publish(DeviceEvent::slider_values(values)),
+            Err(ConfigError::NotConnected) => break,
+            Err(e) => warn!("Slider poll failed: {}", e),
+        }
+    }
+}
+
+/// Errors meaning "this firmware doesn't implement the command", as opposed to a real failure
+fn is_missing_command(error: &ConfigError) -> bool {
+    matches!(
+        error,
+        ConfigError::Unsupported { .. }
+            | ConfigError::DeviceStatus { status: StatusCode::InvalidCmd | StatusCode::NotSupported, .. }
+    )
+}
+
+/// `ConfigError::VerifyFailed` unless the device read back what was written
+fn check_read_back(written: &ModelEntry, read: ModelEntry) -> Result<(), ConfigError> {
+    if read == *written {
+        return Ok(());
+    }
+    let key = written.key();
+    warn!("Verification of {:?} failed: wrote {:?}, read {:?}", key, written, read);
+    Err(ConfigError::VerifyFailed { key, written: written.clone(), read })
+}
+
+/// Split a bulk write into the requests it's sent as: a complete keymap layer
+/// of a `matrix` (rows, cols) board, in row-major order, goes out in bulk
+/// packets; everything else, sparse keymap edits included, one entry at a time
+fn write_units(entries: &[ModelEntry], matrix: Option<(u8, u8)>) -> Vec<Vec<ModelEntry>> {
+    let mut runs: Vec<Vec<ModelEntry>> = Vec::new();
+    for entry in entries {
+        match (runs.last_mut(), entry) {
+            (Some(run), ModelEntry::Keymap(e))
+                if matches!(&run[0], ModelEntry::Keymap(first) if first.layer == e.layer) =>
+            {
+                run.push(entry.clone())
+            }
+            _ => runs.push(vec![entry.clone()]),
+        }
+    }
+
+    let mut units = Vec::new();
+    for run in runs {
+        if run.len() > 1 && matrix.is_some_and(|(rows, cols)| is_full_layer(&run, rows, cols)) {
+            units.push(run);
+        } else {
+            units.extend(run.into_iter().map(|entry| vec![entry]));
+        }
+    }
+    units
+}
+
+/// Whether `run` is every cell of one layer, row by row
+fn is_full_layer(run: &[ModelEntry], rows: u8, cols: u8) -> bool {
+    let cols = cols as usize;
+    run.len() == rows as usize * cols
+        && run.iter().enumerate().all(|(i, entry)| {
+            matches!(entry, ModelEntry::Keymap(e) if e.row as usize == i / cols && e.col as usize == i % cols)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::connected_simulator;
+    use crate::transport::Report;
+
+    /// The simulator with one 1x3 slave at address 32
+    fn with_slave() -> SimulatedDevice {
+        let topology = serde_json::from_str(r#"{ "slaves": [{ "address": 32, "matrix_rows": 1, "matrix_cols": 3 }] }"#).unwrap();
+        SimulatedDevice::with_topology(&topology).unwrap()
+    }
+
+    #[tokio::test]
+    async fn handshake_reads_capabilities() {
+        let (manager, _sim) = connected_simulator(SimulatedDevice::new());
+        let info = manager.handshake().await.unwrap();
+        assert_eq!(info.protocol_version, CONFIG_PROTOCOL_VERSION);
+
+        let caps = manager.capabilities().unwrap();
+        assert!(caps.supports(ConfigCommand::SetMagneticSwitchConfig));
+        assert!(!caps.supports(ConfigCommand::SetI2CConfig));
+        assert_eq!(caps.cell_types(), LayoutCellType::ALL.to_vec());
+
+        // Unsupported commands fail fast instead of timing out
+        let err = manager.send_command(ConfigCommand::SetI2CConfig, &[]).await.unwrap_err();
+        assert_eq!(err, ConfigError::Unsupported { command: ConfigCommand::SetI2CConfig });
+    }
+
+    #[tokio::test]
+    async fn handshake_probes_legacy_firmware() {
+        let (manager, sim) = connected_simulator(SimulatedDevice::new());
+        sim.with_device(|device| device.set_protocol_version(1));
+        manager.handshake().await.unwrap();
+
+        let caps = manager.capabilities().unwrap();
+        assert_eq!(caps.protocol_version, 1);
+        assert!(!caps.supports(ConfigCommand::GetCapabilities));
+        assert!(caps.supports(ConfigCommand::GetSliderValue));
+        assert!(caps.supports_cell_type(LayoutCellType::MagneticSwitch));
+    }
+
+    #[tokio::test]
+    async fn handshake_rejects_incompatible_protocol() {
+        let (manager, sim) = connected_simulator(SimulatedDevice::new());
+        sim.with_device(|device| device.set_protocol_version(CONFIG_PROTOCOL_VERSION + 1));
+        assert!(matches!(
+            manager.handshake().await,
+            Err(ConfigError::IncompatibleProtocol { .. })
+        ));
+        assert!(manager.capabilities().is_none());
+    }
+
+    /// Wraps the simulator to count round trips and add a fixed per-request link latency
+    struct CountingTransport {
+        inner: SimulatorTransport,
+        writes: std::sync::atomic::AtomicUsize,
+        latency: std::time::Duration,
+    }
+
+    impl ConfigTransport for CountingTransport {
+        fn write_report(&self, report: &Report) -> Result<(), ConfigError> {
+            self.writes.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            std::thread::sleep(self.latency);
+            self.inner.write_report(report)
+        }
+
+        fn read_report(&self, timeout_ms: i32) -> Result<Option<Report>, ConfigError> {
+            self.inner.read_report(timeout_ms)
+        }
+    }
+
+    fn connect_counting(device: SimulatedDevice, latency_us: u64) -> (HidManager, Arc<CountingTransport>) {
+        let transport = Arc::new(CountingTransport {
+            inner: SimulatorTransport::new(device),
+            writes: Default::default(),
+            latency: std::time::Duration::from_micros(latency_us),
+        });
+        let manager = HidManager::new().unwrap();
+        manager.connect_transport(transport.clone());
+        (manager, transport)
+    }
+
+    /// Everything `build_full_state` reads on connect
+    async fn load_everything(manager: &HidManager) -> (Vec<Vec<Vec<KeymapEntry>>>, Vec<Vec<EncoderEntry>>) {
+        let info = manager.get_device_info().await.unwrap();
+        let mut keymap = Vec::new();
+        let mut encoders = Vec::new();
+        for layer in 0..info.layer_count {
+            keymap.push(manager.get_keymap_layer(layer, info.matrix_rows, info.matrix_cols).await.unwrap());
+            encoders.push(manager.get_encoder_layer(layer, info.encoder_count).await.unwrap());
+        }
+        (keymap, encoders)
+    }
+
+    fn without_bulk() -> SimulatedDevice {
+        let mut device = SimulatedDevice::new();
+        device.remove_command(ConfigCommand::GetKeymapBulk);
+        device.remove_command(ConfigCommand::SetKeymapBulk);
+        device.remove_command(ConfigCommand::GetEncoderMapBulk);
+        device
+    }
+
+    #[tokio::test]
+    async fn bulk_and_per_key_reads_agree() {
+        let (bulk, _) = connect_counting(SimulatedDevice::new(), 0);
+        let (per_key, _) = connect_counting(without_bulk(), 0);
+        let (bulk_keymap, bulk_encoders) = load_everything(&bulk).await;
+        let (keymap, encoders) = load_everything(&per_key).await;
+
+        assert_eq!(bulk_keymap.len(), 4);
+        for (a, b) in bulk_keymap.iter().flatten().flatten().zip(keymap.iter().flatten().flatten()) {
+            assert_eq!((a.layer, a.row, a.col, a.keycode), (b.layer, b.row, b.col, b.keycode));
+        }
+        for (a, b) in bulk_encoders.iter().flatten().zip(encoders.iter().flatten()) {
+            assert_eq!((a.encoder_id, a.ccw_keycode, a.cw_keycode), (b.encoder_id, b.ccw_keycode, b.cw_keycode));
+        }
+    }
+
+    #[tokio::test]
+    async fn bulk_writes_fall_back_to_per_key() {
+        for device in [SimulatedDevice::new(), without_bulk()] {
+            let (manager, _) = connect_counting(device, 0);
+            let mut layer = manager.get_keymap_layer(2, 4, 4).await.unwrap();
+            for (i, entry) in layer.iter_mut().flatten().enumerate() {
+                entry.keycode = 0x100 + i as u16;
+            }
+            manager.set_keymap_layer(2, &layer).await.unwrap();
+
+            let read_back = manager.get_keymap_layer(2, 4, 4).await.unwrap();
+            assert_eq!(read_back[3][1].keycode, 0x100 + 13);
+        }
+    }
+
+    #[tokio::test]
+    async fn narrow_keymap_rectangles_land_on_their_own_cells() {
+        let (manager, _sim) = connected_simulator(SimulatedDevice::new());
+        let before = manager.get_keymap_layer(1, 4, 4).await.unwrap();
+
+        // One column over two rows: as a linear range this would hit r0c0 and r0c1
+        let rows = vec![
+            vec![KeymapEntry { layer: 1, row: 0, col: 0, keycode: 0x2C }],
+            vec![KeymapEntry { layer: 1, row: 1, col: 0, keycode: 0x2D }],
+        ];
+        manager.set_keymap_layer(1, &rows).await.unwrap();
+
+        let after = manager.get_keymap_layer(1, 4, 4).await.unwrap();
+        assert_eq!((after[0][0].keycode, after[1][0].keycode), (0x2C, 0x2D));
+        assert_eq!(after[0][1].keycode, before[0][1].keycode);
+        assert_eq!(after[1][1].keycode, before[1][1].keycode);
+    }
+
+    #[tokio::test]
+    async fn handshake_skips_bulk_on_firmware_without_it() {
+        let (manager, transport) = connect_counting(without_bulk(), 0);
+        manager.handshake().await.unwrap();
+        let before = transport.writes.load(std::sync::atomic::Ordering::Relaxed);
+
+        manager.get_keymap_layer(0, 4, 4).await.unwrap();
+        // No failed bulk attempt first: exactly one request per key
+        assert_eq!(transport.writes.load(std::sync::atomic::Ordering::Relaxed) - before, 16);
+    }
+
+    #[tokio::test]
+    async fn connection_load_request_counts() {
+        let mut results = Vec::new();
+        for device in [SimulatedDevice::new(), without_bulk()] {
+            let (manager, transport) = connect_counting(device, 0);
+            load_everything(&manager).await;
+            results.push(transport.writes.load(std::sync::atomic::Ordering::Relaxed));
+        }
+
+        // GetInfo + per layer one keymap packet (16 keys) and one encoder packet
+        assert_eq!(results[0], 1 + 4 * 2);
+        // GetInfo + one failed probe per bulk command, then per layer 16 keys and 2 encoders
+        assert_eq!(results[1], 1 + 2 + 4 * (16 + 2));
+    }
+
+    /// Connection-time benchmark: wall time to load a full snapshot with and
+    /// without bulk commands, at 2 ms per round trip (a full-speed HID
+    /// interrupt endpoint polled every 1 ms each way). Run with
+    /// `cargo test bench_connection_load -- --ignored --nocapture`.
+    #[tokio::test]
+    #[ignore]
+    async fn bench_connection_load() {
+        const RUNS: u32 = 5;
+        for (name, device) in [("bulk", SimulatedDevice::new as fn() -> SimulatedDevice), ("per-key", without_bulk)] {
+            let mut total = std::time::Duration::ZERO;
+            let mut requests = 0;
+            for _ in 0..RUNS {
+                let (manager, transport) = connect_counting(device(), 2000);
+                let started = std::time::Instant::now();
+                load_everything(&manager).await;
+                total += started.elapsed();
+                requests = transport.writes.load(std::sync::atomic::Ordering::Relaxed);
+            }
+            println!("bench_connection_load: {:>7}: {:>3} requests, {:?} per load (mean of {})", name, requests, total / RUNS, RUNS);
+        }
+    }
+
+    #[tokio::test]
+    async fn transaction_snapshot_reads_whole_layers_in_bulk() {
+        let (manager, _sim) = connected_simulator(SimulatedDevice::new());
+        let entries: Vec<ModelEntry> = (0..16u8)
+            .map(|i| ModelEntry::Keymap(KeymapEntry { layer: 2, row: i / 4, col: i % 4, keycode: 0x100 + i as u16 }))
+            .collect();
+        manager.write_transaction("set_full_keymap", &entries, false, &JobContext::detached("test")).await.unwrap();
+
+        let stats = manager.link_stats();
+        let requests = |command| stats.commands.iter().find(|c| c.command == Some(command)).map(|c| c.requests);
+        assert_eq!(requests(ConfigCommand::GetKeymap), None);
+        assert_eq!(requests(ConfigCommand::GetKeymapBulk), Some(1));
+        assert_eq!(requests(ConfigCommand::SetKeymapBulk), Some(1));
+    }
+
+    #[tokio::test]
+    async fn failed_bulk_write_is_rolled_back_and_not_saved() {
+        let (manager, _sim) = connected_simulator(SimulatedDevice::new());
+        let entries = [
+            ModelEntry::Keymap(KeymapEntry { layer: 0, row: 0, col: 0, keycode: 0x2C }),
+            // Rejected by the device, which keeps the old config
+            ModelEntry::Slider(SliderConfig {
+                layer: 0,
+                slider_id: 0,
+                midi_cc: 7,
+                midi_channel: 16,
+                min_midi_value: 0,
+                max_midi_value: 127,
+            }),
+        ];
+        let job = JobContext::detached("test");
+        match manager.write_transaction("set_full_keymap", &entries, true, &job).await {
+            Err(ConfigError::WriteFailed { failed, written, unknown, rolled_back, .. }) => {
+                assert_eq!(failed.iter().map(|f| f.key).collect::<Vec<_>>(), [entries[1].key()]);
+                assert!(written.is_empty() && unknown.is_empty() && rolled_back);
+            }
+            other => panic!("expected WriteFailed, got {:?}", other),
+        }
+
+        assert_eq!(manager.get_keymap_entry(0, 0, 0).await.unwrap().keycode, 0x04);
+        assert!(manager.history().summary().undo.is_empty());
+        let stats = manager.link_stats();
+        assert!(stats.commands.iter().all(|c| c.command != Some(ConfigCommand::SaveConfig)));
+    }
+
+    #[tokio::test]
+    async fn failed_write_that_cannot_be_restored_is_reported_as_unknown() {
+        let (manager, transport) = connected_simulator(with_slave());
+        // Cached, so the snapshot doesn't need the slave
+        manager.get_slave_keymap_entry(32, 0, 0, 2).await.unwrap();
+        assert!(transport.with_device(|d| d.set_slave_online(32, false)));
+
+        let entries = [
+            ModelEntry::Keymap(KeymapEntry { layer: 0, row: 0, col: 0, keycode: 0x2C }),
+            ModelEntry::Keymap(KeymapEntry { layer: 0, row: 0, col: 1, keycode: 0x2D }),
+            ModelEntry::SlaveKeymap(SlaveKeymapEntry { slave_addr: 32, layer: 0, row: 0, col: 2, keycode: 0x2A }),
+        ];
+        let job = JobContext::detached("test");
+        match manager.write_transaction("set_full_keymap", &entries, true, &job).await {
+            Err(ConfigError::WriteFailed { failed, written, unknown, rolled_back, .. }) => {
+                assert_eq!(failed.iter().map(|f| f.key).collect::<Vec<_>>(), [entries[2].key()]);
+                // The slave may or may not have taken the write before it dropped off
+                assert!(written.is_empty());
+                assert_eq!(unknown, [entries[2].key()]);
+                assert!(!rolled_back);
+            }
+            other => panic!("expected WriteFailed, got {:?}", other),
+        }
+
+        assert_eq!(manager.get_keymap_entry(0, 0, 0).await.unwrap().keycode, 0x04);
+        assert_eq!(manager.get_keymap_entry(0, 0, 1).await.unwrap().keycode, 0x05);
+        assert_eq!(manager.history().summary().undo.len(), 1);
+        let stats = manager.link_stats();
+        assert!(stats.commands.iter().all(|c| c.command != Some(ConfigCommand::SaveConfig)));
+    }
+
+    #[tokio::test]
+    async fn verified_writes_catch_values_that_did_not_land() {
+        let (manager, transport) = connected_simulator(with_slave());
+        assert!(transport.with_device(|d| d.set_slave_layer_write_bug(32, true)));
+
+        let entry = ModelEntry::SlaveKeymap(SlaveKeymapEntry { slave_addr: 32, layer: 1, row: 0, col: 2, keycode: 0x2A });
+        // Acked, so without verification the write looks fine
+        manager.write_entry(&entry).await.unwrap();
+
+        manager.set_verify_writes(true);
+        match manager.write_entry(&entry).await {
+            Err(ConfigError::VerifyFailed { key, written, read: ModelEntry::SlaveKeymap(read) }) => {
+                assert_eq!((key, written), (entry.key(), entry.clone()));
+                assert_ne!(read.keycode, 0x2A);
+            }
+            other => panic!("expected VerifyFailed, got {:?}", other),
+        }
+        // The cache holds what the device read back, not what was sent
+        assert_ne!(manager.model().device_value(&entry.key()), Some(entry.clone()));
+
+        // The public setters check too, not just the generic write path
+        let ModelEntry::SlaveKeymap(slave_entry) = &entry else { unreachable!() };
+        let result = manager.set_slave_keymap_entry(slave_entry).await;
+        assert!(matches!(result, Err(ConfigError::VerifyFailed { .. })), "{:?}", result);
+
+        manager.write_entry(&ModelEntry::Keymap(KeymapEntry { layer: 1, row: 0, col: 0, keycode: 0x2C })).await.unwrap();
+
+        // A bulk layer write is read back in bulk
+        let rows: Vec<Vec<KeymapEntry>> = (0..4)
+            .map(|row| (0..4).map(|col| KeymapEntry { layer: 2, row, col, keycode: 0x2C }).collect())
+            .collect();
+        manager.set_keymap_layer(2, &rows).await.unwrap();
+        let stats = manager.link_stats();
+        let bulk_reads = stats.commands.iter().find(|c| c.command == Some(ConfigCommand::GetKeymapBulk)).unwrap();
+        assert_eq!(bulk_reads.requests, 1);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::model::ModelEntry;
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Steps kept for undo; the oldest is dropped beyond this
+pub const HISTORY_LIMIT: usize = 200;
+
+/// One cell as it was before an edit and as the edit left it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Change {
+    pub before: ModelEntry,
+    pub after: ModelEntry,
+}
+
+/// One user action: a single edit, or every cell of a bulk write
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryStep {
+    pub id: u64,
+    /// What the user did, e.g. the command name
+    pub label: String,
+    pub timestamp_ms: u64,
+    /// In the order they were written
+    pub changes: Vec<Change>,
+}
+
+/// What `get_history` returns, most recent step first on both stacks
+#[derive(Debug, Clone, Serialize)]
+pub struct HistorySummary {
+    pub undo: Vec<HistoryStep>,
+    pub redo: Vec<HistoryStep>,
+}
+
+/// Undo and redo stacks for one device's configuration edits
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<HistoryStep>,
+    redo: Vec<HistoryStep>,
+    next_id: u64,
+    /// Board the steps were recorded on, None if it wasn't identified
+    device_id: Option<String>,
+}
+
+impl History {
+    /// Add a step for edits just written. Cells written with the value they
+    /// already held are left out; a step without changes isn't recorded.
+    /// A new step makes everything undone so far unredoable.
+    pub fn record(&mut self, label: &str, changes: Vec<Change>) {
+        let changes: Vec<Change> = changes.into_iter().filter(|c| c.before != c.after).collect();
+        if changes.is_empty() {
+            return;
+        }
+
+        self.next_id += 1;
+        let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        self.undo.push(HistoryStep { id: self.next_id, label: label.to_string(), timestamp_ms, changes });
+        if self.undo.len() > HISTORY_LIMIT {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    /// Take the step to undo; give it back with `undone` once it was reverted
+    /// on the device, or with `undo_failed` if it wasn't
+    pub fn pop_undo(&mut self) -> Option<HistoryStep> {
+        self.undo.pop()
+    }
+
+    pub fn undone(&mut self, step: HistoryStep) {
+        self.redo.push(step);
+    }
+
+    /// Put a step back after a failed undo. Writes are idempotent, so undoing
+    /// it again also covers the cells that were already reverted.
+    pub fn undo_failed(&mut self, step: HistoryStep) {
+        self.undo.push(step);
+    }
+
+    /// Take the step to redo; give it back with `redone` or `redo_failed`
+    pub fn pop_redo(&mut self) -> Option<HistoryStep> {
+        self.redo.pop()
+    }
+
+    pub fn redone(&mut self, step: HistoryStep) {
+        self.undo.push(step);
+    }
+
+    pub fn redo_failed(&mut self, step: HistoryStep) {
+        self.redo.push(step);
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    /// Keep the steps when a connection is opened to the board they were
+    /// recorded on, e.g. after a replug; start over for any other board,
+    /// including one that can't be identified
+    pub fn switch_device(&mut self, device_id: Option<&str>) {
+        if device_id.is_none() || self.device_id.as_deref() != device_id {
+            self.clear();
+        }
+        self.device_id = device_id.map(str::to_string);
+    }
+
+    pub fn summary(&self) -> HistorySummary {
+        HistorySummary {
+            undo: self.undo.iter().rev().cloned().collect(),
+            redo: self.redo.iter().rev().cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hid_manager::HidManager;
+    use crate::protocol::KeymapEntry;
+    use crate::simulator::{connected_simulator, SimulatedDevice, SimulatorTransport, SIMULATOR_DEVICE_PATH};
+    use std::sync::Arc;
+
+    fn key(col: u8, keycode: u16) -> ModelEntry {
+        ModelEntry::Keymap(KeymapEntry { layer: 0, row: 0, col, keycode })
+    }
+
+    #[tokio::test]
+    async fn undo_and_redo_reapply_whole_steps_on_the_device() {
+        let (manager, _sim) = connected_simulator(SimulatedDevice::new());
+        let before = manager.get_keymap_entry(0, 0, 0).await.unwrap().keycode;
+
+        manager.apply_edit("set_keymap_entry", &[key(0, 0x2C)]).await.unwrap();
+        manager.apply_edit("set_full_keymap", &[key(0, 0x28), key(1, 0x29)]).await.unwrap();
+        // Writing what's already there adds nothing to undo
+        manager.apply_edit("set_keymap_entry", &[key(1, 0x29)]).await.unwrap();
+        assert_eq!(manager.history().summary().undo.len(), 2);
+
+        let step = manager.undo().await.unwrap().unwrap();
+        assert_eq!(step.changes.len(), 2);
+        assert_eq!(manager.get_keymap_entry(0, 0, 0).await.unwrap().keycode, 0x2C);
+        manager.undo().await.unwrap();
+        assert_eq!(manager.get_keymap_entry(0, 0, 0).await.unwrap().keycode, before);
+        assert!(manager.undo().await.unwrap().is_none());
+
+        manager.redo().await.unwrap();
+        assert_eq!(manager.get_keymap_entry(0, 0, 0).await.unwrap().keycode, 0x2C);
+
+        // A new edit drops what's left to redo
+        manager.apply_edit("set_keymap_entry", &[key(2, 0x2A)]).await.unwrap();
+        let history = manager.history().summary();
+        assert!(history.redo.is_empty());
+        assert_eq!(history.undo.iter().map(|s| s.label.as_str()).collect::<Vec<_>>(), ["set_keymap_entry", "set_keymap_entry"]);
+    }
+
+    #[tokio::test]
+    async fn history_survives_reconnecting_the_same_board_only() {
+        let manager = HidManager::new().unwrap();
+        manager.connect(SIMULATOR_DEVICE_PATH).unwrap();
+        manager.apply_edit("set_keymap_entry", &[key(0, 0x2C)]).await.unwrap();
+        manager.undo().await.unwrap();
+
+        // Replugged: same device id, so the step can still be redone
+        manager.disconnect();
+        manager.connect(SIMULATOR_DEVICE_PATH).unwrap();
+        assert!(manager.redo().await.unwrap().is_some());
+        assert_eq!(manager.get_keymap_entry(0, 0, 0).await.unwrap().keycode, 0x2C);
+
+        // Another board starts over
+        manager.connect_transport(Arc::new(SimulatorTransport::new(SimulatedDevice::new())));
+        let history = manager.history().summary();
+        assert!(history.undo.is_empty() && history.redo.is_empty());
+    }
+}
@@ -0,0 +1,182 @@
+use crate::hid_manager::{DeviceDescriptor, OPENGRADER_PID, OPENGRADER_VID};
+use hidapi::HidApi;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// How often the device list is diffed where there are no OS notifications
+const POLL_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// An OpenGrader interface appeared or went away
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HotplugEvent {
+    Added { device: DeviceDescriptor },
+    Removed { device: DeviceDescriptor },
+}
+
+impl HotplugEvent {
+    pub fn device(&self) -> &DeviceDescriptor {
+        match self {
+            HotplugEvent::Added { device } | HotplugEvent::Removed { device } => device,
+        }
+    }
+
+    /// Name of the Tauri event the frontend listens for
+    pub fn tauri_event(&self) -> &'static str {
+        match self {
+            HotplugEvent::Added { .. } => "og:device-added",
+            HotplugEvent::Removed { .. } => "og:device-removed",
+        }
+    }
+}
+
+/// Watch for OpenGrader VID/PID interfaces coming and going. The watcher
+/// thread exits once the receiver is dropped and the next change arrives.
+pub fn spawn_watcher(api: Arc<Mutex<HidApi>>) -> mpsc::UnboundedReceiver<HotplugEvent> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    std::thread::Builder::new()
+        .name("og-hotplug".to_string())
+        .spawn(move || {
+            let mut wait_for_change = change_signal();
+            let mut known = attached_devices(&api);
+            loop {
+                wait_for_change();
+                let current = attached_devices(&api);
+                for event in diff(&known, &current) {
+                    if tx.send(event).is_err() {
+                        return;
+                    }
+                }
+                known = current;
+            }
+        })
+        .expect("failed to spawn hotplug watcher thread");
+    rx
+}
+
+/// OpenGrader interfaces currently attached, keyed by path
+fn attached_devices(api: &Mutex<HidApi>) -> HashMap<String, DeviceDescriptor> {
+    let mut api = api.lock().unwrap();
+    if let Err(e) = api.refresh_devices() {
+        warn!("failed to refresh devices: {}", e);
+    }
+    api.device_list()
+        .filter(|di| di.vendor_id() == OPENGRADER_VID && di.product_id() == OPENGRADER_PID)
+        .map(|di| {
+            let path = di.path().to_string_lossy().to_string();
+            let device = DeviceDescriptor {
+                vendor_id: di.vendor_id(),
+                product_id: di.product_id(),
+                serial_number: di.serial_number().map(|s| s.to_string()),
+                product_string: di.product_string().map(|s| s.to_string()),
+                path: path.clone(),
+                interface_number: di.interface_number(),
+                usage_page: di.usage_page(),
+            };
+            (path, device)
+        })
+        .collect()
+}
+
+/// What changed between two snapshots. Removals come first so a device that
+/// re-enumerated on the same path reads as unplug + plug.
+fn diff(before: &HashMap<String, DeviceDescriptor>, after: &HashMap<String, DeviceDescriptor>) -> Vec<HotplugEvent> {
+    let mut removed: Vec<_> = before.iter().filter(|(path, _)| !after.contains_key(*path)).collect();
+    let mut added: Vec<_> = after.iter().filter(|(path, _)| !before.contains_key(*path)).collect();
+    removed.sort_by_key(|(path, _)| path.as_str());
+    added.sort_by_key(|(path, _)| path.as_str());
+
+    removed
+        .into_iter()
+        .map(|(_, device)| HotplugEvent::Removed { device: device.clone() })
+        .chain(added.into_iter().map(|(_, device)| HotplugEvent::Added { device: device.clone() }))
+        .collect()
+}
+
+/// Blocks until the attached devices may have changed: udev hidraw events on
+/// Linux, a fixed interval everywhere else (or if udev is unavailable)
+fn change_signal() -> Box<dyn FnMut()> {
+    #[cfg(target_os = "linux")]
+    match udev::MonitorBuilder::new().and_then(|b| b.match_subsystem("hidraw")).and_then(|b| b.listen()) {
+        Ok(socket) => {
+            return Box::new(move || loop {
+                // The socket is non-blocking; drain everything queued, rescan once
+                if socket.iter().count() > 0 {
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            });
+        }
+        Err(e) => warn!("udev monitor unavailable ({}), polling instead", e),
+    }
+
+    Box::new(|| std::thread::sleep(POLL_INTERVAL))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hid_manager::{HidManager, HotplugAction};
+    use crate::simulator::{SIMULATOR_DEVICE_PATH, SIMULATOR_SERIAL};
+
+    fn descriptor(path: &str, serial: &str) -> DeviceDescriptor {
+        DeviceDescriptor {
+            vendor_id: OPENGRADER_VID,
+            product_id: OPENGRADER_PID,
+            serial_number: Some(serial.to_string()),
+            product_string: Some("OpenGrader Configuration".to_string()),
+            path: path.to_string(),
+            interface_number: 2,
+            usage_page: 0xFF00,
+        }
+    }
+
+    fn snapshot(devices: &[(&str, &str)]) -> HashMap<String, DeviceDescriptor> {
+        devices.iter().map(|&(path, serial)| (path.to_string(), descriptor(path, serial))).collect()
+    }
+
+    #[test]
+    fn diff_reports_arrivals_and_removals() {
+        let before = snapshot(&[("/dev/hidraw1", "A"), ("/dev/hidraw2", "B")]);
+        let after = snapshot(&[("/dev/hidraw2", "B"), ("/dev/hidraw3", "C")]);
+
+        let events: Vec<(&str, String)> = diff(&before, &after)
+            .iter()
+            .map(|e| (e.tauri_event(), e.device().path.clone()))
+            .collect();
+        assert_eq!(
+            events,
+            vec![("og:device-removed", "/dev/hidraw1".to_string()), ("og:device-added", "/dev/hidraw3".to_string())]
+        );
+        assert!(diff(&after, &after).is_empty());
+    }
+
+    #[tokio::test]
+    async fn unplug_disconnects_and_replug_reconnects_by_serial() {
+        let manager = HidManager::new().unwrap();
+        manager.connect(SIMULATOR_DEVICE_PATH).unwrap();
+        manager.handshake().await.unwrap();
+        let ours = descriptor(SIMULATOR_DEVICE_PATH, SIMULATOR_SERIAL);
+
+        let unplugged = HotplugEvent::Removed { device: ours.clone() };
+        assert_eq!(manager.handle_hotplug(&unplugged).await.unwrap(), HotplugAction::Disconnected);
+        assert!(!manager.is_connected());
+
+        // Someone else's board is left alone
+        let other = HotplugEvent::Added { device: descriptor("/dev/hidraw9", "OTHER") };
+        assert_eq!(manager.handle_hotplug(&other).await.unwrap(), HotplugAction::None);
+
+        let replugged = HotplugEvent::Added { device: ours };
+        assert_eq!(manager.handle_hotplug(&replugged).await.unwrap(), HotplugAction::Reconnected);
+        assert!(manager.is_connected());
+
+        manager.set_auto_reconnect(false);
+        manager.handle_hotplug(&unplugged).await.unwrap();
+        assert_eq!(manager.handle_hotplug(&replugged).await.unwrap(), HotplugAction::None);
+        assert!(!manager.is_connected());
+    }
+}
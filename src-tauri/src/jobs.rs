@@ -0,0 +1,231 @@
+use crate::error::ConfigError;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, watch};
+use tracing::{info, warn};
+
+pub type JobId = u64;
+
+/// Finished jobs whose result is kept for `await_job`; older ones are dropped
+const FINISHED_JOBS_KEPT: usize = 16;
+
+/// What a job ended with: its return value as JSON, or why it stopped
+pub type JobOutcome = Result<serde_json::Value, ConfigError>;
+
+/// Payload of `og:progress`
+#[derive(Debug, Clone, Serialize)]
+pub struct JobProgress {
+    pub job_id: JobId,
+    pub kind: &'static str,
+    pub done: u32,
+    pub total: u32,
+    /// Layer being worked on, where the operation goes layer by layer
+    pub layer: Option<u8>,
+    /// Row being worked on, where the operation goes row by row
+    pub row: Option<u8>,
+}
+
+/// Payload of `og:job-finished`; exactly one of `result` and `error` is set
+#[derive(Debug, Clone, Serialize)]
+pub struct JobFinished {
+    pub job_id: JobId,
+    pub kind: &'static str,
+    pub result: Option<serde_json::Value>,
+    pub error: Option<ConfigError>,
+}
+
+/// Something a job reports while it runs
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum JobEvent {
+    Progress(JobProgress),
+    Finished(JobFinished),
+}
+
+impl JobEvent {
+    /// Name of the Tauri event the frontend listens for
+    pub fn tauri_event(&self) -> &'static str {
+        match self {
+            JobEvent::Progress(_) => "og:progress",
+            JobEvent::Finished(_) => "og:job-finished",
+        }
+    }
+}
+
+/// Handed to a job's body to report progress and notice cancellation
+#[derive(Clone)]
+pub struct JobContext {
+    /// 0 for a detached context
+    id: JobId,
+    kind: &'static str,
+    cancelled: Arc<AtomicBool>,
+    events: Option<broadcast::Sender<JobEvent>>,
+}
+
+impl JobContext {
+    /// Run a job's body inline: nothing is reported and nothing can cancel it
+    pub fn detached(kind: &'static str) -> Self {
+        JobContext { id: 0, kind, cancelled: Arc::new(AtomicBool::new(false)), events: None }
+    }
+
+    /// Call between requests. Once `cancel_job` was called this fails, so the
+    /// operation stops on a request boundary instead of abandoning one mid-flight.
+    pub fn check(&self) -> Result<(), ConfigError> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(ConfigError::Cancelled { job_id: self.id });
+        }
+        Ok(())
+    }
+
+    pub fn progress(&self, done: u32, total: u32, layer: Option<u8>, row: Option<u8>) {
+        if let Some(events) = &self.events {
+            let _ = events.send(JobEvent::Progress(JobProgress { job_id: self.id, kind: self.kind, done, total, layer, row }));
+        }
+    }
+}
+
+struct JobEntry {
+    kind: &'static str,
+    cancelled: Arc<AtomicBool>,
+    outcome: watch::Receiver<Option<JobOutcome>>,
+}
+
+impl JobEntry {
+    fn is_finished(&self) -> bool {
+        self.outcome.borrow().is_some()
+    }
+}
+
+/// Long-running operations started in the background, by id
+pub struct Jobs {
+    next_id: AtomicU64,
+    jobs: Mutex<BTreeMap<JobId, JobEntry>>,
+    events: broadcast::Sender<JobEvent>,
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        let (events, _) = broadcast::channel(256);
+        Jobs { next_id: AtomicU64::new(1), jobs: Mutex::new(BTreeMap::new()), events }
+    }
+}
+
+impl Jobs {
+    /// Progress and completion of every job
+    pub fn subscribe(&self) -> broadcast::Receiver<JobEvent> {
+        self.events.subscribe()
+    }
+
+    /// Start `run` on its own task and return its id right away. The result is
+    /// announced with `og:job-finished` and kept for `wait`. Must be called
+    /// from within the Tokio runtime.
+    pub fn spawn<T, F, Fut>(&self, kind: &'static str, run: F) -> JobId
+    where
+        T: Serialize,
+        F: FnOnce(JobContext) -> Fut,
+        Fut: Future<Output = Result<T, ConfigError>> + Send + 'static,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let context = JobContext { id, kind, cancelled: cancelled.clone(), events: Some(self.events.clone()) };
+        let (outcome_tx, outcome) = watch::channel(None);
+
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            let finished: Vec<JobId> = jobs.iter().filter(|(_, job)| job.is_finished()).map(|(id, _)| *id).collect();
+            for old in finished.iter().take(finished.len().saturating_sub(FINISHED_JOBS_KEPT)) {
+                jobs.remove(old);
+            }
+            jobs.insert(id, JobEntry { kind, cancelled, outcome });
+        }
+
+        info!(job_id = id, "Job {} started", kind);
+        let body = run(context);
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            let outcome = body.await.and_then(|value| {
+                serde_json::to_value(value).map_err(|e| ConfigError::protocol(format!("Unserializable job result: {}", e)))
+            });
+            match &outcome {
+                Ok(_) => info!(job_id = id, "Job {} finished", kind),
+                Err(ConfigError::Cancelled { .. }) => info!(job_id = id, "Job {} cancelled", kind),
+                Err(e) => warn!(job_id = id, "Job {} failed: {}", kind, e),
+            }
+            let (result, error) = match &outcome {
+                Ok(value) => (Some(value.clone()), None),
+                Err(e) => (None, Some(e.clone())),
+            };
+            let _ = events.send(JobEvent::Finished(JobFinished { job_id: id, kind, result, error }));
+            outcome_tx.send_replace(Some(outcome));
+        });
+        id
+    }
+
+    /// Ask a running job to stop at its next request boundary. False if it
+    /// isn't known or has already finished.
+    pub fn cancel(&self, id: JobId) -> bool {
+        match self.jobs.lock().unwrap().get(&id) {
+            Some(job) if !job.is_finished() => {
+                info!(job_id = id, "Cancelling job {}", job.kind);
+                job.cancelled.store(true, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Wait for a job to end and hand over its outcome; it's forgotten afterwards
+    pub async fn wait(&self, id: JobId) -> JobOutcome {
+        let mut outcome = match self.jobs.lock().unwrap().get(&id) {
+            Some(job) => job.outcome.clone(),
+            None => return Err(ConfigError::invalid_input(format!("Unknown job {}", id))),
+        };
+        let result = match outcome.wait_for(|outcome| outcome.is_some()).await {
+            Ok(outcome) => outcome.clone().unwrap(),
+            Err(_) => Err(ConfigError::protocol(format!("Job {} ended without a result", id))),
+        };
+        self.jobs.lock().unwrap().remove(&id);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::{connected_simulator, SimulatedDevice};
+
+    #[tokio::test]
+    async fn cancelled_job_stops_between_requests_and_leaves_the_link_usable() {
+        let (manager, _sim) = connected_simulator(SimulatedDevice::new());
+        let manager = Arc::new(manager);
+        let jobs = Jobs::default();
+        let mut events = jobs.subscribe();
+
+        let device = manager.clone();
+        let id = jobs.spawn("read_row", move |job| async move {
+            let mut keycodes = Vec::new();
+            for col in 0..200u8 {
+                job.check()?;
+                keycodes.push(device.get_keymap_entry(0, 0, col % 4).await?.keycode);
+                job.progress(col as u32 + 1, 200, Some(0), Some(0));
+            }
+            Ok(keycodes)
+        });
+
+        // Cancel as soon as the first step is reported
+        match events.recv().await.unwrap() {
+            JobEvent::Progress(progress) => assert_eq!((progress.job_id, progress.done), (id, 1)),
+            other => panic!("expected progress, got {:?}", other),
+        }
+        assert!(jobs.cancel(id));
+        assert_eq!(jobs.wait(id).await, Err(ConfigError::Cancelled { job_id: id }));
+        assert!(!jobs.cancel(id));
+
+        // The request in flight completed; the next one goes through normally
+        assert_eq!(manager.get_keymap_entry(0, 0, 0).await.unwrap().keycode, 0x04);
+        assert!(jobs.wait(id).await.is_err());
+    }
+}
@@ -2,29 +2,81 @@ mod protocol;
 mod hid_manager;
 mod commands;
 mod keycodes;
+mod transport;
+mod simulator;
+mod replay;
+mod trace;
+mod error;
+mod dispatcher;
+mod events;
+mod hotplug;
+mod registry;
+mod device_store;
+mod retry;
+mod console;
+mod logging;
+mod stats;
+mod jobs;
+mod model;
+mod history;
+mod config_file;
 
 use commands::*;
-use hid_manager::HidManager;
+use device_store::{DeviceStore, DEVICE_STORE_FILE};
+use jobs::Jobs;
+use logging::LOG_DIR;
+use registry::DeviceRegistry;
 use std::sync::Arc;
 use tauri::Manager;
-use tokio::sync::RwLock;
+use tracing::{error, warn};
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .setup(|app| {
-            // Initialize the HID manager
-            let hid_manager = match HidManager::new() {
-                Ok(manager) => manager,
+            let data_dir = app.path().app_data_dir();
+
+            // Log to stdout and to rotating files next to the settings; the guard flushes on exit
+            if let Some(guard) = logging::init(data_dir.as_ref().ok().map(|dir| dir.join(LOG_DIR)).as_deref()) {
+                app.manage(guard);
+            }
+
+            // Per-device settings live in the app data dir; without one they last for this session
+            let store = match data_dir {
+                Ok(dir) => DeviceStore::open(dir.join(DEVICE_STORE_FILE)),
+                Err(e) => {
+                    warn!("No app data dir ({}), device settings won't be saved", e);
+                    DeviceStore::in_memory()
+                }
+            };
+
+            // Initialize the device registry (and with it the HID API)
+            let registry = match DeviceRegistry::new(store) {
+                Ok(registry) => registry,
                 Err(e) => {
-                    eprintln!("Failed to initialize HID manager: {}", e);
+                    error!("Failed to initialize HID manager: {}", e);
                     std::process::exit(1);
                 }
             };
+            let state: AppState = Arc::new(registry);
+
+            // Push device-initiated events (layer changes, sliders, keys), the
+            // protocol console and link health to the frontend
+            forward_device_events(app.handle().clone(), state.primary());
+            forward_protocol_console(app.handle().clone(), state.primary());
+            forward_link_health(app.handle().clone(), state.primary());
+
+            // Report devices coming and going, and reconnect the last used one
+            forward_hotplug_events(app.handle().clone(), state.clone(), state.watch_devices());
 
-            // Store the HID manager in app state
-            app.manage(Arc::new(RwLock::new(hid_manager)));
+            // Long-running operations report progress and can be cancelled
+            let jobs = Jobs::default();
+            forward_job_events(app.handle().clone(), jobs.subscribe());
+
+            // Store the registry and the job list in app state
+            app.manage(state);
+            app.manage(jobs);
 
             Ok(())
         })
@@ -33,11 +85,25 @@ pub fn run() {
             scan_devices,
             connect_device,
             disconnect_device,
+            list_connected_devices,
+            list_known_devices,
+            get_device_settings,
+            set_device_nickname,
+            set_preferred_profile,
+            forget_device,
             is_device_connected,
             ping_device,
             check_device_status_and_reconnect,
             auto_connect,
             get_connection_status,
+            get_auto_reconnect,
+            set_auto_reconnect,
+            get_capabilities,
+            
+            // Traffic recording and replay
+            start_trace_recording,
+            stop_trace_recording,
+            connect_replay,
             
             // Simple connect/disconnect
             simple_connect,
@@ -54,6 +120,8 @@ pub fn run() {
             
             // Slider management
             get_slider_value,
+            subscribe_sliders,
+            unsubscribe_sliders,
             get_slider_config,
             set_slider_config,
             
@@ -80,6 +148,20 @@ pub fn run() {
             save_config,
             load_config,
             reset_config,
+            export_config,
+            import_config,
+            
+            // Undo/redo
+            undo,
+            redo,
+            get_history,
+            
+            // Cached device model
+            get_device_model,
+            stage_changes,
+            get_pending_changes,
+            commit_changes,
+            discard_changes,
             
             // I2C device management
             get_i2c_devices,
@@ -98,6 +180,25 @@ pub fn run() {
             // System commands
             reboot_device,
             
+            // Link tuning
+            get_pipeline_window,
+            set_pipeline_window,
+            get_retry_policy,
+            set_retry_policy,
+            set_protocol_console,
+            get_verify_writes,
+            set_verify_writes,
+            get_link_stats,
+            reset_link_stats,
+            
+            // Background jobs
+            cancel_job,
+            await_job,
+            
+            // Large transfers
+            send_large,
+            receive_large,
+            
             // Utility commands
             get_keycodes
         ])
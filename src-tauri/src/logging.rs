@@ -0,0 +1,51 @@
+use std::path::Path;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// Subdirectory of the app data dir holding the log files
+pub const LOG_DIR: &str = "logs";
+
+/// Daily log files kept before the oldest is deleted
+const LOG_FILES_KEPT: usize = 7;
+
+/// Log to stdout (level from `RUST_LOG`, `info` by default) and, given a
+/// directory, to a daily rotating file that also keeps every decoded packet.
+/// The returned guard flushes the file on drop and must live as long as the app.
+pub fn init(log_dir: Option<&Path>) -> Option<WorkerGuard> {
+    let stdout = tracing_subscriber::fmt::layer()
+        .with_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")));
+
+    let file = log_dir.and_then(|dir| {
+        let appender = RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix("opengrader")
+            .filename_suffix("log")
+            .max_log_files(LOG_FILES_KEPT)
+            .build(dir);
+        match appender {
+            Ok(appender) => Some(tracing_appender::non_blocking(appender)),
+            Err(e) => {
+                eprintln!("Failed to open log directory {}: {}", dir.display(), e);
+                None
+            }
+        }
+    });
+    let (file, guard) = match file {
+        Some((writer, guard)) => (Some(writer), Some(guard)),
+        None => (None, None),
+    };
+    let file_layer = file.map(|writer| {
+        tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(false)
+            .with_filter(EnvFilter::new("info,opengrader_configurator_lib=debug"))
+    });
+
+    if let Err(e) = tracing_subscriber::registry().with(stdout).with(file_layer).try_init() {
+        eprintln!("Logging already initialised: {}", e);
+    }
+    guard
+}
@@ -0,0 +1,334 @@
+use crate::protocol::{
+    BoardLayoutInfo, DeviceInfo, EncoderEntry, KeymapEntry, LayerState, MagneticSwitchConfig, SlaveEncoderEntry,
+    SlaveKeymapEntry, SliderConfig,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Address of one configurable value on a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ModelKey {
+    LayerState,
+    Keymap { layer: u8, row: u8, col: u8 },
+    Encoder { layer: u8, encoder_id: u8 },
+    Slider { layer: u8, slider_id: u8 },
+    MagneticSwitch { layer: u8, switch_id: u8 },
+    SlaveKeymap { slave_addr: u8, layer: u8, row: u8, col: u8 },
+    SlaveEncoder { slave_addr: u8, layer: u8, encoder_id: u8 },
+}
+
+/// One configurable value, as the device reports it or as the user wants it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "entry", rename_all = "snake_case")]
+pub enum ModelEntry {
+    LayerState(LayerState),
+    Keymap(KeymapEntry),
+    Encoder(EncoderEntry),
+    Slider(SliderConfig),
+    MagneticSwitch(MagneticSwitchConfig),
+    SlaveKeymap(SlaveKeymapEntry),
+    SlaveEncoder(SlaveEncoderEntry),
+}
+
+impl ModelEntry {
+    pub fn key(&self) -> ModelKey {
+        match self {
+            ModelEntry::LayerState(_) => ModelKey::LayerState,
+            ModelEntry::Keymap(e) => ModelKey::Keymap { layer: e.layer, row: e.row, col: e.col },
+            ModelEntry::Encoder(e) => ModelKey::Encoder { layer: e.layer, encoder_id: e.encoder_id },
+            ModelEntry::Slider(c) => ModelKey::Slider { layer: c.layer, slider_id: c.slider_id },
+            ModelEntry::MagneticSwitch(c) => ModelKey::MagneticSwitch { layer: c.layer, switch_id: c.switch_id },
+            ModelEntry::SlaveKeymap(e) => {
+                ModelKey::SlaveKeymap { slave_addr: e.slave_addr, layer: e.layer, row: e.row, col: e.col }
+            }
+            ModelEntry::SlaveEncoder(e) => {
+                ModelKey::SlaveEncoder { slave_addr: e.slave_addr, layer: e.layer, encoder_id: e.encoder_id }
+            }
+        }
+    }
+
+    /// Layer the value belongs to; None for the layer state itself
+    pub fn layer(&self) -> Option<u8> {
+        match self {
+            ModelEntry::LayerState(_) => None,
+            ModelEntry::Keymap(e) => Some(e.layer),
+            ModelEntry::Encoder(e) => Some(e.layer),
+            ModelEntry::Slider(c) => Some(c.layer),
+            ModelEntry::MagneticSwitch(c) => Some(c.layer),
+            ModelEntry::SlaveKeymap(e) => Some(e.layer),
+            ModelEntry::SlaveEncoder(e) => Some(e.layer),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Cell {
+    /// Last value read from or written to the device
+    device: Option<ModelEntry>,
+    /// Value waiting for `commit_changes`, if it differs from `device`
+    staged: Option<ModelEntry>,
+}
+
+impl Cell {
+    fn current(&self) -> Option<&ModelEntry> {
+        self.staged.as_ref().or(self.device.as_ref())
+    }
+}
+
+/// What one slave board holds, as far as it has been read
+#[derive(Debug, Clone, Serialize)]
+pub struct SlaveModel {
+    pub slave_addr: u8,
+    pub keymap: Vec<SlaveKeymapEntry>,
+    pub encoders: Vec<SlaveEncoderEntry>,
+}
+
+/// What `get_device_model` returns. Staged values are shown in place of the
+/// device's; `dirty` lists where they differ.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelSnapshot {
+    pub device_info: Option<DeviceInfo>,
+    pub layout: Option<BoardLayoutInfo>,
+    pub layer_state: Option<LayerState>,
+    /// Layers in order, up to the first one not completely cached
+    pub keymap: Vec<Vec<Vec<KeymapEntry>>>,
+    pub encoders: Vec<Vec<EncoderEntry>>,
+    pub sliders: Vec<SliderConfig>,
+    pub magnetic_switches: Vec<MagneticSwitchConfig>,
+    pub slaves: Vec<SlaveModel>,
+    pub dirty: Vec<ModelKey>,
+}
+
+/// Host-side copy of a device's configuration. Every read and write that goes
+/// through `HidManager` updates it; edits can be staged here and written in one
+/// go with `commit_changes`, which only sends the cells that changed.
+#[derive(Debug, Default)]
+pub struct DeviceModel {
+    device_info: Option<DeviceInfo>,
+    slave_info: BTreeMap<u8, DeviceInfo>,
+    layout: Option<BoardLayoutInfo>,
+    cells: BTreeMap<ModelKey, Cell>,
+}
+
+impl DeviceModel {
+    /// Forget everything, e.g. when a different device is connected
+    pub fn clear(&mut self) {
+        *self = DeviceModel::default();
+    }
+
+    pub fn device_info(&self) -> Option<DeviceInfo> {
+        self.device_info.clone()
+    }
+
+    pub fn set_device_info(&mut self, info: &DeviceInfo) {
+        self.device_info = Some(info.clone());
+    }
+
+    pub fn slave_info(&self, slave_addr: u8) -> Option<DeviceInfo> {
+        self.slave_info.get(&slave_addr).cloned()
+    }
+
+    pub fn set_slave_info(&mut self, slave_addr: u8, info: &DeviceInfo) {
+        self.slave_info.insert(slave_addr, info.clone());
+    }
+
+    pub fn layout(&self) -> Option<BoardLayoutInfo> {
+        self.layout.clone()
+    }
+
+    pub fn set_layout(&mut self, layout: &BoardLayoutInfo) {
+        self.layout = Some(layout.clone());
+    }
+
+    /// The device was read back as holding `entry`; a staged edit stays staged
+    /// unless the device already matches it
+    pub fn record_read(&mut self, entry: ModelEntry) {
+        let cell = self.cells.entry(entry.key()).or_default();
+        if cell.staged.as_ref() == Some(&entry) {
+            cell.staged = None;
+        }
+        cell.device = Some(entry);
+    }
+
+    /// `entry` was written to the device, replacing anything staged for it
+    pub fn record_written(&mut self, entry: ModelEntry) {
+        let cell = self.cells.entry(entry.key()).or_default();
+        cell.staged = None;
+        cell.device = Some(entry);
+    }
+
+    /// Drop cached values the device may have changed on its own
+    pub fn forget(&mut self, mut stale: impl FnMut(&ModelKey) -> bool) {
+        self.cells.retain(|key, cell| !stale(key) || cell.staged.is_some());
+        for (_, cell) in self.cells.iter_mut().filter(|(key, _)| stale(key)) {
+            cell.device = None;
+        }
+    }
+
+    /// Keep `entry` for the next commit. Staging the value the device already
+    /// holds un-dirties the cell.
+    pub fn stage(&mut self, entry: ModelEntry) {
+        let cell = self.cells.entry(entry.key()).or_default();
+        cell.staged = if cell.device.as_ref() == Some(&entry) { None } else { Some(entry) };
+    }
+
+    /// Staged value if there is one, else the device's
+    pub fn get(&self, key: &ModelKey) -> Option<ModelEntry> {
+        self.cells.get(key).and_then(Cell::current).cloned()
+    }
+
+    /// What the device held when last read or written
+    pub fn device_value(&self, key: &ModelKey) -> Option<ModelEntry> {
+        self.cells.get(key).and_then(|cell| cell.device.clone())
+    }
+
+    /// Staged edits in key order
+    pub fn dirty(&self) -> Vec<ModelEntry> {
+        self.cells.values().filter_map(|cell| cell.staged.clone()).collect()
+    }
+
+    /// Drop every staged edit; returns how many there were
+    pub fn discard(&mut self) -> usize {
+        let mut discarded = 0;
+        for cell in self.cells.values_mut() {
+            discarded += cell.staged.take().is_some() as usize;
+        }
+        self.cells.retain(|_, cell| cell.device.is_some());
+        discarded
+    }
+
+    /// A whole keymap layer, if every cell of it is cached
+    pub fn keymap_layer(&self, layer: u8, rows: u8, cols: u8) -> Option<Vec<Vec<KeymapEntry>>> {
+        (0..rows)
+            .map(|row| {
+                (0..cols)
+                    .map(|col| match self.get(&ModelKey::Keymap { layer, row, col }) {
+                        Some(ModelEntry::Keymap(entry)) => Some(entry),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Every encoder binding on a layer, if all of them are cached
+    pub fn encoder_layer(&self, layer: u8, encoder_count: u8) -> Option<Vec<EncoderEntry>> {
+        (0..encoder_count)
+            .map(|encoder_id| match self.get(&ModelKey::Encoder { layer, encoder_id }) {
+                Some(ModelEntry::Encoder(entry)) => Some(entry),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn snapshot(&self) -> ModelSnapshot {
+        let (layers, rows, cols, encoder_count) = self
+            .device_info
+            .as_ref()
+            .map_or((0, 0, 0, 0), |info| (info.layer_count.max(1), info.matrix_rows, info.matrix_cols, info.encoder_count));
+
+        let mut snapshot = ModelSnapshot {
+            device_info: self.device_info.clone(),
+            layout: self.layout.clone(),
+            layer_state: None,
+            keymap: (0..layers).map_while(|layer| self.keymap_layer(layer, rows, cols)).collect(),
+            encoders: (0..layers).map_while(|layer| self.encoder_layer(layer, encoder_count)).collect(),
+            sliders: Vec::new(),
+            magnetic_switches: Vec::new(),
+            slaves: Vec::new(),
+            dirty: self.cells.iter().filter(|(_, cell)| cell.staged.is_some()).map(|(key, _)| *key).collect(),
+        };
+
+        let mut slaves: BTreeMap<u8, SlaveModel> = BTreeMap::new();
+        for entry in self.cells.values().filter_map(Cell::current) {
+            match entry {
+                ModelEntry::LayerState(state) => snapshot.layer_state = Some(state.clone()),
+                ModelEntry::Slider(config) => snapshot.sliders.push(config.clone()),
+                ModelEntry::MagneticSwitch(config) => snapshot.magnetic_switches.push(config.clone()),
+                ModelEntry::SlaveKeymap(e) => slave_model(&mut slaves, e.slave_addr).keymap.push(e.clone()),
+                ModelEntry::SlaveEncoder(e) => slave_model(&mut slaves, e.slave_addr).encoders.push(e.clone()),
+                ModelEntry::Keymap(_) | ModelEntry::Encoder(_) => {}
+            }
+        }
+        snapshot.slaves = slaves.into_values().collect();
+        snapshot
+    }
+}
+
+fn slave_model(slaves: &mut BTreeMap<u8, SlaveModel>, slave_addr: u8) -> &mut SlaveModel {
+    slaves.entry(slave_addr).or_insert_with(|| SlaveModel { slave_addr, keymap: Vec::new(), encoders: Vec::new() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ConfigCommand;
+    use crate::simulator::{connected_simulator, SimulatedDevice, SimulatorTopology};
+
+    #[tokio::test]
+    async fn commit_writes_only_cells_that_differ_from_the_device() {
+        let (manager, _sim) = connected_simulator(SimulatedDevice::new());
+        let a = manager.get_keymap_entry(0, 0, 0).await.unwrap();
+        let b = manager.get_keymap_entry(0, 0, 1).await.unwrap();
+
+        // Staging a cell back to what the device holds leaves it clean
+        manager.model().stage(ModelEntry::Keymap(KeymapEntry { keycode: 0x2C, ..a.clone() }));
+        manager.model().stage(ModelEntry::Keymap(a.clone()));
+        manager.model().stage(ModelEntry::Keymap(KeymapEntry { keycode: 0x28, ..b.clone() }));
+        assert_eq!(manager.model().snapshot().dirty, vec![ModelKey::Keymap { layer: 0, row: 0, col: 1 }]);
+
+        // Reads see the staged value without touching the device
+        let key = ModelKey::Keymap { layer: 0, row: 0, col: 1 };
+        assert_eq!(manager.model().get(&key), Some(ModelEntry::Keymap(KeymapEntry { keycode: 0x28, ..b.clone() })));
+
+        assert_eq!(manager.commit_changes().await.unwrap(), 1);
+        assert!(manager.model().dirty().is_empty());
+        let stats = manager.link_stats();
+        let writes = stats.commands.iter().find(|c| c.command == Some(ConfigCommand::SetKeymap)).map(|c| c.requests);
+        assert_eq!(writes, Some(1));
+        assert_eq!(manager.get_keymap_entry(0, 0, 1).await.unwrap().keycode, 0x28);
+
+        manager.model().stage(ModelEntry::Keymap(KeymapEntry { keycode: 0x29, ..a }));
+        assert_eq!(manager.model().discard(), 1);
+        assert_eq!(manager.commit_changes().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn sparse_commit_writes_each_cell_where_it_belongs() {
+        let (manager, _sim) = connected_simulator(SimulatedDevice::new());
+        let neighbour = manager.get_keymap_entry(0, 0, 1).await.unwrap();
+
+        // Two cells of one column: not a layer, so they must not go out as a bulk range
+        manager.model().stage(ModelEntry::Keymap(KeymapEntry { layer: 0, row: 0, col: 0, keycode: 0x2C }));
+        manager.model().stage(ModelEntry::Keymap(KeymapEntry { layer: 0, row: 1, col: 0, keycode: 0x2D }));
+        assert_eq!(manager.commit_changes().await.unwrap(), 2);
+
+        assert_eq!(manager.get_keymap_entry(0, 0, 0).await.unwrap().keycode, 0x2C);
+        assert_eq!(manager.get_keymap_entry(0, 1, 0).await.unwrap().keycode, 0x2D);
+        assert_eq!(manager.get_keymap_entry(0, 0, 1).await.unwrap(), neighbour);
+    }
+
+    #[tokio::test]
+    async fn failed_commit_keeps_the_edits_staged() {
+        let topology: SimulatorTopology =
+            serde_json::from_str(r#"{ "slaves": [{ "address": 32, "matrix_rows": 1, "matrix_cols": 1 }] }"#).unwrap();
+        let (manager, transport) = connected_simulator(SimulatedDevice::with_topology(&topology).unwrap());
+        let slave = manager.get_slave_keymap_entry(32, 0, 0, 0).await.unwrap();
+        let key = manager.get_keymap_entry(0, 0, 0).await.unwrap();
+        assert!(transport.with_device(|d| d.set_slave_online(32, false)));
+
+        let edits = vec![
+            ModelEntry::Keymap(KeymapEntry { keycode: 0x2C, ..key.clone() }),
+            ModelEntry::SlaveKeymap(SlaveKeymapEntry { keycode: 0x2D, ..slave }),
+        ];
+        for edit in &edits {
+            manager.model().stage(edit.clone());
+        }
+        assert!(manager.commit_changes().await.is_err());
+
+        // Rolled back on the device, still pending in the model
+        assert_eq!(manager.get_keymap_entry(0, 0, 0).await.unwrap(), key);
+        assert_eq!(manager.model().dirty(), edits);
+    }
+}
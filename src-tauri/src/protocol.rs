@@ -1,7 +1,10 @@
+use crate::error::ConfigError;
 use serde::{Deserialize, Serialize};
 
 /// Configuration Protocol Version
-pub const CONFIG_PROTOCOL_VERSION: u8 = 1;
+pub const CONFIG_PROTOCOL_VERSION: u8 = 2;
+/// Oldest protocol version the configurator can still talk to (no `GetCapabilities`)
+pub const CONFIG_MIN_PROTOCOL_VERSION: u8 = 1;
 
 /// Packet header and sizes
 pub const CONFIG_PACKET_HEADER: u16 = 0x4F47; // "OG" - will send as [0x47, 0x4F] in little-endian
@@ -9,7 +12,7 @@ pub const CONFIG_MAX_PAYLOAD_SIZE: usize = 56; // 64 - 8 (header + command + sta
 pub const CONFIG_PACKET_SIZE: usize = 64;
 
 /// Command types
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ConfigCommand {
     GetInfo = 0x01,
     GetKeymap = 0x02,
@@ -48,11 +51,95 @@ pub enum ConfigCommand {
     SetMagneticSwitchConfig = 0x20,
     CalibrateMagneticSwitch = 0x21,
     SetMagneticSwitchSensitivity = 0x22,
+    // Capability negotiation (protocol v2)
+    GetCapabilities = 0x23,
+    // Bulk transfers (protocol v2, optional)
+    GetKeymapBulk = 0x24,
+    SetKeymapBulk = 0x25,
+    GetEncoderMapBulk = 0x26,
+    // Chunked transfers for payloads over CONFIG_MAX_PAYLOAD_SIZE (protocol v2, optional)
+    LargeWrite = 0x27,
+    LargeRead = 0x28,
+    // Device -> host only, sent as an unsolicited event (protocol v2)
+    KeyEvent = 0x29,
+    // Slider/potentiometer streaming (protocol v2, optional)
+    SubscribeSliders = 0x2A,
+    UnsubscribeSliders = 0x2B,
+    SliderValues = 0x2C, // device -> host event
 }
-                         
-impl From<u8> for ConfigCommand {
-    fn from(value: u8) -> Self {
-        match value {
+
+impl ConfigCommand {
+    /// Every command, in wire order
+    pub const ALL: [ConfigCommand; 44] = [
+        ConfigCommand::GetInfo,
+        ConfigCommand::GetKeymap,
+        ConfigCommand::SetKeymap,
+        ConfigCommand::GetEncoderMap,
+        ConfigCommand::SetEncoderMap,
+        ConfigCommand::SaveConfig,
+        ConfigCommand::LoadConfig,
+        ConfigCommand::ResetConfig,
+        ConfigCommand::GetI2CDevices,
+        ConfigCommand::SetI2CConfig,
+        ConfigCommand::GetDeviceStatus,
+        ConfigCommand::Reboot,
+        ConfigCommand::MidiSendRaw,
+        ConfigCommand::MidiNoteOn,
+        ConfigCommand::MidiNoteOff,
+        ConfigCommand::MidiControlChange,
+        ConfigCommand::GetSlaveKeymap,
+        ConfigCommand::SetSlaveKeymap,
+        ConfigCommand::GetSlaveInfo,
+        ConfigCommand::GetSlaveEncoder,
+        ConfigCommand::SetSlaveEncoder,
+        ConfigCommand::GetLayoutInfo,
+        ConfigCommand::SetLayerState,
+        ConfigCommand::GetLayerState,
+        ConfigCommand::GetLayoutCellType,
+        ConfigCommand::GetLayoutCellComponentId,
+        ConfigCommand::GetSliderValue,
+        ConfigCommand::GetSliderConfig,
+        ConfigCommand::SetSliderConfig,
+        ConfigCommand::GetMagneticSwitchValue,
+        ConfigCommand::GetMagneticSwitchConfig,
+        ConfigCommand::SetMagneticSwitchConfig,
+        ConfigCommand::CalibrateMagneticSwitch,
+        ConfigCommand::SetMagneticSwitchSensitivity,
+        ConfigCommand::GetCapabilities,
+        ConfigCommand::GetKeymapBulk,
+        ConfigCommand::SetKeymapBulk,
+        ConfigCommand::GetEncoderMapBulk,
+        ConfigCommand::LargeWrite,
+        ConfigCommand::LargeRead,
+        ConfigCommand::KeyEvent,
+        ConfigCommand::SubscribeSliders,
+        ConfigCommand::UnsubscribeSliders,
+        ConfigCommand::SliderValues,
+    ];
+
+    /// Commands the device only ever sends as unsolicited events, never answers
+    pub fn is_event_only(self) -> bool {
+        matches!(self, ConfigCommand::KeyEvent | ConfigCommand::SliderValues)
+    }
+
+    /// Commands the master relays over I2C and answers only once the slave has
+    pub fn is_slave_forwarded(self) -> bool {
+        matches!(
+            self,
+            ConfigCommand::GetSlaveKeymap
+                | ConfigCommand::SetSlaveKeymap
+                | ConfigCommand::GetSlaveInfo
+                | ConfigCommand::GetSlaveEncoder
+                | ConfigCommand::SetSlaveEncoder
+        )
+    }
+}
+
+impl TryFrom<u8> for ConfigCommand {
+    type Error = ConfigError;
+
+    fn try_from(value: u8) -> Result<Self, ConfigError> {
+        let command = match value {
             0x01 => ConfigCommand::GetInfo,
             0x02 => ConfigCommand::GetKeymap,
             0x03 => ConfigCommand::SetKeymap,
@@ -87,13 +174,24 @@ impl From<u8> for ConfigCommand {
             0x20 => ConfigCommand::SetMagneticSwitchConfig,
             0x21 => ConfigCommand::CalibrateMagneticSwitch,
             0x22 => ConfigCommand::SetMagneticSwitchSensitivity,
-            _ => ConfigCommand::GetInfo, // Default fallback
-        }
+            0x23 => ConfigCommand::GetCapabilities,
+            0x24 => ConfigCommand::GetKeymapBulk,
+            0x25 => ConfigCommand::SetKeymapBulk,
+            0x26 => ConfigCommand::GetEncoderMapBulk,
+            0x27 => ConfigCommand::LargeWrite,
+            0x28 => ConfigCommand::LargeRead,
+            0x29 => ConfigCommand::KeyEvent,
+            0x2A => ConfigCommand::SubscribeSliders,
+            0x2B => ConfigCommand::UnsubscribeSliders,
+            0x2C => ConfigCommand::SliderValues,
+            _ => return Err(ConfigError::UnknownCommand { byte: value }),
+        };
+        Ok(command)
     }
 }
 
 /// Status codes
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum StatusCode {
     Ok = 0x00,
     Error = 0x01,          // STATUS_ERROR - generic error
@@ -103,23 +201,41 @@ pub enum StatusCode {
     NotSupported = 0x05,   // STATUS_NOT_SUPPORTED - feature not supported
 }
 
-impl From<u8> for StatusCode {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for StatusCode {
+    type Error = ConfigError;
+
+    fn try_from(value: u8) -> Result<Self, ConfigError> {
         match value {
-            0x00 => StatusCode::Ok,
-            0x01 => StatusCode::Error,
-            0x02 => StatusCode::InvalidCmd,
-            0x03 => StatusCode::InvalidParam,
-            0x04 => StatusCode::Busy,
-            0x05 => StatusCode::NotSupported,
-            _ => StatusCode::Error, // Default fallback
+            0x00 => Ok(StatusCode::Ok),
+            0x01 => Ok(StatusCode::Error),
+            0x02 => Ok(StatusCode::InvalidCmd),
+            0x03 => Ok(StatusCode::InvalidParam),
+            0x04 => Ok(StatusCode::Busy),
+            0x05 => Ok(StatusCode::NotSupported),
+            _ => Err(ConfigError::UnknownStatus { byte: value }),
         }
     }
 }
 
+/// Wire layout of the 8-byte packet header (firmware config_packet_t).
+/// Multi-byte fields are little-endian. `to_bytes` and `from_bytes` both go
+/// through these offsets, so they cannot disagree on field order.
+const OFFSET_HEADER: usize = 0; // u16
+const OFFSET_COMMAND: usize = 2;
+const OFFSET_STATUS: usize = 3;
+const OFFSET_SEQUENCE: usize = 4;
+const OFFSET_PAYLOAD_LENGTH: usize = 5;
+const OFFSET_RESERVED: usize = 6; // [u8; 2]
+const OFFSET_PAYLOAD: usize = 8;
+
+const _: () = assert!(OFFSET_PAYLOAD + CONFIG_MAX_PAYLOAD_SIZE == CONFIG_PACKET_SIZE);
+
+/// Set in `reserved[0]` on packets the device sends on its own rather than in
+/// answer to a request
+pub const PACKET_FLAG_UNSOLICITED: u8 = 0x01;
+
 /// Configuration packet structure (matches firmware)
-#[repr(C, packed)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ConfigPacket {
     pub header: u16,
     pub command: u8,
@@ -131,55 +247,97 @@ pub struct ConfigPacket {
 }
 
 impl ConfigPacket {
-    pub fn new(command: ConfigCommand, sequence: u8, payload: &[u8]) -> Self {
+    /// Build a request; payloads over `CONFIG_MAX_PAYLOAD_SIZE` are rejected, use
+    /// `HidManager::send_large` for those
+    pub fn new(command: ConfigCommand, sequence: u8, payload: &[u8]) -> Result<Self, ConfigError> {
+        if payload.len() > CONFIG_MAX_PAYLOAD_SIZE {
+            return Err(ConfigError::PayloadTooLarge { length: payload.len(), max: CONFIG_MAX_PAYLOAD_SIZE });
+        }
+
         let mut packet = ConfigPacket {
             header: CONFIG_PACKET_HEADER,
             command: command as u8,
-            sequence,
             status: StatusCode::Ok as u8,
-            payload_length: payload.len().min(CONFIG_MAX_PAYLOAD_SIZE) as u8,
+            sequence,
+            payload_length: payload.len() as u8,
             reserved: [0; 2],
             payload: [0; CONFIG_MAX_PAYLOAD_SIZE],
         };
-        
-        let len = payload.len().min(CONFIG_MAX_PAYLOAD_SIZE);
-        packet.payload[..len].copy_from_slice(&payload[..len]);
-        
-        packet
+        packet.payload[..payload.len()].copy_from_slice(payload);
+
+        Ok(packet)
     }
 
-    pub fn to_bytes(&self) -> [u8; CONFIG_PACKET_SIZE] {
-        // Since we have a packed C struct, we can safely transmute it to bytes
-        // This ensures the exact same memory layout as the C struct
-        unsafe {
-            let ptr = self as *const ConfigPacket as *const u8;
-            let mut bytes = [0u8; CONFIG_PACKET_SIZE];
-            std::ptr::copy_nonoverlapping(ptr, bytes.as_mut_ptr(), CONFIG_PACKET_SIZE);
-            bytes
-        }
+    /// Build a device-initiated event; events carry sequence 0 and never answer a request
+    pub fn event(command: ConfigCommand, payload: &[u8]) -> Result<Self, ConfigError> {
+        let mut packet = ConfigPacket::new(command, 0, payload)?;
+        packet.reserved[0] |= PACKET_FLAG_UNSOLICITED;
+        Ok(packet)
     }
-    
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
-        if bytes.len() < CONFIG_PACKET_SIZE {
-            return Err("Packet too short".to_string());
+
+    pub fn is_unsolicited(&self) -> bool {
+        self.reserved[0] & PACKET_FLAG_UNSOLICITED != 0
+    }
+
+    /// The valid part of the payload as announced by `payload_length`
+    pub fn payload_bytes(&self) -> &[u8] {
+        &self.payload[..(self.payload_length as usize).min(CONFIG_MAX_PAYLOAD_SIZE)]
+    }
+
+    /// Decoded command byte
+    pub fn command(&self) -> Result<ConfigCommand, ConfigError> {
+        ConfigCommand::try_from(self.command)
+    }
+
+    /// Decoded status byte
+    pub fn status(&self) -> Result<StatusCode, ConfigError> {
+        StatusCode::try_from(self.status)
+    }
+
+    /// Make sure a response echoes the command it answers
+    pub fn check_echo(&self, request: ConfigCommand) -> Result<(), ConfigError> {
+        if self.command != request as u8 {
+            return Err(ConfigError::CommandMismatch { expected: request, actual: self.command });
         }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> [u8; CONFIG_PACKET_SIZE] {
+        let mut bytes = [0u8; CONFIG_PACKET_SIZE];
+        bytes[OFFSET_HEADER..OFFSET_HEADER + 2].copy_from_slice(&self.header.to_le_bytes());
+        bytes[OFFSET_COMMAND] = self.command;
+        bytes[OFFSET_STATUS] = self.status;
+        bytes[OFFSET_SEQUENCE] = self.sequence;
+        bytes[OFFSET_PAYLOAD_LENGTH] = self.payload_length;
+        bytes[OFFSET_RESERVED..OFFSET_RESERVED + 2].copy_from_slice(&self.reserved);
+        bytes[OFFSET_PAYLOAD..].copy_from_slice(&self.payload);
+        bytes
+    }
 
-        let header = u16::from_le_bytes([bytes[0], bytes[1]]);
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ConfigError> {
+        ConfigError::check_len("Packet", bytes, CONFIG_PACKET_SIZE)?;
+
+        let header = u16::from_le_bytes([bytes[OFFSET_HEADER], bytes[OFFSET_HEADER + 1]]);
         if header != CONFIG_PACKET_HEADER {
-            return Err(format!("Invalid header: 0x{:04X}", header));
+            return Err(ConfigError::protocol(format!("Invalid header: 0x{:04X}", header)));
+        }
+
+        let payload_length = bytes[OFFSET_PAYLOAD_LENGTH];
+        if payload_length as usize > CONFIG_MAX_PAYLOAD_SIZE {
+            return Err(ConfigError::PayloadLength { length: payload_length, max: CONFIG_MAX_PAYLOAD_SIZE });
         }
+        StatusCode::try_from(bytes[OFFSET_STATUS])?;
 
         let mut packet = ConfigPacket {
             header,
-            command: bytes[2],
-            sequence: bytes[4],      // sequence at position 4 in firmware
-            status: bytes[3],        // status at position 3 in firmware
-            payload_length: bytes[5],
-            reserved: [bytes[6], bytes[7]],
+            command: bytes[OFFSET_COMMAND],
+            status: bytes[OFFSET_STATUS],
+            sequence: bytes[OFFSET_SEQUENCE],
+            payload_length,
+            reserved: [bytes[OFFSET_RESERVED], bytes[OFFSET_RESERVED + 1]],
             payload: [0; CONFIG_MAX_PAYLOAD_SIZE],
         };
-
-        packet.payload.copy_from_slice(&bytes[8..8 + CONFIG_MAX_PAYLOAD_SIZE]);
+        packet.payload.copy_from_slice(&bytes[OFFSET_PAYLOAD..CONFIG_PACKET_SIZE]);
 
         Ok(packet)
     }
@@ -202,10 +360,8 @@ pub struct DeviceInfo {
 }
 
 impl DeviceInfo {
-    pub fn from_payload(payload: &[u8]) -> Result<Self, String> {
-        if payload.len() < 56 {
-            return Err("Device info payload too short".to_string());
-        }
+    pub fn from_payload(payload: &[u8]) -> Result<Self, ConfigError> {
+        ConfigError::check_len("Device info", payload, 56)?;
 
         // Extract device name (null-terminated string)
         let name_bytes = &payload[10..42];
@@ -229,7 +385,7 @@ impl DeviceInfo {
 }
 
 /// Keymap entry structure (matches firmware)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct KeymapEntry {
     pub layer: u8,
     pub row: u8,
@@ -238,7 +394,7 @@ pub struct KeymapEntry {
 }
 
 /// Slave keymap entry structure (matches firmware)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SlaveKeymapEntry {
     pub slave_addr: u8,
     pub layer: u8,
@@ -248,7 +404,7 @@ pub struct SlaveKeymapEntry {
 }
 
 /// Slave encoder entry structure (matches firmware)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SlaveEncoderEntry {
     pub slave_addr: u8,
     pub layer: u8,
@@ -259,10 +415,8 @@ pub struct SlaveEncoderEntry {
 }
 
 impl KeymapEntry {
-    pub fn from_payload(payload: &[u8]) -> Result<Self, String> {
-        if payload.len() < 5 {
-            return Err("Keymap entry payload too short".to_string());
-        }
+    pub fn from_payload(payload: &[u8]) -> Result<Self, ConfigError> {
+        ConfigError::check_len("Keymap entry", payload, 5)?;
 
         Ok(KeymapEntry {
             layer: payload[0],
@@ -274,10 +428,8 @@ impl KeymapEntry {
 }
 
 impl SlaveKeymapEntry {
-    pub fn from_payload(slave_addr: u8, payload: &[u8]) -> Result<Self, String> {
-        if payload.len() < 5 {
-            return Err("Slave keymap entry payload too short".to_string());
-        }
+    pub fn from_payload(slave_addr: u8, payload: &[u8]) -> Result<Self, ConfigError> {
+        ConfigError::check_len("Slave keymap entry", payload, 5)?;
 
         Ok(SlaveKeymapEntry {
             slave_addr,
@@ -311,7 +463,7 @@ impl KeymapEntry {
 }
 
 /// Encoder entry structure (matches firmware)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct EncoderEntry {
     pub layer: u8,
     pub encoder_id: u8,
@@ -321,10 +473,8 @@ pub struct EncoderEntry {
 }
 
 impl EncoderEntry {
-    pub fn from_payload(payload: &[u8]) -> Result<Self, String> {
-        if payload.len() < 7 {
-            return Err("Encoder entry payload too short".to_string());
-        }
+    pub fn from_payload(payload: &[u8]) -> Result<Self, ConfigError> {
+        ConfigError::check_len("Encoder entry", payload, 7)?;
 
         Ok(EncoderEntry {
             layer: payload[0],
@@ -346,6 +496,190 @@ impl EncoderEntry {
     }
 }
 
+/// Keycodes per `GetKeymapBulk`/`SetKeymapBulk` packet: 4-byte range header + 2 bytes per key
+pub const KEYMAP_BULK_MAX_KEYS: usize = (CONFIG_MAX_PAYLOAD_SIZE - 4) / 2;
+
+/// Encoders per `GetEncoderMapBulk` packet: 3-byte range header + 4 bytes per encoder
+pub const ENCODER_BULK_MAX: usize = (CONFIG_MAX_PAYLOAD_SIZE - 3) / 4;
+
+/// Consecutive keymap cells on one layer, indexed row-major (`row * matrix_cols + col`).
+/// Payload: [layer, start_lo, start_hi, count, keycode_lo, keycode_hi, ...]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeymapRange {
+    pub layer: u8,
+    pub start: u16,
+    pub keycodes: Vec<u16>,
+}
+
+impl KeymapRange {
+    /// `GetKeymapBulk` request for `count` cells starting at `start`
+    pub fn request_payload(layer: u8, start: u16, count: u8) -> [u8; 4] {
+        let [start_lo, start_hi] = start.to_le_bytes();
+        [layer, start_lo, start_hi, count]
+    }
+
+    pub fn from_payload(payload: &[u8]) -> Result<Self, ConfigError> {
+        ConfigError::check_len("Keymap range", payload, 4)?;
+        let count = payload[3] as usize;
+        ConfigError::check_len("Keymap range", payload, 4 + count * 2)?;
+
+        Ok(KeymapRange {
+            layer: payload[0],
+            start: u16::from_le_bytes([payload[1], payload[2]]),
+            keycodes: payload[4..4 + count * 2]
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect(),
+        })
+    }
+
+    pub fn to_payload(&self) -> Vec<u8> {
+        let mut payload = Self::request_payload(self.layer, self.start, self.keycodes.len() as u8).to_vec();
+        for keycode in &self.keycodes {
+            payload.extend_from_slice(&keycode.to_le_bytes());
+        }
+        payload
+    }
+}
+
+/// Consecutive encoders on one layer.
+/// Payload: [layer, start, count, (ccw_lo, ccw_hi, cw_lo, cw_hi)...]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncoderRange {
+    pub layer: u8,
+    pub start: u8,
+    /// (ccw_keycode, cw_keycode) per encoder
+    pub bindings: Vec<(u16, u16)>,
+}
+
+impl EncoderRange {
+    pub fn from_payload(payload: &[u8]) -> Result<Self, ConfigError> {
+        ConfigError::check_len("Encoder range", payload, 3)?;
+        let count = payload[2] as usize;
+        ConfigError::check_len("Encoder range", payload, 3 + count * 4)?;
+
+        Ok(EncoderRange {
+            layer: payload[0],
+            start: payload[1],
+            bindings: payload[3..3 + count * 4]
+                .chunks_exact(4)
+                .map(|b| (u16::from_le_bytes([b[0], b[1]]), u16::from_le_bytes([b[2], b[3]])))
+                .collect(),
+        })
+    }
+
+    pub fn to_payload(&self) -> Vec<u8> {
+        let mut payload = vec![self.layer, self.start, self.bindings.len() as u8];
+        for (ccw, cw) in &self.bindings {
+            payload.extend_from_slice(&ccw.to_le_bytes());
+            payload.extend_from_slice(&cw.to_le_bytes());
+        }
+        payload
+    }
+}
+
+/// Largest blob a chunked transfer can carry (offsets and totals are u16)
+pub const LARGE_TRANSFER_MAX: usize = u16::MAX as usize;
+
+/// Data bytes per `LargeWrite` chunk: [target, offset(2), total(2), crc32(4)] + data
+pub const LARGE_WRITE_CHUNK: usize = CONFIG_MAX_PAYLOAD_SIZE - 9;
+
+/// Data bytes per `LargeRead` chunk: [offset(2), total(2), crc32(4)] + data
+pub const LARGE_READ_CHUNK: usize = CONFIG_MAX_PAYLOAD_SIZE - 8;
+
+/// CRC-32 (IEEE 802.3, reflected, as in zlib) guarding each transfer chunk
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// One host -> device piece of a chunked transfer addressed to `target`.
+/// The device acks with the little-endian offset it expects next; an ack that
+/// doesn't cover this chunk (gap or CRC failure) means "resend from there".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LargeWriteChunk {
+    pub target: u8,
+    pub offset: u16,
+    pub total: u16,
+    pub data: Vec<u8>,
+}
+
+impl LargeWriteChunk {
+    pub fn from_payload(payload: &[u8]) -> Result<Self, ConfigError> {
+        ConfigError::check_len("Large write chunk", payload, 9)?;
+        let offset = u16::from_le_bytes([payload[1], payload[2]]);
+        let data = payload[9..].to_vec();
+        let crc = u32::from_le_bytes([payload[5], payload[6], payload[7], payload[8]]);
+        if crc32(&data) != crc {
+            return Err(ConfigError::ChecksumMismatch { offset });
+        }
+
+        Ok(LargeWriteChunk {
+            target: payload[0],
+            offset,
+            total: u16::from_le_bytes([payload[3], payload[4]]),
+            data,
+        })
+    }
+
+    pub fn to_payload(&self) -> Vec<u8> {
+        let mut payload = vec![self.target];
+        payload.extend_from_slice(&self.offset.to_le_bytes());
+        payload.extend_from_slice(&self.total.to_le_bytes());
+        payload.extend_from_slice(&crc32(&self.data).to_le_bytes());
+        payload.extend_from_slice(&self.data);
+        payload
+    }
+}
+
+/// One device -> host piece of a chunked transfer, answering a `LargeRead`
+/// request of [target, offset(2), args...]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LargeReadChunk {
+    pub offset: u16,
+    pub total: u16,
+    pub data: Vec<u8>,
+}
+
+impl LargeReadChunk {
+    pub fn request_payload(target: u8, offset: u16, args: &[u8]) -> Vec<u8> {
+        let mut payload = vec![target];
+        payload.extend_from_slice(&offset.to_le_bytes());
+        payload.extend_from_slice(args);
+        payload
+    }
+
+    pub fn from_payload(payload: &[u8]) -> Result<Self, ConfigError> {
+        ConfigError::check_len("Large read chunk", payload, 8)?;
+        let offset = u16::from_le_bytes([payload[0], payload[1]]);
+        let data = payload[8..].to_vec();
+        let crc = u32::from_le_bytes([payload[4], payload[5], payload[6], payload[7]]);
+        if crc32(&data) != crc {
+            return Err(ConfigError::ChecksumMismatch { offset });
+        }
+
+        Ok(LargeReadChunk {
+            offset,
+            total: u16::from_le_bytes([payload[2], payload[3]]),
+            data,
+        })
+    }
+
+    pub fn to_payload(&self) -> Vec<u8> {
+        let mut payload = self.offset.to_le_bytes().to_vec();
+        payload.extend_from_slice(&self.total.to_le_bytes());
+        payload.extend_from_slice(&crc32(&self.data).to_le_bytes());
+        payload.extend_from_slice(&self.data);
+        payload
+    }
+}
+
 /// I2C device info structure (matches firmware)  
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct I2CDeviceInfo {
@@ -385,10 +719,8 @@ impl I2CDeviceInfo {
 }
 
 impl SlaveEncoderEntry {
-    pub fn from_payload(slave_addr: u8, payload: &[u8]) -> Result<Self, String> {
-        if payload.len() < 7 {
-            return Err("Slave encoder entry payload too short".to_string());
-        }
+    pub fn from_payload(slave_addr: u8, payload: &[u8]) -> Result<Self, ConfigError> {
+        ConfigError::check_len("Slave encoder entry", payload, 7)?;
 
         Ok(SlaveEncoderEntry {
             slave_addr,
@@ -413,17 +745,15 @@ impl SlaveEncoderEntry {
 }
 
 /// Layer state payload (active layer mask + default layer index)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LayerState {
     pub active_mask: u8,
     pub default_layer: u8,
 }
 
 impl LayerState {
-    pub fn from_payload(payload: &[u8]) -> Result<Self, String> {
-        if payload.len() < 2 {
-            return Err("Layer state payload too short".to_string());
-        }
+    pub fn from_payload(payload: &[u8]) -> Result<Self, ConfigError> {
+        ConfigError::check_len("Layer state", payload, 2)?;
 
         Ok(LayerState {
             active_mask: payload[0],
@@ -449,6 +779,15 @@ pub enum LayoutCellType {
 }
 
 impl LayoutCellType {
+    pub const ALL: [LayoutCellType; 6] = [
+        LayoutCellType::Empty,
+        LayoutCellType::Switch,
+        LayoutCellType::Encoder,
+        LayoutCellType::Slider,
+        LayoutCellType::Potentiometer,
+        LayoutCellType::MagneticSwitch,
+    ];
+
     pub fn from_u8(value: u8) -> Option<Self> {
         match value {
             0 => Some(LayoutCellType::Empty),
@@ -462,6 +801,71 @@ impl LayoutCellType {
     }
 }
 
+/// What the connected device implements, learned during the connect handshake
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceCapabilities {
+    pub protocol_version: u8,
+    /// Bit N set = command byte N is implemented
+    pub command_bitmap: [u8; 32],
+    /// Bit N set = `LayoutCellType` N may appear in the layout
+    pub cell_type_mask: u8,
+}
+
+impl DeviceCapabilities {
+    /// Payload layout: [protocol_version, cell_type_mask, command_bitmap(32)]
+    pub fn from_payload(payload: &[u8]) -> Result<Self, ConfigError> {
+        ConfigError::check_len("Capabilities", payload, 34)?;
+
+        let mut command_bitmap = [0u8; 32];
+        command_bitmap.copy_from_slice(&payload[2..34]);
+        Ok(DeviceCapabilities {
+            protocol_version: payload[0],
+            command_bitmap,
+            cell_type_mask: payload[1],
+        })
+    }
+
+    pub fn to_payload(&self) -> Vec<u8> {
+        let mut payload = vec![self.protocol_version, self.cell_type_mask];
+        payload.extend_from_slice(&self.command_bitmap);
+        payload
+    }
+
+    /// Capabilities built from explicit command and cell type lists
+    pub fn from_parts(protocol_version: u8, commands: &[ConfigCommand], cell_types: &[LayoutCellType]) -> Self {
+        let mut capabilities = DeviceCapabilities {
+            protocol_version,
+            command_bitmap: [0; 32],
+            cell_type_mask: 0,
+        };
+        for &command in commands {
+            let bit = command as u8;
+            capabilities.command_bitmap[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+        for &cell_type in cell_types {
+            capabilities.cell_type_mask |= 1 << (cell_type as u8);
+        }
+        capabilities
+    }
+
+    pub fn supports(&self, command: ConfigCommand) -> bool {
+        let bit = command as u8;
+        self.command_bitmap[(bit / 8) as usize] & (1 << (bit % 8)) != 0
+    }
+
+    pub fn supports_cell_type(&self, cell_type: LayoutCellType) -> bool {
+        self.cell_type_mask & (1 << (cell_type as u8)) != 0
+    }
+
+    pub fn commands(&self) -> Vec<ConfigCommand> {
+        ConfigCommand::ALL.iter().copied().filter(|&c| self.supports(c)).collect()
+    }
+
+    pub fn cell_types(&self) -> Vec<LayoutCellType> {
+        LayoutCellType::ALL.iter().copied().filter(|&t| self.supports_cell_type(t)).collect()
+    }
+}
+
 /// Layout cell definition (matches firmware layout_cell_t)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LayoutCell {
@@ -484,20 +888,11 @@ pub struct BoardLayoutInfo {
 }
 
 impl BoardLayoutInfo {
-    pub fn from_payload(payload: &[u8]) -> Result<Self, String> {
-        if payload.len() < 7 {
-            return Err("Board layout payload too short".to_string());
-        }
+    pub fn from_payload(payload: &[u8]) -> Result<Self, ConfigError> {
+        ConfigError::check_len("Board layout", payload, 7)?;
 
         let bitmap_length = payload[6] as usize;
-        let expected_len = 8 + bitmap_length;
-        if payload.len() < expected_len {
-            return Err(format!(
-                "Board layout payload truncated: expected {} bytes, got {}",
-                expected_len,
-                payload.len()
-            ));
-        }
+        ConfigError::check_len("Board layout", payload, 8 + bitmap_length)?;
 
         let mut bitmap = Vec::with_capacity(bitmap_length);
         bitmap.extend_from_slice(&payload[8..8 + bitmap_length]);
@@ -560,7 +955,7 @@ impl BoardLayoutInfo {
 }
 
 /// Slider configuration structure (matches firmware slider_config_t)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SliderConfig {
     pub layer: u8,
     pub slider_id: u8,
@@ -571,10 +966,8 @@ pub struct SliderConfig {
 }
 
 impl SliderConfig {
-    pub fn from_payload(payload: &[u8]) -> Result<Self, String> {
-        if payload.len() < 8 {
-            return Err("Slider config payload too short".to_string());
-        }
+    pub fn from_payload(payload: &[u8]) -> Result<Self, ConfigError> {
+        ConfigError::check_len("Slider config", payload, 8)?;
 
         Ok(SliderConfig {
             layer: payload[0],
@@ -601,7 +994,7 @@ impl SliderConfig {
 }
 
 /// Magnetic switch configuration structure (matches firmware magnetic_switch_config_t)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MagneticSwitchConfig {
     pub layer: u8,
     pub switch_id: u8,
@@ -613,10 +1006,8 @@ pub struct MagneticSwitchConfig {
 }
 
 impl MagneticSwitchConfig {
-    pub fn from_payload(payload: &[u8]) -> Result<Self, String> {
-        if payload.len() < 10 {
-            return Err("Magnetic switch config payload too short".to_string());
-        }
+    pub fn from_payload(payload: &[u8]) -> Result<Self, ConfigError> {
+        ConfigError::check_len("Magnetic switch config", payload, 10)?;
 
         Ok(MagneticSwitchConfig {
             layer: payload[0],
@@ -640,4 +1031,266 @@ impl MagneticSwitchConfig {
         payload.push(if self.is_calibrated { 1 } else { 0 });
         payload
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hid_manager::HidManager;
+    use crate::replay::{ReplayExchange, ReplayTransport};
+    use std::sync::Arc;
+
+    #[test]
+    fn command_and_status_bytes_round_trip() {
+        for command in ConfigCommand::ALL {
+            assert_eq!(ConfigCommand::try_from(command as u8).unwrap(), command);
+        }
+        assert_eq!(ConfigCommand::try_from(0x00), Err(ConfigError::UnknownCommand { byte: 0x00 }));
+        assert_eq!(ConfigCommand::try_from(0x7E), Err(ConfigError::UnknownCommand { byte: 0x7E }));
+
+        assert_eq!(StatusCode::try_from(0x04).unwrap(), StatusCode::Busy);
+        assert_eq!(StatusCode::try_from(0x06), Err(ConfigError::UnknownStatus { byte: 0x06 }));
+    }
+
+    #[test]
+    fn from_bytes_rejects_corrupt_framing() {
+        let good = ConfigPacket::new(ConfigCommand::GetKeymap, 3, &[1, 2, 3]).unwrap().to_bytes();
+        assert!(ConfigPacket::from_bytes(&good).is_ok());
+
+        let mut bad_length = good;
+        bad_length[5] = CONFIG_MAX_PAYLOAD_SIZE as u8 + 1;
+        assert!(matches!(ConfigPacket::from_bytes(&bad_length), Err(ConfigError::PayloadLength { .. })));
+
+        let mut bad_status = good;
+        bad_status[3] = 0xEE;
+        assert_eq!(ConfigPacket::from_bytes(&bad_status).unwrap_err(), ConfigError::UnknownStatus { byte: 0xEE });
+    }
+
+    /// One request per command, its payload built by the same encoder the host uses, sent with
+    /// the command byte as sequence. Expected bytes: header "OG" (LE), command, status, sequence,
+    /// payload_length, reserved, payload; the rest of the packet is zero padding.
+    fn golden_requests() -> Vec<(ConfigCommand, Vec<u8>, &'static str)> {
+        let keymap_entry = KeymapEntry { layer: 1, row: 2, col: 3, keycode: 0x0104 };
+        let encoder_entry = EncoderEntry { layer: 1, encoder_id: 0, ccw_keycode: 0x0080, cw_keycode: 0x0081, reserved: 0 };
+        let slave_keymap_entry = SlaveKeymapEntry { slave_addr: 0x20, layer: 1, row: 2, col: 3, keycode: 0x0104 };
+        let slave_encoder_entry = SlaveEncoderEntry {
+            slave_addr: 0x20,
+            layer: 1,
+            encoder_id: 0,
+            ccw_keycode: 0x0080,
+            cw_keycode: 0x0081,
+            reserved: 0,
+        };
+        let slider_config = SliderConfig {
+            layer: 1,
+            slider_id: 0,
+            midi_cc: 7,
+            midi_channel: 0,
+            min_midi_value: 0,
+            max_midi_value: 127,
+        };
+        let switch_config = MagneticSwitchConfig {
+            layer: 1,
+            switch_id: 1,
+            unpressed_value: 2048,
+            pressed_value: 3200,
+            sensitivity: 40,
+            keycode: 0x0104,
+            is_calibrated: true,
+        };
+        let layer_state = LayerState { active_mask: 0x05, default_layer: 0 };
+        let keymap_range = KeymapRange { layer: 1, start: 4, keycodes: vec![0x0004, 0x0005, 0x0104] };
+        // Last chunk of a 56-byte blob; crc32("123456789") is the standard check value 0xCBF43926
+        let write_chunk = LargeWriteChunk {
+            target: ConfigCommand::SetKeymapBulk as u8,
+            offset: LARGE_WRITE_CHUNK as u16,
+            total: 56,
+            data: b"123456789".to_vec(),
+        };
+
+        vec![
+            (ConfigCommand::GetInfo, vec![], "474f 01 00 01 00 0000"),
+            (ConfigCommand::GetKeymap, vec![1, 2, 3], "474f 02 00 02 03 0000 010203"),
+            (ConfigCommand::SetKeymap, keymap_entry.to_payload(), "474f 03 00 03 05 0000 010203 0401"),
+            (ConfigCommand::GetEncoderMap, vec![1, 0], "474f 04 00 04 02 0000 0100"),
+            (ConfigCommand::SetEncoderMap, encoder_entry.to_payload(), "474f 05 00 05 07 0000 0100 8000 8100 00"),
+            (ConfigCommand::SaveConfig, vec![], "474f 06 00 06 00 0000"),
+            (ConfigCommand::LoadConfig, vec![], "474f 07 00 07 00 0000"),
+            (ConfigCommand::ResetConfig, vec![], "474f 08 00 08 00 0000"),
+            (ConfigCommand::GetI2CDevices, vec![], "474f 09 00 09 00 0000"),
+            (ConfigCommand::SetI2CConfig, vec![], "474f 0a 00 0a 00 0000"),
+            (ConfigCommand::GetDeviceStatus, vec![], "474f 0b 00 0b 00 0000"),
+            (ConfigCommand::Reboot, vec![], "474f 0c 00 0c 00 0000"),
+            (ConfigCommand::MidiSendRaw, vec![0x90, 60, 100], "474f 0d 00 0d 03 0000 903c64"),
+            (ConfigCommand::MidiNoteOn, vec![0, 60, 100], "474f 0e 00 0e 03 0000 003c64"),
+            (ConfigCommand::MidiNoteOff, vec![0, 60, 0], "474f 0f 00 0f 03 0000 003c00"),
+            (ConfigCommand::MidiControlChange, vec![0, 7, 127], "474f 10 00 10 03 0000 00077f"),
+            (ConfigCommand::GetSlaveKeymap, vec![0x20, 1, 2, 3], "474f 11 00 11 04 0000 20010203"),
+            (ConfigCommand::SetSlaveKeymap, slave_keymap_entry.to_payload(), "474f 12 00 12 06 0000 20010203 0401"),
+            (ConfigCommand::GetSlaveInfo, vec![0x20], "474f 13 00 13 01 0000 20"),
+            (ConfigCommand::GetSlaveEncoder, vec![0x20, 1, 0], "474f 14 00 14 03 0000 200100"),
+            (ConfigCommand::SetSlaveEncoder, slave_encoder_entry.to_payload(), "474f 15 00 15 08 0000 200100 8000 8100 00"),
+            (ConfigCommand::GetLayoutInfo, vec![], "474f 16 00 16 00 0000"),
+            (ConfigCommand::SetLayerState, layer_state.to_payload().to_vec(), "474f 17 00 17 02 0000 0500"),
+            (ConfigCommand::GetLayerState, vec![], "474f 18 00 18 00 0000"),
+            (ConfigCommand::GetLayoutCellType, vec![2, 3], "474f 19 00 19 02 0000 0203"),
+            (ConfigCommand::GetLayoutCellComponentId, vec![2, 3], "474f 1a 00 1a 02 0000 0203"),
+            (ConfigCommand::GetSliderValue, vec![0], "474f 1b 00 1b 01 0000 00"),
+            (ConfigCommand::GetSliderConfig, vec![1, 0], "474f 1c 00 1c 02 0000 0100"),
+            (ConfigCommand::SetSliderConfig, slider_config.to_payload(), "474f 1d 00 1d 08 0000 0100 0700 007f 0000"),
+            (ConfigCommand::GetMagneticSwitchValue, vec![1], "474f 1e 00 1e 01 0000 01"),
+            (ConfigCommand::GetMagneticSwitchConfig, vec![1, 1], "474f 1f 00 1f 02 0000 0101"),
+            (
+                ConfigCommand::SetMagneticSwitchConfig,
+                switch_config.to_payload(),
+                "474f 20 00 20 0a 0000 0101 0008 800c 28 0401 01",
+            ),
+            (ConfigCommand::CalibrateMagneticSwitch, vec![1, 2], "474f 21 00 21 02 0000 0102"),
+            (ConfigCommand::SetMagneticSwitchSensitivity, vec![1, 40], "474f 22 00 22 02 0000 0128"),
+            (ConfigCommand::GetCapabilities, vec![], "474f 23 00 23 00 0000"),
+            (
+                ConfigCommand::GetKeymapBulk,
+                KeymapRange::request_payload(1, 0x0102, 16).to_vec(),
+                "474f 24 00 24 04 0000 01 0201 10",
+            ),
+            (
+                ConfigCommand::SetKeymapBulk,
+                keymap_range.to_payload(),
+                "474f 25 00 25 0a 0000 01 0400 03 0400 0500 0401",
+            ),
+            (ConfigCommand::GetEncoderMapBulk, vec![1, 0, 2], "474f 26 00 26 03 0000 010002"),
+            (
+                ConfigCommand::LargeWrite,
+                write_chunk.to_payload(),
+                "474f 27 00 27 12 0000 25 2f00 3800 2639f4cb 313233343536373839",
+            ),
+            (
+                ConfigCommand::LargeRead,
+                LargeReadChunk::request_payload(ConfigCommand::GetKeymapBulk as u8, 0x0034, &[1]),
+                "474f 28 00 28 04 0000 24 3400 01",
+            ),
+            (ConfigCommand::KeyEvent, vec![2, 5, 1], "474f 29 00 29 03 0000 020501"),
+            (ConfigCommand::SubscribeSliders, 20u16.to_le_bytes().to_vec(), "474f 2a 00 2a 02 0000 1400"),
+            (ConfigCommand::UnsubscribeSliders, vec![], "474f 2b 00 2b 00 0000"),
+            (ConfigCommand::SliderValues, vec![0x40, 0x7f], "474f 2c 00 2c 02 0000 407f"),
+        ]
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn golden_request_vectors() {
+        let requests = golden_requests();
+        assert_eq!(requests.len(), ConfigCommand::ALL.len());
+        for (command, payload, expected) in requests {
+            let expected = hex(expected);
+            let bytes = ConfigPacket::new(command, command as u8, &payload).unwrap().to_bytes();
+            assert_eq!(&bytes[..expected.len()], &expected[..], "{:?}", command);
+            assert!(bytes[expected.len()..].iter().all(|&b| b == 0), "{:?}", command);
+        }
+    }
+
+    #[test]
+    fn golden_response_vector() {
+        // Busy reply to SetKeymap seq 0x42 as the firmware sends it
+        let mut bytes = hex("474f 03 04 42 00 0000");
+        bytes.resize(CONFIG_PACKET_SIZE, 0);
+
+        let packet = ConfigPacket::from_bytes(&bytes).unwrap();
+        assert_eq!(packet.command().unwrap(), ConfigCommand::SetKeymap);
+        assert_eq!(packet.status().unwrap(), StatusCode::Busy);
+        assert_eq!(packet.sequence, 0x42);
+        assert_eq!(packet.to_bytes().to_vec(), bytes);
+    }
+
+    #[test]
+    fn golden_event_vector() {
+        // Key (2, 5) pressed, pushed by the firmware without a request
+        let mut bytes = hex("474f 29 00 00 03 0100 020501");
+        bytes.resize(CONFIG_PACKET_SIZE, 0);
+
+        let packet = ConfigPacket::from_bytes(&bytes).unwrap();
+        assert!(packet.is_unsolicited());
+        assert_eq!(packet, ConfigPacket::event(ConfigCommand::KeyEvent, &[2, 5, 1]).unwrap());
+        assert!(!ConfigPacket::new(ConfigCommand::KeyEvent, 0, &[2, 5, 1]).unwrap().is_unsolicited());
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        fn prop(command: u8, status: u8, sequence: u8, reserved: (u8, u8), payload: Vec<u8>) -> bool {
+            let command = ConfigCommand::ALL[command as usize % ConfigCommand::ALL.len()];
+            let status = status % (StatusCode::NotSupported as u8 + 1);
+            let payload = &payload[..payload.len().min(CONFIG_MAX_PAYLOAD_SIZE)];
+
+            let mut packet = ConfigPacket::new(command, sequence, payload).unwrap();
+            packet.status = status;
+            packet.reserved = [reserved.0, reserved.1];
+
+            let decoded = ConfigPacket::from_bytes(&packet.to_bytes()).unwrap();
+            decoded == packet && decoded.payload_bytes() == payload
+        }
+        quickcheck::quickcheck(prop as fn(u8, u8, u8, (u8, u8), Vec<u8>) -> bool);
+    }
+
+    #[test]
+    fn valid_reports_round_trip() {
+        fn prop(body: Vec<u8>, status: u8, payload_length: u8) -> bool {
+            let mut bytes = [0u8; CONFIG_PACKET_SIZE];
+            for (dst, src) in bytes.iter_mut().zip(&body) {
+                *dst = *src;
+            }
+            bytes[..2].copy_from_slice(&CONFIG_PACKET_HEADER.to_le_bytes());
+            bytes[3] = status % (StatusCode::NotSupported as u8 + 1);
+            bytes[5] = payload_length % (CONFIG_MAX_PAYLOAD_SIZE as u8 + 1);
+
+            ConfigPacket::from_bytes(&bytes).unwrap().to_bytes() == bytes
+        }
+        quickcheck::quickcheck(prop as fn(Vec<u8>, u8, u8) -> bool);
+    }
+
+    #[test]
+    fn oversized_payloads_are_rejected_not_truncated() {
+        let payload = [0x11; CONFIG_MAX_PAYLOAD_SIZE + 1];
+        assert_eq!(
+            ConfigPacket::new(ConfigCommand::SetKeymap, 1, &payload).unwrap_err(),
+            ConfigError::PayloadTooLarge { length: CONFIG_MAX_PAYLOAD_SIZE + 1, max: CONFIG_MAX_PAYLOAD_SIZE }
+        );
+        let full = ConfigPacket::new(ConfigCommand::SetKeymap, 1, &payload[1..]).unwrap();
+        assert_eq!(full.payload_bytes(), &payload[1..]);
+    }
+
+    #[test]
+    fn transfer_chunks_carry_a_crc() {
+        // zlib's check value
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let chunk = LargeWriteChunk { target: 0x02, offset: 47, total: 300, data: vec![7; LARGE_WRITE_CHUNK] };
+        let mut payload = chunk.to_payload();
+        assert_eq!(payload.len(), CONFIG_MAX_PAYLOAD_SIZE);
+        assert_eq!(LargeWriteChunk::from_payload(&payload).unwrap(), chunk);
+
+        payload[20] ^= 0x01;
+        assert_eq!(LargeWriteChunk::from_payload(&payload).unwrap_err(), ConfigError::ChecksumMismatch { offset: 47 });
+
+        let chunk = LargeReadChunk { offset: 96, total: 100, data: vec![1, 2, 3, 4] };
+        assert_eq!(LargeReadChunk::from_payload(&chunk.to_payload()).unwrap(), chunk);
+    }
+
+    #[tokio::test]
+    async fn mismatched_echo_is_a_distinct_error() {
+        let request = ConfigPacket::new(ConfigCommand::GetLayerState, 1, &[]).unwrap().to_bytes();
+        let mut response = ConfigPacket::new(ConfigCommand::GetKeymap, 1, &[0x01, 0x00]).unwrap();
+        response.status = StatusCode::Ok as u8;
+        let transport = ReplayTransport::new(vec![ReplayExchange { request, responses: vec![response.to_bytes()] }]);
+
+        let manager = HidManager::new().unwrap();
+        manager.connect_transport(Arc::new(transport));
+        assert_eq!(
+            manager.get_layer_state().await.unwrap_err(),
+            ConfigError::CommandMismatch { expected: ConfigCommand::GetLayerState, actual: ConfigCommand::GetKeymap as u8 }
+        );
+    }
+}
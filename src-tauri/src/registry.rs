@@ -0,0 +1,415 @@
+use crate::device_store::DeviceStore;
+use crate::error::ConfigError;
+use crate::hid_manager::{HidManager, HotplugAction};
+use crate::hotplug::{self, HotplugEvent};
+use crate::protocol::BoardLayoutInfo;
+use hidapi::HidApi;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+use tracing::{info, warn};
+
+/// One connection. Commands hold it for reading; connecting, disconnecting and
+/// hotplug reconnects hold it for writing, so they never swap the transport under a command.
+pub type DeviceHandle = Arc<RwLock<HidManager>>;
+
+/// A device as listed by `list_connected_devices`
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectedDeviceInfo {
+    pub device_id: String,
+    pub path: Option<String>,
+    pub primary: bool,
+}
+
+/// Result of opening a device through the registry
+pub struct Connected {
+    pub device_id: String,
+    pub handle: DeviceHandle,
+    /// True if this opened an additional device rather than the primary one
+    pub additional: bool,
+}
+
+/// Every board the configurator is connected to.
+///
+/// The primary device is what commands talk to when they aren't given a
+/// device id; it always exists, connected or not. Further boards are kept by
+/// device id (serial number, or path without one), each with its own
+/// `HidManager` and therefore its own sequence counter and command queue.
+/// What we remember about each device between sessions lives in `store`.
+pub struct DeviceRegistry {
+    api: Arc<Mutex<HidApi>>,
+    primary: DeviceHandle,
+    additional: Mutex<HashMap<String, DeviceHandle>>,
+    store: DeviceStore,
+}
+
+impl DeviceRegistry {
+    pub fn new(store: DeviceStore) -> Result<Self, ConfigError> {
+        let api = HidApi::new().map_err(|e| ConfigError::io(format!("Failed to initialize HID API: {}", e)))?;
+        let api = Arc::new(Mutex::new(api));
+        Ok(DeviceRegistry {
+            primary: Arc::new(RwLock::new(HidManager::with_api(api.clone()))),
+            api,
+            additional: Mutex::new(HashMap::new()),
+            store,
+        })
+    }
+
+    pub fn primary(&self) -> DeviceHandle {
+        self.primary.clone()
+    }
+
+    /// Per-device settings
+    pub fn store(&self) -> &DeviceStore {
+        &self.store
+    }
+
+    /// Id of `device_id`'s connection, or of the primary device if none is given
+    pub async fn resolve_id(&self, device_id: Option<&str>) -> Result<String, ConfigError> {
+        match device_id {
+            Some(device_id) => Ok(device_id.to_string()),
+            None => self.primary.read().await.device_id().ok_or(ConfigError::NotConnected),
+        }
+    }
+
+    /// Note a device that just completed its handshake; the primary one becomes
+    /// the first choice for the next `auto_connect`
+    pub fn remember_connection(&self, device_id: &str, primary: bool) {
+        if let Err(e) = self.store.record_connection(device_id, primary) {
+            warn!("Failed to save device settings for {}: {}", device_id, e);
+        }
+    }
+
+    /// Keep the layout a device reported, so it can be shown while it's unplugged
+    pub async fn remember_layout(&self, device_id: Option<&str>, layout: &BoardLayoutInfo) {
+        let Ok(device_id) = self.read(device_id).await.and_then(|m| m.device_id().ok_or(ConfigError::NotConnected)) else {
+            return;
+        };
+        if let Err(e) = self.store.update_settings(&device_id, |s| s.last_layout = Some(layout.clone())) {
+            warn!("Failed to save device settings for {}: {}", device_id, e);
+        }
+    }
+
+    /// The connection for `device_id`, or the primary device if none is given
+    pub async fn get(&self, device_id: Option<&str>) -> Result<DeviceHandle, ConfigError> {
+        let Some(device_id) = device_id else { return Ok(self.primary.clone()) };
+
+        if self.primary.read().await.device_id().as_deref() == Some(device_id) {
+            return Ok(self.primary.clone());
+        }
+        self.additional
+            .lock()
+            .unwrap()
+            .get(device_id)
+            .cloned()
+            .ok_or_else(|| ConfigError::UnknownDevice { device_id: device_id.to_string() })
+    }
+
+    pub async fn read(&self, device_id: Option<&str>) -> Result<OwnedRwLockReadGuard<HidManager>, ConfigError> {
+        Ok(self.get(device_id).await?.read_owned().await)
+    }
+
+    pub async fn write(&self, device_id: Option<&str>) -> Result<OwnedRwLockWriteGuard<HidManager>, ConfigError> {
+        Ok(self.get(device_id).await?.write_owned().await)
+    }
+
+    /// Open the device at `path`: as the primary device if that is free (or
+    /// already on `path`), otherwise alongside it. A device that is already
+    /// open as an additional one is not opened a second time.
+    pub async fn connect(&self, path: &str) -> Result<Connected, ConfigError> {
+        if let Some(device_id) = self.additional_on(path).await {
+            return Err(ConfigError::invalid_input(format!("{} is already open as {}", path, device_id)));
+        }
+        {
+            let primary = self.primary.write().await;
+            if !primary.is_connected() || primary.connected_path().as_deref() == Some(path) {
+                primary.connect(path)?;
+                let device_id = primary.device_id().ok_or(ConfigError::NotConnected)?;
+                if self.additional.lock().unwrap().contains_key(&device_id) {
+                    primary.disconnect();
+                    return Err(ConfigError::invalid_input(format!("{} is already connected", device_id)));
+                }
+                return Ok(Connected { device_id, handle: self.primary.clone(), additional: false });
+            }
+        }
+
+        let manager = HidManager::with_api(self.api.clone());
+        manager.connect(path)?;
+        self.add(manager).await
+    }
+
+    /// Replay the trace at `trace_path`: as the primary device if that is free
+    /// (or already replaying it), otherwise alongside it
+    pub async fn connect_replay(&self, trace_path: &Path) -> Result<Connected, ConfigError> {
+        {
+            let primary = self.primary.write().await;
+            if !primary.is_connected() || primary.connected_path() == Some(trace_path.display().to_string()) {
+                primary.connect_replay(trace_path)?;
+                return Ok(Connected {
+                    device_id: primary.device_id().ok_or(ConfigError::NotConnected)?,
+                    handle: self.primary.clone(),
+                    additional: false,
+                });
+            }
+        }
+
+        let manager = HidManager::with_api(self.api.clone());
+        manager.connect_replay(trace_path)?;
+        self.add(manager).await
+    }
+
+    /// Keep an already connected manager as an additional device. A previous
+    /// connection under the same id (another interface of the same board) is replaced.
+    pub async fn add(&self, manager: HidManager) -> Result<Connected, ConfigError> {
+        let device_id = manager.device_id().ok_or(ConfigError::NotConnected)?;
+        if self.primary.read().await.device_id().as_deref() == Some(device_id.as_str()) {
+            manager.disconnect();
+            return Err(ConfigError::invalid_input(format!("{} is already the primary device", device_id)));
+        }
+
+        let handle = Arc::new(RwLock::new(manager));
+        let replaced = self.additional.lock().unwrap().insert(device_id.clone(), handle.clone());
+        if let Some(replaced) = replaced {
+            replaced.write().await.disconnect();
+        }
+        info!("Connected additional device {}", device_id);
+        Ok(Connected { device_id, handle, additional: true })
+    }
+
+    /// Id of the additional device opened on `path`, if any
+    async fn additional_on(&self, path: &str) -> Option<String> {
+        let additional: Vec<(String, DeviceHandle)> =
+            self.additional.lock().unwrap().iter().map(|(id, h)| (id.clone(), h.clone())).collect();
+        for (device_id, handle) in additional {
+            if handle.read().await.connected_path().as_deref() == Some(path) {
+                return Some(device_id);
+            }
+        }
+        None
+    }
+
+    /// Disconnect one device; additional devices are forgotten, the primary slot stays
+    pub async fn disconnect(&self, device_id: Option<&str>) -> Result<(), ConfigError> {
+        let handle = self.get(device_id).await?;
+        if let Some(device_id) = device_id {
+            self.additional.lock().unwrap().remove(device_id);
+        }
+        handle.write().await.disconnect();
+        Ok(())
+    }
+
+    /// Connected devices, primary first
+    pub async fn devices(&self) -> Vec<ConnectedDeviceInfo> {
+        let mut devices = Vec::new();
+        {
+            let primary = self.primary.read().await;
+            if let (true, Some(device_id)) = (primary.is_connected(), primary.device_id()) {
+                devices.push(ConnectedDeviceInfo { device_id, path: primary.connected_path(), primary: true });
+            }
+        }
+
+        let mut additional: Vec<(String, DeviceHandle)> =
+            self.additional.lock().unwrap().iter().map(|(id, h)| (id.clone(), h.clone())).collect();
+        additional.sort_by(|a, b| a.0.cmp(&b.0));
+        for (device_id, handle) in additional {
+            let manager = handle.read().await;
+            if manager.is_connected() {
+                devices.push(ConnectedDeviceInfo { device_id, path: manager.connected_path(), primary: false });
+            }
+        }
+        devices
+    }
+
+    /// Start watching for OpenGrader devices being plugged in or removed
+    pub fn watch_devices(&self) -> mpsc::UnboundedReceiver<HotplugEvent> {
+        hotplug::spawn_watcher(self.api.clone())
+    }
+
+    /// Let every connection react to a device coming or going. Returns the id
+    /// of each device whose connection changed; unplugged additional devices
+    /// are forgotten. Only the primary device auto-reconnects.
+    pub async fn handle_hotplug(&self, event: &HotplugEvent) -> Vec<(String, HotplugAction)> {
+        let mut changes = Vec::new();
+
+        {
+            // Written: a reconnect swaps the transport under any command still running
+            let primary = self.primary.write().await;
+            let device_id = primary.device_id();
+            match primary.handle_hotplug(event).await {
+                Ok(HotplugAction::None) => {}
+                Ok(action) => {
+                    // After a reconnect the id is known again; after a removal it was known before
+                    if let Some(device_id) = primary.device_id().or(device_id) {
+                        if action == HotplugAction::Reconnected {
+                            self.remember_connection(&device_id, true);
+                        }
+                        changes.push((device_id, action));
+                    }
+                }
+                Err(e) => warn!("Auto-reconnect to {} failed: {}", event.device().path, e),
+            }
+        }
+
+        if let HotplugEvent::Removed { device } = event {
+            let gone: Vec<(String, DeviceHandle)> = self
+                .additional
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(id, h)| (id.clone(), h.clone()))
+                .collect();
+            for (device_id, handle) in gone {
+                let manager = handle.write().await;
+                if manager.connected_path().as_deref() == Some(device.path.as_str()) {
+                    manager.disconnect();
+                    self.additional.lock().unwrap().remove(&device_id);
+                    changes.push((device_id, HotplugAction::Disconnected));
+                }
+            }
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::KeymapEntry;
+    use crate::simulator::{connected_simulator, SimulatedDevice, SimulatorTransport, SIMULATOR_DEVICE_PATH, SIMULATOR_SERIAL};
+
+    async fn add_simulated(registry: &DeviceRegistry, path: &str, serial: &str) -> Connected {
+        let manager = HidManager::with_api(registry.api.clone());
+        manager.connect_transport(Arc::new(SimulatorTransport::new(SimulatedDevice::new())));
+        manager.set_identity(path, serial);
+        registry.add(manager).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn commands_reach_the_device_they_name() {
+        let registry = DeviceRegistry::new(DeviceStore::in_memory()).unwrap();
+        let primary = registry.connect(SIMULATOR_DEVICE_PATH).await.unwrap();
+        assert!(!primary.additional);
+        assert_eq!(primary.device_id, SIMULATOR_SERIAL);
+        let second = add_simulated(&registry, "/dev/hidraw7", "BOARD-B").await;
+        assert!(second.additional);
+
+        let entry = KeymapEntry { layer: 0, row: 0, col: 0, keycode: 0x2C };
+        registry.read(Some("BOARD-B")).await.unwrap().set_keymap_entry(&entry).await.unwrap();
+
+        let on_b = registry.read(Some("BOARD-B")).await.unwrap().get_keymap_entry(0, 0, 0).await.unwrap();
+        let on_primary = registry.read(None).await.unwrap().get_keymap_entry(0, 0, 0).await.unwrap();
+        let by_id = registry.read(Some(SIMULATOR_SERIAL)).await.unwrap().get_keymap_entry(0, 0, 0).await.unwrap();
+        assert_eq!(on_b.keycode, 0x2C);
+        assert_eq!(on_primary.keycode, 0x04);
+        assert_eq!(by_id.keycode, 0x04);
+
+        let ids: Vec<String> = registry.devices().await.into_iter().map(|d| d.device_id).collect();
+        assert_eq!(ids, vec![SIMULATOR_SERIAL.to_string(), "BOARD-B".to_string()]);
+
+        assert!(matches!(registry.read(Some("NOPE")).await, Err(ConfigError::UnknownDevice { .. })));
+        registry.disconnect(Some("BOARD-B")).await.unwrap();
+        assert!(matches!(registry.read(Some("BOARD-B")).await, Err(ConfigError::UnknownDevice { .. })));
+        assert!(registry.read(None).await.unwrap().is_connected());
+    }
+
+    #[tokio::test]
+    async fn a_device_open_as_additional_is_not_opened_again_as_primary() {
+        let registry = DeviceRegistry::new(DeviceStore::in_memory()).unwrap();
+        registry.connect(SIMULATOR_DEVICE_PATH).await.unwrap();
+        add_simulated(&registry, "/dev/hidraw7", "BOARD-B").await;
+        registry.disconnect(None).await.unwrap();
+
+        // Same path
+        let result = registry.connect("/dev/hidraw7").await;
+        assert!(matches!(result, Err(ConfigError::InvalidInput { .. })), "{:?}", result.err());
+
+        // Same board through another path
+        add_simulated(&registry, "/dev/hidraw9", SIMULATOR_SERIAL).await;
+        let result = registry.connect(SIMULATOR_DEVICE_PATH).await;
+        assert!(matches!(result, Err(ConfigError::InvalidInput { .. })), "{:?}", result.err());
+        assert!(!registry.read(None).await.unwrap().is_connected());
+        assert_eq!(registry.devices().await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn hotplug_reconnect_waits_for_running_commands() {
+        let registry = DeviceRegistry::new(DeviceStore::in_memory()).unwrap();
+        registry.connect(SIMULATOR_DEVICE_PATH).await.unwrap();
+        registry.read(None).await.unwrap().handshake().await.unwrap();
+        let device = crate::hid_manager::DeviceDescriptor {
+            vendor_id: 0,
+            product_id: 0,
+            serial_number: Some(SIMULATOR_SERIAL.to_string()),
+            product_string: None,
+            path: SIMULATOR_DEVICE_PATH.to_string(),
+            interface_number: 2,
+            usage_page: 0xFF00,
+        };
+        registry.handle_hotplug(&HotplugEvent::Removed { device: device.clone() }).await;
+
+        // A command holds its read guard: the reconnect must not swap the transport under it
+        let command = registry.read(None).await.unwrap();
+        let replugged = HotplugEvent::Added { device };
+        let blocked = tokio::time::timeout(std::time::Duration::from_millis(50), registry.handle_hotplug(&replugged)).await;
+        assert!(blocked.is_err());
+        assert!(!command.is_connected());
+        drop(command);
+
+        let changes = registry.handle_hotplug(&replugged).await;
+        assert_eq!(changes, vec![(SIMULATOR_SERIAL.to_string(), HotplugAction::Reconnected)]);
+    }
+
+    #[tokio::test]
+    async fn replays_are_registered_under_their_own_id() {
+        let path = std::env::temp_dir().join(format!("og-registry-replay-{}.jsonl", std::process::id()));
+        let (recording, _sim) = connected_simulator(SimulatedDevice::new());
+        recording.start_recording(&path).unwrap();
+        recording.get_keymap_entry(0, 0, 0).await.unwrap();
+        assert!(recording.stop_recording());
+        let replay_id = format!("replay:{}", path.display());
+
+        // Nothing connected yet: the replay becomes the primary device
+        let registry = DeviceRegistry::new(DeviceStore::in_memory()).unwrap();
+        let replay = registry.connect_replay(&path).await.unwrap();
+        assert!(!replay.additional);
+        assert_eq!(registry.resolve_id(None).await.unwrap(), replay_id);
+
+        let registry = DeviceRegistry::new(DeviceStore::in_memory()).unwrap();
+        registry.connect(SIMULATOR_DEVICE_PATH).await.unwrap();
+        let replay = registry.connect_replay(&path).await.unwrap();
+        assert!(replay.additional);
+        assert_eq!(replay.device_id, replay_id);
+        let entry = registry.read(Some(&replay_id)).await.unwrap().get_keymap_entry(0, 0, 0).await.unwrap();
+        assert_eq!(entry.keycode, 0x04);
+        assert_eq!(registry.devices().await.len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unplugging_an_additional_device_forgets_it() {
+        let registry = DeviceRegistry::new(DeviceStore::in_memory()).unwrap();
+        registry.connect(SIMULATOR_DEVICE_PATH).await.unwrap();
+        let second = add_simulated(&registry, "/dev/hidraw7", "BOARD-B").await;
+
+        let mut device = crate::hid_manager::DeviceDescriptor {
+            vendor_id: 0,
+            product_id: 0,
+            serial_number: Some("BOARD-B".to_string()),
+            product_string: None,
+            path: "/dev/hidraw7".to_string(),
+            interface_number: 2,
+            usage_page: 0xFF00,
+        };
+        let changes = registry.handle_hotplug(&HotplugEvent::Removed { device: device.clone() }).await;
+        assert_eq!(changes, vec![("BOARD-B".to_string(), HotplugAction::Disconnected)]);
+        assert!(!second.handle.read().await.is_connected());
+        assert_eq!(registry.devices().await.len(), 1);
+
+        // Unrelated removals leave the primary alone
+        device.path = "/dev/hidraw8".to_string();
+        assert!(registry.handle_hotplug(&HotplugEvent::Removed { device }).await.is_empty());
+        assert!(registry.read(None).await.unwrap().is_connected());
+    }
+}
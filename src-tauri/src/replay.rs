@@ -0,0 +1,64 @@
+use crate::error::ConfigError;
+use crate::trace::{exchanges_from_trace, load_trace};
+use crate::transport::{ConfigTransport, Report};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// One request written by the host together with everything the device sent back for it
+#[derive(Debug, Clone)]
+pub struct ReplayExchange {
+    pub request: Report,
+    pub responses: Vec<Report>,
+}
+
+/// Transport that plays back a previously captured session.
+///
+/// Every write must match the next recorded request byte for byte; the
+/// responses recorded after it are then served to subsequent reads.
+pub struct ReplayTransport {
+    exchanges: Mutex<VecDeque<ReplayExchange>>,
+    pending: Mutex<VecDeque<Report>>,
+}
+
+impl ReplayTransport {
+    pub fn new(exchanges: Vec<ReplayExchange>) -> Self {
+        ReplayTransport {
+            exchanges: Mutex::new(exchanges.into()),
+            pending: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Load a session captured with `HidManager::start_recording`
+    pub fn from_trace_file(path: &Path) -> Result<Self, ConfigError> {
+        let records = load_trace(path)?;
+        Ok(Self::new(exchanges_from_trace(&records)?))
+    }
+
+    /// Number of recorded requests that have not been replayed yet
+    pub fn remaining(&self) -> usize {
+        self.exchanges.lock().unwrap().len()
+    }
+}
+
+impl ConfigTransport for ReplayTransport {
+    fn write_report(&self, report: &Report) -> Result<(), ConfigError> {
+        let exchange = self.exchanges.lock().unwrap().pop_front()
+            .ok_or_else(|| ConfigError::protocol("Replay trace exhausted"))?;
+
+        if exchange.request != *report {
+            return Err(ConfigError::protocol(format!(
+                "Replay mismatch: expected request {:02X?}, got {:02X?}",
+                &exchange.request[..8],
+                &report[..8]
+            )));
+        }
+
+        self.pending.lock().unwrap().extend(exchange.responses);
+        Ok(())
+    }
+
+    fn read_report(&self, _timeout_ms: i32) -> Result<Option<Report>, ConfigError> {
+        Ok(self.pending.lock().unwrap().pop_front())
+    }
+}
@@ -0,0 +1,123 @@
+use crate::error::ConfigError;
+use crate::protocol::ConfigCommand;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Longest pause between two attempts, however far the backoff has doubled
+pub const MAX_BACKOFF_MS: u32 = 2000;
+
+/// How one command is sent: how long to wait for each answer, how often to
+/// resend on silence and how often to retry when the device reports `Busy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetrySettings {
+    /// Wait for one response before resending
+    pub timeout_ms: u32,
+    /// Sends in total, including the first one
+    pub attempts: u8,
+    /// Pause before the first retry; doubles with every further one
+    pub backoff_ms: u32,
+    /// Extra tries after a `Busy` status, on top of `attempts`
+    pub busy_retries: u8,
+}
+
+impl RetrySettings {
+    /// Settings for commands the master answers itself
+    pub const DIRECT: RetrySettings = RetrySettings { timeout_ms: 800, attempts: 2, backoff_ms: 20, busy_retries: 3 };
+
+    /// Settings for commands the master forwards over I2C to a slave
+    pub const SLAVE: RetrySettings = RetrySettings { timeout_ms: 2000, attempts: 3, backoff_ms: 50, busy_retries: 5 };
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms as u64)
+    }
+
+    /// Pause before retry number `retry` (1-based)
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u32 << retry.saturating_sub(1).min(16);
+        Duration::from_millis(self.backoff_ms.saturating_mul(factor).min(MAX_BACKOFF_MS) as u64)
+    }
+
+    fn validate(&self, what: &str) -> Result<(), ConfigError> {
+        let fail = |reason: &str| Err(ConfigError::invalid_input(format!("{} retry settings: {}", what, reason)));
+        if !(50..=30_000).contains(&self.timeout_ms) {
+            return fail("timeout_ms must be between 50 and 30000");
+        }
+        if !(1..=10).contains(&self.attempts) {
+            return fail("attempts must be between 1 and 10");
+        }
+        if self.backoff_ms > MAX_BACKOFF_MS {
+            return fail(&format!("backoff_ms must be at most {}", MAX_BACKOFF_MS));
+        }
+        if self.busy_retries > 20 {
+            return fail("busy_retries must be at most 20");
+        }
+        Ok(())
+    }
+}
+
+/// Timeouts and retries for every command: a default, a default for
+/// slave-forwarded commands, and overrides for single commands
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    pub default: RetrySettings,
+    pub slave: RetrySettings,
+    #[serde(default)]
+    pub overrides: HashMap<ConfigCommand, RetrySettings>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy { default: RetrySettings::DIRECT, slave: RetrySettings::SLAVE, overrides: HashMap::new() }
+    }
+}
+
+impl RetryPolicy {
+    pub fn for_command(&self, command: ConfigCommand) -> RetrySettings {
+        match self.overrides.get(&command) {
+            Some(settings) => *settings,
+            None if command.is_slave_forwarded() => self.slave,
+            None => self.default,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.default.validate("Default")?;
+        self.slave.validate("Slave")?;
+        for (command, settings) in &self.overrides {
+            settings.validate(&format!("{:?}", command))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slave_commands_get_their_own_defaults_unless_overridden() {
+        let mut policy = RetryPolicy::default();
+        assert_eq!(policy.for_command(ConfigCommand::GetKeymap), RetrySettings::DIRECT);
+        assert_eq!(policy.for_command(ConfigCommand::GetSlaveKeymap), RetrySettings::SLAVE);
+
+        let slow = RetrySettings { timeout_ms: 5000, ..RetrySettings::SLAVE };
+        policy.overrides.insert(ConfigCommand::GetSlaveKeymap, slow);
+        assert_eq!(policy.for_command(ConfigCommand::GetSlaveKeymap), slow);
+        assert_eq!(policy.for_command(ConfigCommand::SetSlaveKeymap), RetrySettings::SLAVE);
+
+        // Round-trips through the JSON the frontend sends
+        let json = serde_json::to_string(&policy).unwrap();
+        assert_eq!(serde_json::from_str::<RetryPolicy>(&json).unwrap(), policy);
+
+        policy.overrides.insert(ConfigCommand::Reboot, RetrySettings { attempts: 0, ..RetrySettings::DIRECT });
+        assert!(policy.validate().is_err());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let settings = RetrySettings { backoff_ms: 100, ..RetrySettings::DIRECT };
+        let delays: Vec<u64> = (1..=6).map(|n| settings.backoff(n).as_millis() as u64).collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1600, 2000]);
+    }
+}
@@ -0,0 +1,1252 @@
+use crate::error::ConfigError;
+use crate::protocol::*;
+use crate::transport::{ConfigTransport, Report};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Path reported by `scan_devices` for the simulated device
+pub const SIMULATOR_DEVICE_PATH: &str = "MOCK_DEVICE_PATH";
+
+/// Serial number reported for the simulated device
+pub const SIMULATOR_SERIAL: &str = "MOCK001";
+
+/// Environment variable pointing at a JSON or TOML `SimulatorTopology` file
+pub const SIMULATOR_TOPOLOGY_ENV: &str = "OPENGRADER_SIM_TOPOLOGY";
+
+/// Resting/bottomed-out ADC readings used for uncalibrated magnetic switches
+const MAGNETIC_DEFAULT_UNPRESSED: u16 = 2000;
+const MAGNETIC_DEFAULT_PRESSED: u16 = 3000;
+
+/// Everything the firmware keeps in EEPROM
+#[derive(Debug, Clone, PartialEq)]
+struct StoredConfig {
+    /// Keycodes indexed by layer, then row-major cell
+    keymap: Vec<u16>,
+    /// (ccw, cw) keycodes indexed by layer, then encoder id
+    encoders: Vec<(u16, u16)>,
+    /// Indexed by layer, then slider id
+    sliders: Vec<SliderConfig>,
+    /// Indexed by layer, then switch id
+    magnetic_switches: Vec<MagneticSwitchConfig>,
+}
+
+/// Description of the I2C chain hanging off the simulated master, e.g.
+///
+/// ```json
+/// { "slaves": [
+///     { "address": 32, "name": "Left half", "matrix_rows": 4, "matrix_cols": 6, "encoder_count": 1 },
+///     { "address": 33, "matrix_rows": 2, "matrix_cols": 2, "drop_after": 50 }
+/// ] }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SimulatorTopology {
+    #[serde(default)]
+    pub slaves: Vec<SimulatedSlaveConfig>,
+}
+
+impl SimulatorTopology {
+    /// Load a topology, picking TOML or JSON from the file extension
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::io(format!("Failed to read simulator topology {}: {}", path.display(), e)))?;
+
+        let parsed = if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("toml")) {
+            toml::from_str(&text).map_err(|e| e.to_string())
+        } else {
+            serde_json::from_str(&text).map_err(|e| e.to_string())
+        };
+        parsed.map_err(|e| ConfigError::invalid_input(format!("Invalid simulator topology {}: {}", path.display(), e)))
+    }
+}
+
+/// One simulated I2C slave
+#[derive(Debug, Clone, Deserialize)]
+pub struct SimulatedSlaveConfig {
+    pub address: u8,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default = "default_firmware_version")]
+    pub firmware_version: [u8; 3],
+    pub matrix_rows: u8,
+    pub matrix_cols: u8,
+    #[serde(default)]
+    pub encoder_count: u8,
+    #[serde(default = "default_layer_count")]
+    pub layer_count: u8,
+    /// Whether the slave answers at all when the session starts
+    #[serde(default = "default_online")]
+    pub online: bool,
+    /// Drop off the bus after answering this many forwarded commands
+    #[serde(default)]
+    pub drop_after: Option<u32>,
+    /// Initial keycodes as `[layer][row][col]`; missing entries are KC_NO
+    #[serde(default)]
+    pub keymap: Vec<Vec<Vec<u16>>>,
+    /// Initial `[ccw, cw]` keycodes as `[layer][encoder]`
+    #[serde(default)]
+    pub encoders: Vec<Vec<[u16; 2]>>,
+}
+
+fn default_firmware_version() -> [u8; 3] {
+    [1, 0, 0]
+}
+
+fn default_layer_count() -> u8 {
+    4
+}
+
+fn default_online() -> bool {
+    true
+}
+
+/// Runtime state of a simulated slave
+#[derive(Debug, Clone)]
+struct SimulatedSlave {
+    address: u8,
+    info: DeviceInfo,
+    online: bool,
+    commands_until_drop: Option<u32>,
+    /// Old slave firmware acked keymap writes to any layer but stored them on layer 0
+    layer_write_bug: bool,
+    /// Keycodes indexed by layer, then row-major cell
+    keymap: Vec<u16>,
+    /// (ccw, cw) keycodes indexed by layer, then encoder id
+    encoders: Vec<(u16, u16)>,
+}
+
+impl SimulatedSlave {
+    fn from_config(config: &SimulatedSlaveConfig) -> Result<Self, ConfigError> {
+        if config.layer_count == 0 || config.matrix_rows == 0 || config.matrix_cols == 0 {
+            return Err(ConfigError::invalid_input(format!(
+                "Simulated slave 0x{:02X} needs at least one layer, row and column",
+                config.address
+            )));
+        }
+
+        let layers = config.layer_count as usize;
+        let rows = config.matrix_rows as usize;
+        let cols = config.matrix_cols as usize;
+        let encoder_count = config.encoder_count as usize;
+
+        let mut keymap = vec![0u16; layers * rows * cols];
+        for (layer, layer_rows) in config.keymap.iter().enumerate().take(layers) {
+            for (row, row_keys) in layer_rows.iter().enumerate().take(rows) {
+                for (col, &keycode) in row_keys.iter().enumerate().take(cols) {
+                    keymap[(layer * rows + row) * cols + col] = keycode;
+                }
+            }
+        }
+
+        let mut encoders = vec![(0u16, 0u16); layers * encoder_count];
+        for (layer, layer_encoders) in config.encoders.iter().enumerate().take(layers) {
+            for (encoder_id, &[ccw, cw]) in layer_encoders.iter().enumerate().take(encoder_count) {
+                encoders[layer * encoder_count + encoder_id] = (ccw, cw);
+            }
+        }
+
+        let [major, minor, patch] = config.firmware_version;
+        Ok(SimulatedSlave {
+            address: config.address,
+            info: DeviceInfo {
+                device_name: config.name.clone().unwrap_or_else(|| format!("Mock Slave {}", config.address)),
+                protocol_version: CONFIG_PROTOCOL_VERSION,
+                firmware_version_major: major,
+                firmware_version_minor: minor,
+                firmware_version_patch: patch,
+                device_type: 0, // Slave
+                matrix_rows: config.matrix_rows,
+                matrix_cols: config.matrix_cols,
+                encoder_count: config.encoder_count,
+                layer_count: config.layer_count,
+                i2c_devices: 0,
+            },
+            online: config.online,
+            commands_until_drop: config.drop_after,
+            layer_write_bug: false,
+            keymap,
+            encoders,
+        })
+    }
+
+    /// Count down towards a scheduled drop-off
+    fn tick(&mut self) {
+        if let Some(remaining) = self.commands_until_drop.as_mut() {
+            if *remaining == 0 {
+                self.online = false;
+                self.commands_until_drop = None;
+            } else {
+                *remaining -= 1;
+            }
+        }
+    }
+
+    fn keymap_index(&self, layer: u8, row: u8, col: u8) -> Result<usize, StatusCode> {
+        if layer >= self.info.layer_count || row >= self.info.matrix_rows || col >= self.info.matrix_cols {
+            return Err(StatusCode::InvalidParam);
+        }
+        let cols = self.info.matrix_cols as usize;
+        Ok((layer as usize * self.info.matrix_rows as usize + row as usize) * cols + col as usize)
+    }
+}
+
+/// In-process stand-in for an OpenGrader board.
+///
+/// Answers config packets the way the firmware would, so the rest of the app
+/// can run without hardware attached. Writes land in a live config that only
+/// survives `LoadConfig`/`Reboot` once it has been saved to the fake EEPROM.
+pub struct SimulatedDevice {
+    info: DeviceInfo,
+    layout: BoardLayoutInfo,
+    slider_count: u8,
+    magnetic_switch_count: u8,
+    config: StoredConfig,
+    eeprom: StoredConfig,
+    layer_state: LayerState,
+    slider_values: Vec<u8>,
+    magnetic_raw_values: Vec<u16>,
+    slaves: Vec<SimulatedSlave>,
+    /// Commands this "firmware build" lacks; they get InvalidCmd
+    missing_commands: Vec<ConfigCommand>,
+    /// Blobs delivered through `LargeWrite`, keyed by target command byte
+    blobs: HashMap<u8, Vec<u8>>,
+    /// `LargeWrite` in progress: target and bytes received so far
+    large_write: Option<(u8, Vec<u8>)>,
+    /// Response being served through `LargeRead`
+    large_read: Vec<u8>,
+    /// Slider streaming interval and when values were last pushed
+    slider_stream: Option<(Duration, Instant)>,
+}
+
+impl SimulatedDevice {
+    /// 4x4 board: two encoders in row 0, switches in rows 0-2,
+    /// a slider, a potentiometer and two magnetic switches in row 3
+    pub fn new() -> Self {
+        use LayoutCellType::*;
+        let cells = [
+            (Switch, 0), (Switch, 1), (Encoder, 0), (Encoder, 1),
+            (Switch, 2), (Switch, 3), (Switch, 4), (Switch, 5),
+            (Switch, 6), (Switch, 7), (Switch, 8), (Switch, 9),
+            (Slider, 0), (Potentiometer, 1), (MagneticSwitch, 0), (MagneticSwitch, 1),
+        ];
+
+        let info = DeviceInfo {
+            device_name: "Mock OpenGrader".to_string(),
+            protocol_version: CONFIG_PROTOCOL_VERSION,
+            firmware_version_major: 1,
+            firmware_version_minor: 0,
+            firmware_version_patch: 0,
+            device_type: 1, // Master
+            matrix_rows: 4,
+            matrix_cols: 4,
+            encoder_count: 2,
+            layer_count: 4,
+            i2c_devices: 0,
+        };
+        let layout = BoardLayoutInfo {
+            version: 1,
+            matrix_rows: 4,
+            matrix_cols: 4,
+            encoder_count: 2,
+            first_encoder_column: 2,
+            encoders_per_row: 2,
+            bitmap_length: 2,
+            encoder_bitmap: vec![0b0000_1100, 0b0000_0000],
+            layout: cells
+                .iter()
+                .map(|&(cell_type, component_id)| LayoutCell { cell_type, component_id })
+                .collect(),
+        };
+
+        let mut device = SimulatedDevice {
+            info,
+            layout,
+            slider_count: 2,
+            magnetic_switch_count: 2,
+            config: StoredConfig {
+                keymap: Vec::new(),
+                encoders: Vec::new(),
+                sliders: Vec::new(),
+                magnetic_switches: Vec::new(),
+            },
+            eeprom: StoredConfig {
+                keymap: Vec::new(),
+                encoders: Vec::new(),
+                sliders: Vec::new(),
+                magnetic_switches: Vec::new(),
+            },
+            layer_state: LayerState { active_mask: 0x01, default_layer: 0 },
+            slider_values: vec![0, 64],
+            magnetic_raw_values: vec![MAGNETIC_DEFAULT_UNPRESSED; 2],
+            slaves: Vec::new(),
+            missing_commands: Vec::new(),
+            blobs: HashMap::new(),
+            large_write: None,
+            large_read: Vec::new(),
+            slider_stream: None,
+        };
+        device.config = device.factory_config();
+        device.eeprom = device.config.clone();
+        device
+    }
+
+    /// Default board with the I2C slaves described by `topology` attached
+    pub fn with_topology(topology: &SimulatorTopology) -> Result<Self, ConfigError> {
+        let mut device = Self::new();
+        for slave in &topology.slaves {
+            if device.slaves.iter().any(|s| s.address == slave.address) {
+                return Err(ConfigError::invalid_input(format!("Duplicate simulated slave address 0x{:02X}", slave.address)));
+            }
+            device.slaves.push(SimulatedSlave::from_config(slave)?);
+        }
+        device.info.i2c_devices = device.slaves.len() as u8;
+        Ok(device)
+    }
+
+    /// Build the simulator for `SIMULATOR_DEVICE_PATH`, honouring `SIMULATOR_TOPOLOGY_ENV` if set
+    pub fn from_env() -> Result<Self, ConfigError> {
+        match std::env::var_os(SIMULATOR_TOPOLOGY_ENV) {
+            Some(path) => Self::with_topology(&SimulatorTopology::from_file(Path::new(&path))?),
+            None => Ok(Self::new()),
+        }
+    }
+
+    /// Default configuration the firmware falls back to on `ResetConfig`
+    fn factory_config(&self) -> StoredConfig {
+        let layers = self.info.layer_count as usize;
+        let cells = self.cell_count();
+
+        let mut keymap = vec![0u16; layers * cells];
+        // Layer 0: letters starting at KC_A on every switch cell
+        for (index, keycode) in keymap.iter_mut().take(cells).enumerate() {
+            *keycode = 0x04 + index as u16;
+        }
+
+        let mut encoders = vec![(0u16, 0u16); layers * self.info.encoder_count as usize];
+        for layer in encoders.chunks_mut(self.info.encoder_count.max(1) as usize) {
+            for (encoder_id, entry) in layer.iter_mut().enumerate() {
+                *entry = match encoder_id {
+                    0 => (0x52, 0x51), // Up/Down arrows for encoder 0
+                    1 => (0x50, 0x4F), // Left/Right arrows for encoder 1
+                    _ => (0x00, 0x00), // KC_NO
+                };
+            }
+        }
+
+        let mut sliders = Vec::with_capacity(layers * self.slider_count as usize);
+        let mut magnetic_switches = Vec::with_capacity(layers * self.magnetic_switch_count as usize);
+        for layer in 0..self.info.layer_count {
+            for slider_id in 0..self.slider_count {
+                sliders.push(SliderConfig {
+                    layer,
+                    slider_id,
+                    midi_cc: 1 + slider_id,
+                    midi_channel: 0,
+                    min_midi_value: 0,
+                    max_midi_value: 127,
+                });
+            }
+            for switch_id in 0..self.magnetic_switch_count {
+                magnetic_switches.push(MagneticSwitchConfig {
+                    layer,
+                    switch_id,
+                    unpressed_value: MAGNETIC_DEFAULT_UNPRESSED,
+                    pressed_value: MAGNETIC_DEFAULT_PRESSED,
+                    sensitivity: 50,
+                    keycode: 0,
+                    is_calibrated: false,
+                });
+            }
+        }
+
+        StoredConfig { keymap, encoders, sliders, magnetic_switches }
+    }
+
+    /// Move a slider or potentiometer to a new position
+    #[cfg(test)]
+    pub fn set_slider_value(&mut self, slider_id: u8, value: u8) {
+        if let Some(slot) = self.slider_values.get_mut(slider_id as usize) {
+            *slot = value;
+        }
+    }
+
+    /// Feed a new raw hall-effect reading for a magnetic switch
+    #[cfg(test)]
+    pub fn set_magnetic_raw_value(&mut self, switch_id: u8, raw: u16) {
+        if let Some(slot) = self.magnetic_raw_values.get_mut(switch_id as usize) {
+            *slot = raw;
+        }
+    }
+
+    /// Plug a slave back in or pull it off the bus mid-session
+    #[cfg(test)]
+    pub fn set_slave_online(&mut self, address: u8, online: bool) -> bool {
+        match self.slaves.iter_mut().find(|s| s.address == address) {
+            Some(slave) => {
+                slave.online = online;
+                slave.commands_until_drop = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Make a slave store keymap writes on layer 0 whatever layer they name, while still acking them
+    #[cfg(test)]
+    pub fn set_slave_layer_write_bug(&mut self, address: u8, enabled: bool) -> bool {
+        match self.slaves.iter_mut().find(|s| s.address == address) {
+            Some(slave) => {
+                slave.layer_write_bug = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Pretend to run firmware speaking another protocol version
+    #[cfg(test)]
+    pub fn set_protocol_version(&mut self, version: u8) {
+        self.info.protocol_version = version;
+    }
+
+    /// Behave like firmware built without `command`
+    #[cfg(test)]
+    pub fn remove_command(&mut self, command: ConfigCommand) {
+        self.missing_commands.push(command);
+    }
+
+    /// What `GetCapabilities` reports: everything the simulator implements
+    pub fn capabilities(&self) -> DeviceCapabilities {
+        let commands: Vec<ConfigCommand> = ConfigCommand::ALL
+            .iter()
+            .copied()
+            .filter(|&c| c != ConfigCommand::SetI2CConfig && !c.is_event_only())
+            .filter(|c| !self.missing_commands.contains(c))
+            .collect();
+        DeviceCapabilities::from_parts(self.info.protocol_version, &commands, &LayoutCellType::ALL)
+    }
+
+    /// The `SliderValues` event due now, if the host subscribed to slider streaming
+    pub fn poll_event(&mut self) -> Option<ConfigPacket> {
+        let (interval, last) = self.slider_stream.as_mut()?;
+        if last.elapsed() < *interval {
+            return None;
+        }
+        *last = Instant::now();
+        Some(ConfigPacket::event(ConfigCommand::SliderValues, &self.slider_values).expect("slider values fit in one packet"))
+    }
+
+    /// True if the live config differs from what is stored in EEPROM
+    pub fn has_unsaved_changes(&self) -> bool {
+        self.config != self.eeprom
+    }
+
+    /// Handle one request packet and build the response the firmware would send
+    pub fn handle(&mut self, request: &ConfigPacket) -> ConfigPacket {
+        let payload = &request.payload[..(request.payload_length as usize).min(CONFIG_MAX_PAYLOAD_SIZE)];
+        let result = match request.command() {
+            Ok(command) if !self.missing_commands.contains(&command) => self.dispatch(command, payload),
+            _ => Err(StatusCode::InvalidCmd),
+        };
+        let (status, response) = match result {
+            Ok(response) => (StatusCode::Ok, response),
+            Err(status) => (status, Vec::new()),
+        };
+
+        // Echo the raw command byte, even one the firmware didn't recognise
+        let mut packet = ConfigPacket::new(ConfigCommand::GetInfo, request.sequence, &response)
+            .expect("simulator responses fit in one packet");
+        packet.command = request.command;
+        packet.status = status as u8;
+        packet
+    }
+
+    fn dispatch(&mut self, command: ConfigCommand, payload: &[u8]) -> Result<Vec<u8>, StatusCode> {
+        match command {
+            ConfigCommand::GetInfo => Ok(device_info_payload(&self.info)),
+            ConfigCommand::GetCapabilities if self.info.protocol_version < 2 => Err(StatusCode::InvalidCmd),
+            ConfigCommand::GetCapabilities => Ok(self.capabilities().to_payload()),
+            ConfigCommand::GetDeviceStatus => Ok(vec![self.has_unsaved_changes() as u8]),
+            ConfigCommand::GetLayoutInfo => Ok(layout_info_payload(&self.layout)),
+            ConfigCommand::GetLayoutCellType => {
+                let [row, col] = args::<2>(payload)?;
+                Ok(vec![self.cell(row, col)?.cell_type as u8])
+            }
+            ConfigCommand::GetLayoutCellComponentId => {
+                let [row, col] = args::<2>(payload)?;
+                Ok(vec![self.cell(row, col)?.component_id])
+            }
+
+            ConfigCommand::GetKeymap => {
+                let [layer, row, col] = args::<3>(payload)?;
+                let index = self.keymap_index(layer, row, col)?;
+                let keycode = self.config.keymap[index];
+                Ok(KeymapEntry { layer, row, col, keycode }.to_payload())
+            }
+            ConfigCommand::SetKeymap => {
+                let entry = KeymapEntry::from_payload(payload).map_err(|_| StatusCode::InvalidParam)?;
+                let index = self.keymap_index(entry.layer, entry.row, entry.col)?;
+                self.config.keymap[index] = entry.keycode;
+                Ok(entry.to_payload())
+            }
+            ConfigCommand::GetEncoderMap => {
+                let [layer, encoder_id] = args::<2>(payload)?;
+                let index = self.encoder_index(layer, encoder_id)?;
+                let (ccw_keycode, cw_keycode) = self.config.encoders[index];
+                Ok(EncoderEntry { layer, encoder_id, ccw_keycode, cw_keycode, reserved: 0 }.to_payload())
+            }
+            ConfigCommand::SetEncoderMap => {
+                let entry = EncoderEntry::from_payload(payload).map_err(|_| StatusCode::InvalidParam)?;
+                let index = self.encoder_index(entry.layer, entry.encoder_id)?;
+                self.config.encoders[index] = (entry.ccw_keycode, entry.cw_keycode);
+                Ok(entry.to_payload())
+            }
+
+            ConfigCommand::GetKeymapBulk => {
+                let [layer, start_lo, start_hi, count] = args::<4>(payload)?;
+                let start = u16::from_le_bytes([start_lo, start_hi]);
+                let range = self.keymap_range(layer, start, count as usize)?;
+                let keycodes = self.config.keymap[range].to_vec();
+                Ok(KeymapRange { layer, start, keycodes }.to_payload())
+            }
+            ConfigCommand::SetKeymapBulk => {
+                let request = KeymapRange::from_payload(payload).map_err(|_| StatusCode::InvalidParam)?;
+                let range = self.keymap_range(request.layer, request.start, request.keycodes.len())?;
+                self.config.keymap[range].copy_from_slice(&request.keycodes);
+                Ok(Vec::new())
+            }
+            ConfigCommand::GetEncoderMapBulk => {
+                let [layer, start, count] = args::<3>(payload)?;
+                if count as usize > ENCODER_BULK_MAX || start as usize + count as usize > self.info.encoder_count as usize {
+                    return Err(StatusCode::InvalidParam);
+                }
+                let first = self.encoder_index(layer, start)?;
+                let bindings = self.config.encoders[first..first + count as usize].to_vec();
+                Ok(EncoderRange { layer, start, bindings }.to_payload())
+            }
+
+            ConfigCommand::LargeWrite => {
+                let acked = match LargeWriteChunk::from_payload(payload) {
+                    Ok(chunk) => self.accept_large_chunk(chunk),
+                    // Corrupted chunk: ack what we have so the host resends it
+                    Err(_) => self.large_write.as_ref().map_or(0, |(_, data)| data.len()),
+                };
+                Ok((acked as u16).to_le_bytes().to_vec())
+            }
+            ConfigCommand::LargeRead => {
+                let [target, offset_lo, offset_hi] = args::<3>(payload)?;
+                let offset = u16::from_le_bytes([offset_lo, offset_hi]) as usize;
+                if offset == 0 {
+                    // A stored blob if the host wrote one, otherwise the target's normal response
+                    self.large_read = match self.blobs.get(&target) {
+                        Some(blob) => blob.clone(),
+                        None => {
+                            let command = ConfigCommand::try_from(target).map_err(|_| StatusCode::InvalidParam)?;
+                            if matches!(command, ConfigCommand::LargeRead | ConfigCommand::LargeWrite) {
+                                return Err(StatusCode::InvalidParam);
+                            }
+                            self.dispatch(command, &payload[3..])?
+                        }
+                    };
+                }
+                if offset > self.large_read.len() {
+                    return Err(StatusCode::InvalidParam);
+                }
+                let end = (offset + LARGE_READ_CHUNK).min(self.large_read.len());
+                Ok(LargeReadChunk {
+                    offset: offset as u16,
+                    total: self.large_read.len() as u16,
+                    data: self.large_read[offset..end].to_vec(),
+                }
+                .to_payload())
+            }
+            // Events only ever travel device -> host
+            ConfigCommand::KeyEvent | ConfigCommand::SliderValues => Err(StatusCode::InvalidCmd),
+
+            ConfigCommand::GetLayerState => Ok(self.layer_state.to_payload().to_vec()),
+            ConfigCommand::SetLayerState => {
+                let [active_mask, default_layer] = args::<2>(payload)?;
+                if default_layer >= self.info.layer_count {
+                    return Err(StatusCode::InvalidParam);
+                }
+                // The default layer is always active
+                self.layer_state = LayerState {
+                    active_mask: active_mask | (1 << default_layer),
+                    default_layer,
+                };
+                Ok(self.layer_state.to_payload().to_vec())
+            }
+
+            ConfigCommand::GetSliderValue => {
+                let [slider_id] = args::<1>(payload)?;
+                let value = self.slider_values.get(slider_id as usize).ok_or(StatusCode::InvalidParam)?;
+                Ok(vec![*value])
+            }
+            ConfigCommand::SubscribeSliders => {
+                let [interval_lo, interval_hi] = args::<2>(payload)?;
+                let interval_ms = u16::from_le_bytes([interval_lo, interval_hi]);
+                if interval_ms == 0 {
+                    return Err(StatusCode::InvalidParam);
+                }
+                self.slider_stream = Some((Duration::from_millis(interval_ms as u64), Instant::now()));
+                Ok(Vec::new())
+            }
+            ConfigCommand::UnsubscribeSliders => {
+                self.slider_stream = None;
+                Ok(Vec::new())
+            }
+            ConfigCommand::GetSliderConfig => {
+                let [layer, slider_id] = args::<2>(payload)?;
+                let index = self.slider_index(layer, slider_id)?;
+                Ok(self.config.sliders[index].to_payload())
+            }
+            ConfigCommand::SetSliderConfig => {
+                let config = SliderConfig::from_payload(payload).map_err(|_| StatusCode::InvalidParam)?;
+                let index = self.slider_index(config.layer, config.slider_id)?;
+                if config.midi_channel > 15
+                    || config.midi_cc > 127
+                    || config.min_midi_value > config.max_midi_value
+                    || config.max_midi_value > 127
+                {
+                    return Err(StatusCode::InvalidParam);
+                }
+                self.config.sliders[index] = config;
+                Ok(self.config.sliders[index].to_payload())
+            }
+
+            ConfigCommand::GetMagneticSwitchValue => {
+                let [switch_id] = args::<1>(payload)?;
+                let raw = *self.magnetic_raw_values.get(switch_id as usize).ok_or(StatusCode::InvalidParam)?;
+                let index = self.magnetic_index(self.layer_state.default_layer, switch_id)?;
+                let config = &self.config.magnetic_switches[index];
+                let mut response = raw.to_le_bytes().to_vec();
+                response.push(press_percentage(raw, config.unpressed_value, config.pressed_value));
+                Ok(response)
+            }
+            ConfigCommand::GetMagneticSwitchConfig => {
+                let [layer, switch_id] = args::<2>(payload)?;
+                let index = self.magnetic_index(layer, switch_id)?;
+                Ok(self.config.magnetic_switches[index].to_payload())
+            }
+            ConfigCommand::SetMagneticSwitchConfig => {
+                let config = MagneticSwitchConfig::from_payload(payload).map_err(|_| StatusCode::InvalidParam)?;
+                let index = self.magnetic_index(config.layer, config.switch_id)?;
+                if config.sensitivity > 100 {
+                    return Err(StatusCode::InvalidParam);
+                }
+                self.config.magnetic_switches[index] = config;
+                Ok(self.config.magnetic_switches[index].to_payload())
+            }
+            ConfigCommand::CalibrateMagneticSwitch => {
+                let [switch_id, step] = args::<2>(payload)?;
+                let raw = *self.magnetic_raw_values.get(switch_id as usize).ok_or(StatusCode::InvalidParam)?;
+                // Calibration is per physical switch, so it applies to every layer
+                for config in self.config.magnetic_switches.iter_mut().filter(|c| c.switch_id == switch_id) {
+                    match step {
+                        0 => config.is_calibrated = false,
+                        1 => config.unpressed_value = raw,
+                        2 => config.pressed_value = raw,
+                        3 => config.is_calibrated = config.pressed_value != config.unpressed_value,
+                        _ => return Err(StatusCode::InvalidParam),
+                    }
+                }
+                Ok(Vec::new())
+            }
+            ConfigCommand::SetMagneticSwitchSensitivity => {
+                let [switch_id, sensitivity] = args::<2>(payload)?;
+                if switch_id >= self.magnetic_switch_count || sensitivity > 100 {
+                    return Err(StatusCode::InvalidParam);
+                }
+                for config in self.config.magnetic_switches.iter_mut().filter(|c| c.switch_id == switch_id) {
+                    config.sensitivity = sensitivity;
+                }
+                Ok(Vec::new())
+            }
+
+            ConfigCommand::SaveConfig => {
+                self.eeprom = self.config.clone();
+                Ok(Vec::new())
+            }
+            ConfigCommand::LoadConfig => {
+                self.config = self.eeprom.clone();
+                Ok(Vec::new())
+            }
+            ConfigCommand::ResetConfig => {
+                self.config = self.factory_config();
+                self.eeprom = self.config.clone();
+                Ok(Vec::new())
+            }
+            ConfigCommand::Reboot => {
+                // Unsaved changes, layer state and subscriptions do not survive a reboot
+                self.config = self.eeprom.clone();
+                self.layer_state = LayerState { active_mask: 0x01, default_layer: 0 };
+                self.slider_stream = None;
+                Ok(Vec::new())
+            }
+
+            ConfigCommand::MidiSendRaw => {
+                if payload.is_empty() {
+                    return Err(StatusCode::InvalidParam);
+                }
+                Ok(Vec::new())
+            }
+            ConfigCommand::MidiNoteOn | ConfigCommand::MidiNoteOff | ConfigCommand::MidiControlChange => {
+                let [channel, number, value] = args::<3>(payload)?;
+                if channel > 15 || number > 127 || value > 127 {
+                    return Err(StatusCode::InvalidParam);
+                }
+                Ok(Vec::new())
+            }
+
+            ConfigCommand::GetI2CDevices => {
+                let mut response = vec![self.slaves.len() as u8];
+                for slave in &self.slaves {
+                    response.push(slave.address);
+                    response.push(slave.online as u8);
+                }
+                Ok(response)
+            }
+            ConfigCommand::SetI2CConfig => Err(StatusCode::NotSupported),
+            ConfigCommand::GetSlaveInfo => {
+                let [slave_addr] = args::<1>(payload)?;
+                let slave = self.reach_slave(slave_addr)?;
+                Ok(device_info_payload(&slave.info))
+            }
+            ConfigCommand::GetSlaveKeymap => {
+                let [slave_addr, layer, row, col] = args::<4>(payload)?;
+                let slave = self.reach_slave(slave_addr)?;
+                let index = slave.keymap_index(layer, row, col)?;
+                let keycode = slave.keymap[index];
+                // Firmware echoes the entry without the slave address
+                Ok(KeymapEntry { layer, row, col, keycode }.to_payload())
+            }
+            ConfigCommand::SetSlaveKeymap => {
+                let [slave_addr] = args::<1>(payload)?;
+                let entry = SlaveKeymapEntry::from_payload(slave_addr, &payload[1..]).map_err(|_| StatusCode::InvalidParam)?;
+                let slave = self.reach_slave(slave_addr)?;
+                let index = slave.keymap_index(entry.layer, entry.row, entry.col)?;
+                let index = if slave.layer_write_bug { slave.keymap_index(0, entry.row, entry.col)? } else { index };
+                slave.keymap[index] = entry.keycode;
+                Ok(Vec::new())
+            }
+            ConfigCommand::GetSlaveEncoder => {
+                let [slave_addr, layer, encoder_id] = args::<3>(payload)?;
+                let slave = self.reach_slave(slave_addr)?;
+                let index = per_layer_index(layer, slave.info.layer_count, encoder_id, slave.info.encoder_count)?;
+                let (ccw_keycode, cw_keycode) = slave.encoders[index];
+                Ok(EncoderEntry { layer, encoder_id, ccw_keycode, cw_keycode, reserved: 0 }.to_payload())
+            }
+            ConfigCommand::SetSlaveEncoder => {
+                let [slave_addr] = args::<1>(payload)?;
+                let entry = SlaveEncoderEntry::from_payload(slave_addr, &payload[1..]).map_err(|_| StatusCode::InvalidParam)?;
+                let slave = self.reach_slave(slave_addr)?;
+                let index = per_layer_index(entry.layer, slave.info.layer_count, entry.encoder_id, slave.info.encoder_count)?;
+                slave.encoders[index] = (entry.ccw_keycode, entry.cw_keycode);
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// Forward a command over the simulated I2C bus.
+    /// Unknown or offline slaves fail the way a NACKed transfer does on the real bus.
+    fn reach_slave(&mut self, address: u8) -> Result<&mut SimulatedSlave, StatusCode> {
+        let slave = self
+            .slaves
+            .iter_mut()
+            .find(|s| s.address == address)
+            .ok_or(StatusCode::Error)?;
+        slave.tick();
+        if !slave.online {
+            return Err(StatusCode::Error);
+        }
+        Ok(slave)
+    }
+
+    fn cell_count(&self) -> usize {
+        self.info.matrix_rows as usize * self.info.matrix_cols as usize
+    }
+
+    fn cell(&self, row: u8, col: u8) -> Result<&LayoutCell, StatusCode> {
+        let index = self.layout.cell_index(row, col).ok_or(StatusCode::InvalidParam)?;
+        self.layout.layout.get(index).ok_or(StatusCode::InvalidParam)
+    }
+
+    fn keymap_index(&self, layer: u8, row: u8, col: u8) -> Result<usize, StatusCode> {
+        if layer >= self.info.layer_count {
+            return Err(StatusCode::InvalidParam);
+        }
+        let cell = self.layout.cell_index(row, col).ok_or(StatusCode::InvalidParam)?;
+        Ok(layer as usize * self.cell_count() + cell)
+    }
+
+    /// Append an in-order chunk of a `LargeWrite`; returns the offset expected next
+    fn accept_large_chunk(&mut self, chunk: LargeWriteChunk) -> usize {
+        if chunk.offset == 0 {
+            self.large_write = Some((chunk.target, Vec::with_capacity(chunk.total as usize)));
+        }
+        let Some((target, data)) = self.large_write.as_mut() else { return 0 };
+        if *target != chunk.target || chunk.offset as usize != data.len() {
+            // Gap or stray chunk: ask for what's actually missing
+            return data.len();
+        }
+
+        data.extend_from_slice(&chunk.data);
+        let received = data.len();
+        if received >= chunk.total as usize {
+            let (target, blob) = self.large_write.take().unwrap();
+            self.blobs.insert(target, blob);
+        }
+        received
+    }
+
+    /// Flat keymap indices for `count` row-major cells of `layer` starting at `start`
+    fn keymap_range(&self, layer: u8, start: u16, count: usize) -> Result<std::ops::Range<usize>, StatusCode> {
+        if layer >= self.info.layer_count || count > KEYMAP_BULK_MAX_KEYS || start as usize + count > self.cell_count() {
+            return Err(StatusCode::InvalidParam);
+        }
+        let first = layer as usize * self.cell_count() + start as usize;
+        Ok(first..first + count)
+    }
+
+    fn encoder_index(&self, layer: u8, encoder_id: u8) -> Result<usize, StatusCode> {
+        per_layer_index(layer, self.info.layer_count, encoder_id, self.info.encoder_count)
+    }
+
+    fn slider_index(&self, layer: u8, slider_id: u8) -> Result<usize, StatusCode> {
+        per_layer_index(layer, self.info.layer_count, slider_id, self.slider_count)
+    }
+
+    fn magnetic_index(&self, layer: u8, switch_id: u8) -> Result<usize, StatusCode> {
+        per_layer_index(layer, self.info.layer_count, switch_id, self.magnetic_switch_count)
+    }
+}
+
+impl Default for SimulatedDevice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Index into a `[layer][id]` table flattened row-major
+fn per_layer_index(layer: u8, layer_count: u8, id: u8, per_layer: u8) -> Result<usize, StatusCode> {
+    if layer >= layer_count || id >= per_layer {
+        return Err(StatusCode::InvalidParam);
+    }
+    Ok(layer as usize * per_layer as usize + id as usize)
+}
+
+/// Map a raw reading onto 0-100% travel between the calibration points
+fn press_percentage(raw: u16, unpressed: u16, pressed: u16) -> u8 {
+    if pressed == unpressed {
+        return 0;
+    }
+    let span = pressed as i32 - unpressed as i32;
+    let travel = (raw as i32 - unpressed as i32) * 100 / span;
+    travel.clamp(0, 100) as u8
+}
+
+/// Take the first `N` argument bytes of a request payload
+fn args<const N: usize>(payload: &[u8]) -> Result<[u8; N], StatusCode> {
+    payload
+        .get(..N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(StatusCode::InvalidParam)
+}
+
+/// Encode a `DeviceInfo` the way the firmware lays it out on the wire
+fn device_info_payload(info: &DeviceInfo) -> Vec<u8> {
+    let mut payload = vec![0u8; CONFIG_MAX_PAYLOAD_SIZE];
+    payload[0] = info.protocol_version;
+    payload[1] = info.firmware_version_major;
+    payload[2] = info.firmware_version_minor;
+    payload[3] = info.firmware_version_patch;
+    payload[4] = info.device_type;
+    payload[5] = info.matrix_rows;
+    payload[6] = info.matrix_cols;
+    payload[7] = info.encoder_count;
+    payload[8] = info.layer_count;
+    payload[9] = info.i2c_devices;
+    let name = info.device_name.as_bytes();
+    let name_len = name.len().min(31); // keep the terminating NUL
+    payload[10..10 + name_len].copy_from_slice(&name[..name_len]);
+    payload
+}
+
+/// Encode the board layout header + encoder bitmap
+fn layout_info_payload(layout: &BoardLayoutInfo) -> Vec<u8> {
+    let mut payload = vec![
+        layout.version,
+        layout.matrix_rows,
+        layout.matrix_cols,
+        layout.encoder_count,
+        layout.first_encoder_column,
+        layout.encoders_per_row,
+        layout.bitmap_length,
+        0, // reserved
+    ];
+    payload.extend_from_slice(&layout.encoder_bitmap);
+    payload
+}
+
+/// Transport that feeds packets straight into a `SimulatedDevice`
+pub struct SimulatorTransport {
+    device: Mutex<SimulatedDevice>,
+    pending: Mutex<VecDeque<Report>>,
+}
+
+impl SimulatorTransport {
+    pub fn new(device: SimulatedDevice) -> Self {
+        SimulatorTransport {
+            device: Mutex::new(device),
+            pending: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Queue an event as if the firmware had pushed it on its own
+    #[cfg(test)]
+    pub fn push_event(&self, command: ConfigCommand, payload: &[u8]) {
+        let event = ConfigPacket::event(command, payload).expect("simulated events fit in one packet");
+        self.pending.lock().unwrap().push_back(event.to_bytes());
+    }
+
+    /// Inspect or poke the simulated device while it is connected
+    #[cfg(test)]
+    pub fn with_device<R>(&self, f: impl FnOnce(&mut SimulatedDevice) -> R) -> R {
+        f(&mut self.device.lock().unwrap())
+    }
+}
+
+impl ConfigTransport for SimulatorTransport {
+    fn write_report(&self, report: &Report) -> Result<(), ConfigError> {
+        let request = ConfigPacket::from_bytes(report)?;
+        let response = self.device.lock().unwrap().handle(&request);
+        self.pending.lock().unwrap().push_back(response.to_bytes());
+        Ok(())
+    }
+
+    fn read_report(&self, _timeout_ms: i32) -> Result<Option<Report>, ConfigError> {
+        if let Some(report) = self.pending.lock().unwrap().pop_front() {
+            return Ok(Some(report));
+        }
+        Ok(self.device.lock().unwrap().poll_event().map(|event| event.to_bytes()))
+    }
+}
+
+/// A `HidManager` connected to `device`, with the transport kept for poking the device
+#[cfg(test)]
+pub fn connected_simulator(device: SimulatedDevice) -> (crate::hid_manager::HidManager, std::sync::Arc<SimulatorTransport>) {
+    let transport = std::sync::Arc::new(SimulatorTransport::new(device));
+    let manager = crate::hid_manager::HidManager::new().unwrap();
+    manager.connect_transport(transport.clone());
+    (manager, transport)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{DeviceEvent, SliderReading};
+    use crate::hid_manager::{HidManager, SliderStreamMode};
+    use std::sync::Arc;
+    use tokio::sync::broadcast;
+
+    #[tokio::test]
+    async fn keymap_and_encoder_writes_read_back() {
+        let (manager, _sim) = connected_simulator(SimulatedDevice::new());
+
+        let entry = KeymapEntry { layer: 2, row: 3, col: 1, keycode: 0x2C };
+        manager.set_keymap_entry(&entry).await.unwrap();
+        assert_eq!(manager.get_keymap_entry(2, 3, 1).await.unwrap().keycode, 0x2C);
+        assert_eq!(manager.get_keymap_entry(0, 0, 0).await.unwrap().keycode, 0x04);
+
+        let encoder = EncoderEntry { layer: 1, encoder_id: 1, ccw_keycode: 0x80, cw_keycode: 0x81, reserved: 0 };
+        manager.set_encoder_entry(&encoder).await.unwrap();
+        let read = manager.get_encoder_entry(1, 1).await.unwrap();
+        assert_eq!((read.ccw_keycode, read.cw_keycode), (0x80, 0x81));
+    }
+
+    #[tokio::test]
+    async fn out_of_range_requests_are_rejected() {
+        let (manager, _sim) = connected_simulator(SimulatedDevice::new());
+
+        assert!(manager.get_keymap_entry(4, 0, 0).await.is_err());
+        assert!(manager.get_encoder_entry(0, 2).await.is_err());
+        assert!(manager.get_slider_config(0, 5).await.is_err());
+        assert!(manager.set_layer_state(&LayerState { active_mask: 1, default_layer: 9 }).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn eeprom_save_load_and_reset() {
+        let (manager, sim) = connected_simulator(SimulatedDevice::new());
+
+        manager.set_keymap_entry(&KeymapEntry { layer: 0, row: 0, col: 0, keycode: 0x29 }).await.unwrap();
+        assert!(sim.with_device(|d| d.has_unsaved_changes()));
+        manager.load_config().await.unwrap();
+        assert_eq!(manager.get_keymap_entry(0, 0, 0).await.unwrap().keycode, 0x04);
+
+        manager.set_keymap_entry(&KeymapEntry { layer: 0, row: 0, col: 0, keycode: 0x29 }).await.unwrap();
+        manager.save_config().await.unwrap();
+        assert!(!sim.with_device(|d| d.has_unsaved_changes()));
+        manager.load_config().await.unwrap();
+        assert_eq!(manager.get_keymap_entry(0, 0, 0).await.unwrap().keycode, 0x29);
+
+        manager.reset_config().await.unwrap();
+        assert_eq!(manager.get_keymap_entry(0, 0, 0).await.unwrap().keycode, 0x04);
+    }
+
+    #[tokio::test]
+    async fn sliders_and_magnetic_switches() {
+        let (manager, sim) = connected_simulator(SimulatedDevice::new());
+
+        let slider = SliderConfig {
+            layer: 1,
+            slider_id: 0,
+            midi_cc: 74,
+            midi_channel: 3,
+            min_midi_value: 10,
+            max_midi_value: 100,
+        };
+        manager.set_slider_config(&slider).await.unwrap();
+        assert_eq!(manager.get_slider_config(1, 0).await.unwrap().midi_cc, 74);
+        sim.with_device(|d| d.set_slider_value(0, 99));
+        assert_eq!(manager.get_slider_value(0).await.unwrap(), 99);
+
+        // Walk through the calibration sequence with simulated readings
+        manager.calibrate_magnetic_switch(1, 0).await.unwrap();
+        sim.with_device(|d| d.set_magnetic_raw_value(1, 1000));
+        manager.calibrate_magnetic_switch(1, 1).await.unwrap();
+        sim.with_device(|d| d.set_magnetic_raw_value(1, 3000));
+        manager.calibrate_magnetic_switch(1, 2).await.unwrap();
+        manager.calibrate_magnetic_switch(1, 3).await.unwrap();
+
+        let config = manager.get_magnetic_switch_config(2, 1).await.unwrap();
+        assert!(config.is_calibrated);
+        assert_eq!((config.unpressed_value, config.pressed_value), (1000, 3000));
+
+        sim.with_device(|d| d.set_magnetic_raw_value(1, 2000));
+        assert_eq!(manager.get_magnetic_switch_value(1).await.unwrap(), 50);
+
+        manager.set_magnetic_switch_sensitivity(1, 80).await.unwrap();
+        assert_eq!(manager.get_magnetic_switch_config(0, 1).await.unwrap().sensitivity, 80);
+    }
+
+    #[tokio::test]
+    async fn layer_state_keeps_default_layer_active() {
+        let (manager, _sim) = connected_simulator(SimulatedDevice::new());
+
+        let applied = manager.set_layer_state(&LayerState { active_mask: 0x04, default_layer: 1 }).await.unwrap();
+        assert_eq!((applied.active_mask, applied.default_layer), (0x06, 1));
+        assert_eq!(manager.get_layer_state().await.unwrap().active_mask, 0x06);
+    }
+
+    fn two_slave_topology() -> SimulatorTopology {
+        serde_json::from_str(r#"{ "slaves": [
+            { "address": 32, "name": "Left", "matrix_rows": 2, "matrix_cols": 3, "encoder_count": 1,
+              "keymap": [[[4, 5, 6]], [[30, 31]]], "encoders": [[[82, 81]]] },
+            { "address": 33, "matrix_rows": 1, "matrix_cols": 1, "online": false }
+        ] }"#).unwrap()
+    }
+
+    #[tokio::test]
+    async fn slaves_are_listed_with_their_info() {
+        let (manager, _sim) = connected_simulator(SimulatedDevice::with_topology(&two_slave_topology()).unwrap());
+
+        assert_eq!(manager.get_device_info().await.unwrap().i2c_devices, 2);
+        let devices = manager.get_i2c_devices().await.unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!((devices[0].address, devices[0].status, devices[0].name.as_str()), (32, 1, "Left"));
+        assert_eq!((devices[1].address, devices[1].status), (33, 0));
+
+        let info = manager.get_slave_info(32).await.unwrap();
+        assert_eq!((info.matrix_rows, info.matrix_cols, info.encoder_count), (2, 3, 1));
+        assert!(manager.get_slave_info(33).await.is_err());
+        assert!(manager.get_slave_info(40).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn slave_keymaps_are_kept_per_layer() {
+        let (manager, _sim) = connected_simulator(SimulatedDevice::with_topology(&two_slave_topology()).unwrap());
+
+        assert_eq!(manager.get_slave_keymap_entry(32, 0, 0, 2).await.unwrap().keycode, 6);
+        assert_eq!(manager.get_slave_keymap_entry(32, 1, 0, 1).await.unwrap().keycode, 31);
+
+        let entry = SlaveKeymapEntry { slave_addr: 32, layer: 3, row: 1, col: 2, keycode: 0x2A };
+        manager.set_slave_keymap_entry(&entry).await.unwrap();
+        assert_eq!(manager.get_slave_keymap_entry(32, 3, 1, 2).await.unwrap().keycode, 0x2A);
+        assert_eq!(manager.get_slave_keymap_entry(32, 0, 1, 2).await.unwrap().keycode, 0);
+
+        let encoder = manager.get_slave_encoder_entry(32, 0, 0).await.unwrap();
+        assert_eq!((encoder.ccw_keycode, encoder.cw_keycode), (82, 81));
+        assert!(manager.get_slave_encoder_entry(32, 0, 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn slaves_can_drop_off_mid_session() {
+        let mut topology = two_slave_topology();
+        topology.slaves[0].drop_after = Some(2);
+        let (manager, transport) = connected_simulator(SimulatedDevice::with_topology(&topology).unwrap());
+
+        assert!(manager.get_slave_keymap_entry(32, 0, 0, 0).await.is_ok());
+        assert!(manager.get_slave_keymap_entry(32, 0, 0, 1).await.is_ok());
+        assert!(manager.get_slave_keymap_entry(32, 0, 0, 2).await.is_err());
+        assert_eq!(manager.get_i2c_devices().await.unwrap()[0].status, 0);
+
+        assert!(transport.with_device(|d| d.set_slave_online(32, true)));
+        assert!(manager.get_slave_keymap_entry(32, 0, 0, 2).await.is_ok());
+    }
+
+    #[test]
+    fn duplicate_slave_addresses_are_rejected() {
+        let mut topology = two_slave_topology();
+        topology.slaves[1].address = 32;
+        assert!(SimulatedDevice::with_topology(&topology).is_err());
+    }
+
+    /// Flips one bit in the nth report written, after its CRC was computed
+    struct NoisyTransport {
+        inner: SimulatorTransport,
+        writes: std::sync::atomic::AtomicUsize,
+        corrupt_write: usize,
+    }
+
+    impl ConfigTransport for NoisyTransport {
+        fn write_report(&self, report: &Report) -> Result<(), ConfigError> {
+            let n = self.writes.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let mut report = *report;
+            if n == self.corrupt_write {
+                report[CONFIG_PACKET_SIZE - 1] ^= 0x80;
+            }
+            self.inner.write_report(&report)
+        }
+
+        fn read_report(&self, timeout_ms: i32) -> Result<Option<Report>, ConfigError> {
+            self.inner.read_report(timeout_ms)
+        }
+    }
+
+    fn blob(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn large_transfers_round_trip() {
+        let (manager, _sim) = connected_simulator(SimulatedDevice::new());
+        for len in [0, 1, LARGE_WRITE_CHUNK, LARGE_WRITE_CHUNK + 1, 1000] {
+            let data = blob(len);
+            manager.send_large(ConfigCommand::SetKeymapBulk, &data).await.unwrap();
+            assert_eq!(manager.receive_large(ConfigCommand::SetKeymapBulk, &[]).await.unwrap(), data);
+        }
+
+        // Without a stored blob, a large read returns the command's normal response
+        let info = manager.receive_large(ConfigCommand::GetInfo, &[]).await.unwrap();
+        assert_eq!(DeviceInfo::from_payload(&info).unwrap().device_name, "Mock OpenGrader");
+    }
+
+    #[tokio::test]
+    async fn corrupted_chunks_are_retransmitted() {
+        let transport = Arc::new(NoisyTransport {
+            inner: SimulatorTransport::new(SimulatedDevice::new()),
+            writes: Default::default(),
+            corrupt_write: 2,
+        });
+        let manager = HidManager::new().unwrap();
+        manager.connect_transport(transport.clone());
+
+        let data = blob(300);
+        manager.send_large(ConfigCommand::SetKeymapBulk, &data).await.unwrap();
+        // 7 chunks plus one resend of the corrupted third chunk
+        assert_eq!(transport.writes.load(std::sync::atomic::Ordering::Relaxed), 8);
+        assert_eq!(manager.receive_large(ConfigCommand::SetKeymapBulk, &[]).await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn oversized_payloads_never_truncate_silently() {
+        let (manager, _sim) = connected_simulator(SimulatedDevice::new());
+        let err = manager.send_command(ConfigCommand::SetKeymap, &[0; CONFIG_MAX_PAYLOAD_SIZE + 1]).await.unwrap_err();
+        assert!(matches!(err, ConfigError::PayloadTooLarge { .. }));
+
+        let err = manager.send_large(ConfigCommand::SetKeymap, &vec![0; LARGE_TRANSFER_MAX + 1]).await.unwrap_err();
+        assert!(matches!(err, ConfigError::PayloadTooLarge { .. }));
+    }
+
+    #[test]
+    fn unknown_commands_get_invalid_cmd() {
+        let mut device = SimulatedDevice::new();
+        let mut request = ConfigPacket::new(ConfigCommand::GetInfo, 7, &[]).unwrap();
+        request.command = 0x7E;
+
+        let response = device.handle(&request);
+        assert_eq!(response.command, 0x7E);
+        assert_eq!(response.sequence, 7);
+        assert_eq!(response.status, StatusCode::InvalidCmd as u8);
+    }
+
+    /// Next `og:slider-values` payload, skipping any other events
+    async fn next_slider_values(events: &mut broadcast::Receiver<DeviceEvent>) -> (u64, Vec<SliderReading>) {
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(1), events.recv()).await.unwrap().unwrap();
+            if let DeviceEvent::SliderValues { timestamp_ms, values } = event {
+                return (timestamp_ms, values);
+            }
+        }
+    }
+
+    async fn assert_stream_stopped(events: &mut broadcast::Receiver<DeviceEvent>) {
+        // Let anything already in flight land, then expect silence
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        while events.try_recv().is_ok() {}
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(matches!(events.try_recv(), Err(broadcast::error::TryRecvError::Empty)));
+    }
+
+    #[tokio::test]
+    async fn sliders_stream_from_the_device() {
+        let (manager, sim) = connected_simulator(SimulatedDevice::new());
+        manager.handshake().await.unwrap();
+        let mut events = manager.subscribe_events();
+
+        assert!(manager.subscribe_sliders(&[0, 1], 5).await.is_err());
+        assert_eq!(manager.subscribe_sliders(&[0, 1], 10).await.unwrap(), SliderStreamMode::Device);
+
+        let (first, values) = next_slider_values(&mut events).await;
+        assert_eq!(values, vec![SliderReading { slider_id: 0, value: 0 }, SliderReading { slider_id: 1, value: 64 }]);
+
+        sim.with_device(|d| d.set_slider_value(0, 100));
+        let (later, values) = loop {
+            let (timestamp, values) = next_slider_values(&mut events).await;
+            if values[0].value == 100 {
+                break (timestamp, values);
+            }
+        };
+        assert_eq!(values[1].value, 64);
+        assert!(later >= first);
+
+        manager.unsubscribe_sliders().await.unwrap();
+        assert_stream_stopped(&mut events).await;
+    }
+
+    #[tokio::test]
+    async fn sliders_are_polled_on_firmware_without_streaming() {
+        let (manager, sim) = connected_simulator(SimulatedDevice::new());
+        sim.with_device(|d| d.remove_command(ConfigCommand::SubscribeSliders));
+        manager.handshake().await.unwrap();
+        let mut events = manager.subscribe_events();
+
+        assert_eq!(manager.subscribe_sliders(&[1], 10).await.unwrap(), SliderStreamMode::Polling);
+        let (first, values) = next_slider_values(&mut events).await;
+        assert_eq!(values, vec![SliderReading { slider_id: 1, value: 64 }]);
+
+        sim.with_device(|d| d.set_slider_value(1, 3));
+        let (later, _) = loop {
+            let (timestamp, values) = next_slider_values(&mut events).await;
+            if values[0].value == 3 {
+                break (timestamp, values);
+            }
+        };
+        assert!(later >= first);
+
+        manager.unsubscribe_sliders().await.unwrap();
+        assert_stream_stopped(&mut events).await;
+    }
+}
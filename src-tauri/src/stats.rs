@@ -0,0 +1,288 @@
+use crate::protocol::ConfigCommand;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Upper bounds of the latency histogram buckets; one more bucket catches the rest
+pub const LATENCY_BUCKETS_MS: [u32; 10] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000];
+
+/// Share of requests timing out (after all retries) at which the link counts as poor
+const POOR_TIMEOUT_RATE: f64 = 0.05;
+/// Share of requests needing a resend at which the link counts as poor
+const POOR_RETRY_RATE: f64 = 0.25;
+/// Share of requests needing a resend at which the link counts as degraded
+const DEGRADED_RETRY_RATE: f64 = 0.05;
+
+#[derive(Debug, Clone, Default)]
+struct Counters {
+    requests: u64,
+    retries: u64,
+    busy_retries: u64,
+    timeouts: u64,
+    unexpected_sequence: u64,
+    latency_counts: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    latency_total_us: u64,
+    latency_max_us: u64,
+}
+
+impl Counters {
+    fn add(&mut self, other: &Counters) {
+        self.requests += other.requests;
+        self.retries += other.retries;
+        self.busy_retries += other.busy_retries;
+        self.timeouts += other.timeouts;
+        self.unexpected_sequence += other.unexpected_sequence;
+        for (total, count) in self.latency_counts.iter_mut().zip(other.latency_counts) {
+            *total += count;
+        }
+        self.latency_total_us += other.latency_total_us;
+        self.latency_max_us = self.latency_max_us.max(other.latency_max_us);
+    }
+
+    fn responses(&self) -> u64 {
+        self.latency_counts.iter().sum()
+    }
+
+    fn stats(&self, command: Option<ConfigCommand>) -> CommandStats {
+        let responses = self.responses();
+        CommandStats {
+            command,
+            requests: self.requests,
+            retries: self.retries,
+            busy_retries: self.busy_retries,
+            timeouts: self.timeouts,
+            unexpected_sequence: self.unexpected_sequence,
+            latency: LatencyStats {
+                counts: self.latency_counts.to_vec(),
+                mean_ms: match responses {
+                    0 => 0.0,
+                    n => self.latency_total_us as f64 / n as f64 / 1000.0,
+                },
+                max_ms: self.latency_max_us as f64 / 1000.0,
+            },
+        }
+    }
+}
+
+/// Round-trip times, counted into `LATENCY_BUCKETS_MS`
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyStats {
+    /// One count per bucket, plus a last one for everything slower
+    pub counts: Vec<u64>,
+    pub mean_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Counters for one command, or for all of them
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandStats {
+    /// None on the totals
+    pub command: Option<ConfigCommand>,
+    pub requests: u64,
+    /// Resends after a response didn't arrive in time
+    pub retries: u64,
+    /// Resends after the device answered `Busy`
+    pub busy_retries: u64,
+    /// Requests that failed because no response arrived after every retry
+    pub timeouts: u64,
+    /// Responses nobody was waiting for: late answers to timed-out requests or corrupted sequence bytes
+    pub unexpected_sequence: u64,
+    /// Measured from the first send, so retries show up as latency
+    pub latency: LatencyStats,
+}
+
+/// What `get_link_stats` returns
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkStatsSnapshot {
+    /// Unix time in milliseconds when counting started (connect or reset)
+    pub since_ms: u64,
+    pub latency_buckets_ms: Vec<u32>,
+    pub totals: CommandStats,
+    /// Commands sent at least once, in wire order
+    pub commands: Vec<CommandStats>,
+}
+
+/// Overall verdict on the link over the last interval
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkQuality {
+    /// Nothing was sent
+    Idle,
+    Good,
+    /// Occasional resends or stray packets
+    Degraded,
+    /// Requests are failing or most need resending
+    Poor,
+}
+
+/// Payload of the periodic `og:link-health` event, covering one interval
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkHealth {
+    pub device_id: Option<String>,
+    pub quality: LinkQuality,
+    pub interval_ms: u64,
+    pub requests: u64,
+    pub retries: u64,
+    pub timeouts: u64,
+    pub unexpected_sequence: u64,
+    pub mean_latency_ms: f64,
+    pub max_latency_ms: f64,
+}
+
+/// Per-command link counters, kept by the dispatcher for one connection
+pub struct LinkStats {
+    since: Mutex<SystemTime>,
+    commands: Mutex<HashMap<ConfigCommand, Counters>>,
+    /// Totals at the end of the last health interval
+    last_health: Mutex<Counters>,
+}
+
+impl Default for LinkStats {
+    fn default() -> Self {
+        LinkStats {
+            since: Mutex::new(SystemTime::now()),
+            commands: Mutex::new(HashMap::new()),
+            last_health: Mutex::new(Counters::default()),
+        }
+    }
+}
+
+impl LinkStats {
+    pub fn reset(&self) {
+        *self.since.lock().unwrap() = SystemTime::now();
+        self.commands.lock().unwrap().clear();
+        *self.last_health.lock().unwrap() = Counters::default();
+    }
+
+    fn update(&self, command: ConfigCommand, change: impl FnOnce(&mut Counters)) {
+        change(self.commands.lock().unwrap().entry(command).or_default());
+    }
+
+    pub fn request(&self, command: ConfigCommand) {
+        self.update(command, |c| c.requests += 1);
+    }
+
+    pub fn retry(&self, command: ConfigCommand) {
+        self.update(command, |c| c.retries += 1);
+    }
+
+    pub fn busy_retry(&self, command: ConfigCommand) {
+        self.update(command, |c| c.busy_retries += 1);
+    }
+
+    pub fn timeout(&self, command: ConfigCommand) {
+        self.update(command, |c| c.timeouts += 1);
+    }
+
+    pub fn unexpected_sequence(&self, command: ConfigCommand) {
+        self.update(command, |c| c.unexpected_sequence += 1);
+    }
+
+    pub fn response(&self, command: ConfigCommand, latency: Duration) {
+        let ms = latency.as_secs_f64() * 1000.0;
+        let bucket = LATENCY_BUCKETS_MS.iter().position(|&limit| ms <= limit as f64).unwrap_or(LATENCY_BUCKETS_MS.len());
+        let us = latency.as_micros() as u64;
+        self.update(command, |c| {
+            c.latency_counts[bucket] += 1;
+            c.latency_total_us += us;
+            c.latency_max_us = c.latency_max_us.max(us);
+        });
+    }
+
+    fn totals(&self) -> Counters {
+        let mut totals = Counters::default();
+        for counters in self.commands.lock().unwrap().values() {
+            totals.add(counters);
+        }
+        totals
+    }
+
+    pub fn snapshot(&self) -> LinkStatsSnapshot {
+        let mut commands: Vec<CommandStats> =
+            self.commands.lock().unwrap().iter().map(|(command, c)| c.stats(Some(*command))).collect();
+        commands.sort_by_key(|s| s.command.map(|c| c as u8));
+
+        LinkStatsSnapshot {
+            since_ms: self.since.lock().unwrap().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0),
+            latency_buckets_ms: LATENCY_BUCKETS_MS.to_vec(),
+            totals: self.totals().stats(None),
+            commands,
+        }
+    }
+
+    /// How the link did since the previous call. The maximum latency is over the
+    /// whole connection, the counters only cover the interval.
+    pub fn health(&self, device_id: Option<String>, interval: Duration) -> LinkHealth {
+        let now = self.totals();
+        let mut last = self.last_health.lock().unwrap();
+        let requests = now.requests - last.requests;
+        let retries = now.retries - last.retries;
+        let timeouts = now.timeouts - last.timeouts;
+        let unexpected_sequence = now.unexpected_sequence - last.unexpected_sequence;
+        let responses = now.responses() - last.responses();
+        let mean_latency_ms = match responses {
+            0 => 0.0,
+            n => (now.latency_total_us - last.latency_total_us) as f64 / n as f64 / 1000.0,
+        };
+        *last = now.clone();
+
+        let quality = if requests == 0 {
+            LinkQuality::Idle
+        } else {
+            let timeout_rate = timeouts as f64 / requests as f64;
+            let retry_rate = retries as f64 / requests as f64;
+            if timeout_rate >= POOR_TIMEOUT_RATE || retry_rate >= POOR_RETRY_RATE {
+                LinkQuality::Poor
+            } else if timeouts > 0 || retry_rate >= DEGRADED_RETRY_RATE || unexpected_sequence > 0 {
+                LinkQuality::Degraded
+            } else {
+                LinkQuality::Good
+            }
+        };
+
+        LinkHealth {
+            device_id,
+            quality,
+            interval_ms: interval.as_millis() as u64,
+            requests,
+            retries,
+            timeouts,
+            unexpected_sequence,
+            mean_latency_ms,
+            max_latency_ms: now.latency_max_us as f64 / 1000.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn health_covers_only_the_last_interval() {
+        let stats = LinkStats::default();
+        for _ in 0..10 {
+            stats.request(ConfigCommand::GetKeymap);
+            stats.response(ConfigCommand::GetKeymap, Duration::from_millis(3));
+        }
+        stats.response(ConfigCommand::GetKeymap, Duration::from_secs(5));
+        assert_eq!(stats.health(None, Duration::from_secs(5)).quality, LinkQuality::Good);
+        assert_eq!(stats.health(None, Duration::from_secs(5)).quality, LinkQuality::Idle);
+
+        stats.request(ConfigCommand::GetSlaveKeymap);
+        stats.retry(ConfigCommand::GetSlaveKeymap);
+        stats.timeout(ConfigCommand::GetSlaveKeymap);
+        let health = stats.health(None, Duration::from_secs(5));
+        assert_eq!((health.quality, health.requests, health.timeouts), (LinkQuality::Poor, 1, 1));
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.totals.requests, 11);
+        let keymap = &snapshot.commands[0];
+        assert_eq!(keymap.command, Some(ConfigCommand::GetKeymap));
+        // 3 ms lands in the <= 5 ms bucket, 5 s in the overflow bucket
+        assert_eq!(keymap.latency.counts[2], 10);
+        assert_eq!(keymap.latency.counts[LATENCY_BUCKETS_MS.len()], 1);
+        assert_eq!(keymap.latency.max_ms, 5000.0);
+    }
+}
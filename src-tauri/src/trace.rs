@@ -0,0 +1,200 @@
+use crate::error::ConfigError;
+use crate::protocol::CONFIG_PACKET_SIZE;
+use crate::replay::ReplayExchange;
+use crate::transport::Report;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::warn;
+
+/// Which way a report travelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TraceDirection {
+    /// Host -> device
+    Tx,
+    /// Device -> host
+    Rx,
+}
+
+/// One line of a trace file (JSON Lines, one report per line)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceRecord {
+    /// Microseconds since the recording started
+    pub t_us: u64,
+    pub dir: TraceDirection,
+    /// The 64 report bytes as lowercase hex
+    pub data: String,
+}
+
+impl TraceRecord {
+    pub fn report(&self) -> Result<Report, ConfigError> {
+        let bytes = (0..self.data.len())
+            .step_by(2)
+            .map(|i| {
+                self.data
+                    .get(i..i + 2)
+                    .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                    .ok_or_else(|| ConfigError::invalid_input(format!("Invalid hex in trace record at t={}us", self.t_us)))
+            })
+            .collect::<Result<Vec<u8>, ConfigError>>()?;
+
+        bytes.try_into().map_err(|bytes: Vec<u8>| {
+            ConfigError::invalid_input(format!(
+                "Trace record at t={}us has {} bytes, expected {}",
+                self.t_us,
+                bytes.len(),
+                CONFIG_PACKET_SIZE
+            ))
+        })
+    }
+}
+
+/// Appends every report exchanged with the device to a trace file
+pub struct TraceRecorder {
+    writer: Mutex<BufWriter<File>>,
+    started: Instant,
+}
+
+impl TraceRecorder {
+    pub fn create(path: &Path) -> Result<Self, ConfigError> {
+        let file = File::create(path)
+            .map_err(|e| ConfigError::io(format!("Failed to create trace file {}: {}", path.display(), e)))?;
+
+        Ok(TraceRecorder {
+            writer: Mutex::new(BufWriter::new(file)),
+            started: Instant::now(),
+        })
+    }
+
+    pub fn record(&self, dir: TraceDirection, report: &Report) {
+        let record = TraceRecord {
+            t_us: self.started.elapsed().as_micros() as u64,
+            dir,
+            data: report.iter().map(|b| format!("{:02x}", b)).collect(),
+        };
+
+        // Flush per line so a crash or unplug still leaves a usable trace behind
+        let mut writer = self.writer.lock().unwrap();
+        let result = serde_json::to_writer(&mut *writer, &record)
+            .map_err(|e| e.to_string())
+            .and_then(|_| writer.write_all(b"\n").map_err(|e| e.to_string()))
+            .and_then(|_| writer.flush().map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            warn!("Failed to write trace record: {}", e);
+        }
+    }
+}
+
+/// Read all records from a trace file
+pub fn load_trace(path: &Path) -> Result<Vec<TraceRecord>, ConfigError> {
+    let file = File::open(path)
+        .map_err(|e| ConfigError::io(format!("Failed to open trace file {}: {}", path.display(), e)))?;
+
+    let mut records = Vec::new();
+    for (line_no, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| ConfigError::io(format!("Failed to read trace file {}: {}", path.display(), e)))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line)
+            .map_err(|e| ConfigError::invalid_input(format!("Invalid trace record on line {}: {}", line_no + 1, e)))?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Group a trace into request/response exchanges for `ReplayTransport`.
+/// Reports received before the first request are dropped.
+pub fn exchanges_from_trace(records: &[TraceRecord]) -> Result<Vec<ReplayExchange>, ConfigError> {
+    let mut exchanges: Vec<ReplayExchange> = Vec::new();
+    for record in records {
+        let report = record.report()?;
+        match record.dir {
+            TraceDirection::Tx => exchanges.push(ReplayExchange {
+                request: report,
+                responses: Vec::new(),
+            }),
+            TraceDirection::Rx => {
+                if let Some(exchange) = exchanges.last_mut() {
+                    exchange.responses.push(report);
+                }
+            }
+        }
+    }
+    Ok(exchanges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hid_manager::HidManager;
+    use crate::protocol::KeymapEntry;
+    use crate::simulator::{connected_simulator, SimulatedDevice};
+
+    fn temp_trace_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("og-trace-{}-{}.jsonl", name, std::process::id()))
+    }
+
+    async fn session(manager: &HidManager) -> (String, u16, u16) {
+        let info = manager.get_device_info().await.unwrap();
+        manager
+            .set_keymap_entry(&KeymapEntry { layer: 1, row: 2, col: 3, keycode: 0x2C })
+            .await
+            .unwrap();
+        let written = manager.get_keymap_entry(1, 2, 3).await.unwrap().keycode;
+        let untouched = manager.get_keymap_entry(0, 0, 0).await.unwrap().keycode;
+        (info.device_name, written, untouched)
+    }
+
+    #[tokio::test]
+    async fn recorded_session_replays_deterministically() {
+        let path = temp_trace_path("replay");
+
+        let (recording, _sim) = connected_simulator(SimulatedDevice::new());
+        recording.start_recording(&path).unwrap();
+        let live = session(&recording).await;
+        assert!(recording.stop_recording());
+
+        let records = load_trace(&path).unwrap();
+        assert_eq!(records.len(), 8);
+        assert_eq!(records[0].dir, TraceDirection::Tx);
+        assert_eq!(records[1].dir, TraceDirection::Rx);
+        assert!(records.windows(2).all(|w| w[0].t_us <= w[1].t_us));
+
+        let replaying = HidManager::new().unwrap();
+        replaying.connect_replay(&path).unwrap();
+        assert_eq!(session(&replaying).await, live);
+
+        // The trace is exhausted: anything beyond the captured session fails
+        assert!(replaying.get_keymap_entry(0, 0, 0).await.is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replay_rejects_diverging_requests() {
+        let path = temp_trace_path("diverge");
+
+        let (recording, _sim) = connected_simulator(SimulatedDevice::new());
+        recording.start_recording(&path).unwrap();
+        recording.get_keymap_entry(0, 0, 0).await.unwrap();
+        recording.stop_recording();
+
+        let replaying = HidManager::new().unwrap();
+        replaying.connect_replay(&path).unwrap();
+        assert!(replaying.get_keymap_entry(0, 0, 1).await.is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn malformed_records_are_reported() {
+        let short = TraceRecord { t_us: 5, dir: TraceDirection::Rx, data: "4f47".to_string() };
+        assert!(short.report().is_err());
+
+        let bad_hex = TraceRecord { t_us: 6, dir: TraceDirection::Rx, data: "zz".repeat(CONFIG_PACKET_SIZE) };
+        assert!(bad_hex.report().is_err());
+    }
+}
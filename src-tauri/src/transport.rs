@@ -0,0 +1,96 @@
+use crate::error::ConfigError;
+use crate::protocol::CONFIG_PACKET_SIZE;
+use hidapi::HidDevice;
+use std::sync::Mutex;
+use tracing::warn;
+
+/// A single raw configuration report as exchanged with the device
+pub type Report = [u8; CONFIG_PACKET_SIZE];
+
+/// Byte-level link to something that speaks the OG config protocol.
+///
+/// `HidManager` only ever talks to the device through this trait, so a real
+/// HID interface, the in-process simulator and a recorded trace are all
+/// interchangeable.
+pub trait ConfigTransport: Send + Sync {
+    /// Write one 64-byte report to the device
+    fn write_report(&self, report: &Report) -> Result<(), ConfigError>;
+
+    /// Read one 64-byte report, waiting at most `timeout_ms`.
+    /// Returns `Ok(None)` if nothing (or only a partial report) arrived in time.
+    fn read_report(&self, timeout_ms: i32) -> Result<Option<Report>, ConfigError>;
+}
+
+/// Transport backed by a hidapi device handle
+pub struct HidTransport {
+    device: Mutex<HidDevice>,
+}
+
+impl HidTransport {
+    pub fn new(device: HidDevice) -> Result<Self, ConfigError> {
+        // Set non-blocking mode
+        device.set_blocking_mode(false)
+            .map_err(|e| ConfigError::io(format!("Failed to set non-blocking mode: {}", e)))?;
+
+        Ok(HidTransport {
+            device: Mutex::new(device),
+        })
+    }
+}
+
+impl ConfigTransport for HidTransport {
+    fn write_report(&self, report: &Report) -> Result<(), ConfigError> {
+        let device = self.device.lock().unwrap();
+
+        // On Windows, HIDAPI expects the first byte to be the report ID (0x00 if none)
+        // and will NOT send that byte to the device. If we don't provide it, the first
+        // byte of our payload becomes the report ID and is dropped, shifting our packet.
+        #[cfg(target_os = "windows")]
+        {
+            let mut write_buf = [0u8; CONFIG_PACKET_SIZE + 1];
+            write_buf[0] = 0x00; // report ID 0
+            write_buf[1..].copy_from_slice(report);
+            device
+                .write(&write_buf)
+                .map_err(|e| ConfigError::io(format!("Failed to write packet: {}", e)))?;
+        }
+
+        #[cfg(not(target_os = "windows"))]
+        {
+            device
+                .write(report)
+                .map_err(|e| ConfigError::io(format!("Failed to write packet: {}", e)))?;
+        }
+
+        Ok(())
+    }
+
+    fn read_report(&self, timeout_ms: i32) -> Result<Option<Report>, ConfigError> {
+        // On Windows, reads include the report ID byte (total 65 bytes)
+        let mut buffer = [0u8; CONFIG_PACKET_SIZE + 1];
+        let read_result = {
+            let device = self.device.lock().unwrap();
+            device.read_timeout(&mut buffer, timeout_ms)
+        };
+
+        let mut report = [0u8; CONFIG_PACKET_SIZE];
+        match read_result {
+            // Windows: 65 bytes -> drop report ID; 64 bytes -> use as-is
+            Ok(bytes_read) if cfg!(target_os = "windows") && bytes_read == CONFIG_PACKET_SIZE + 1 => {
+                report.copy_from_slice(&buffer[1..]);
+                Ok(Some(report))
+            }
+            Ok(bytes_read) if bytes_read == CONFIG_PACKET_SIZE => {
+                report.copy_from_slice(&buffer[..CONFIG_PACKET_SIZE]);
+                Ok(Some(report))
+            }
+            Ok(0) => Ok(None),
+            Ok(bytes_read) => {
+                warn!("Incomplete read ({} bytes), ignoring", bytes_read);
+                Ok(None)
+            }
+            Err(hidapi::HidError::HidApiError { message }) if message.contains("timeout") => Ok(None),
+            Err(e) => Err(ConfigError::io(format!("Failed to read response: {}", e))),
+        }
+    }
+}